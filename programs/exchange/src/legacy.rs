@@ -0,0 +1,461 @@
+// Account layouts before ACCOUNT_VERSION 1 stored debt, shares and points as u64
+use crate::math::INTEREST_OFFSET;
+use crate::*;
+use anchor_lang::__private::bytemuck::{self, Pod, Zeroable};
+use anchor_lang::Discriminator;
+use std::cell::Ref;
+
+pub const LEGACY_STATE_SEED: &[u8] = b"statev1";
+
+#[zero_copy]
+#[derive(PartialEq, Default, Debug)]
+pub struct StakingRoundV0 {
+    pub start: u64,      // 8
+    pub amount: u64,     // 8
+    pub all_points: u64, // 8
+}
+#[zero_copy]
+#[derive(PartialEq, Default, Debug)]
+pub struct StakingV0 {
+    pub fund_account: Pubkey,           //32
+    pub round_length: u32,              //4
+    pub amount_per_round: u64,          //8
+    pub finished_round: StakingRoundV0, //24
+    pub current_round: StakingRoundV0,  //24
+    pub next_round: StakingRoundV0,     //24
+}
+#[zero_copy]
+#[derive(PartialEq, Default, Debug)]
+pub struct UserStakingV0 {
+    pub amount_to_claim: u64,       //8
+    pub finished_round_points: u64, //8
+    pub current_round_points: u64,  //8
+    pub next_round_points: u64,     //8
+    pub last_update: u64,           //8
+}
+#[zero_copy]
+#[derive(PartialEq, Default, Debug)]
+pub struct StateV0 {
+    pub admin: Pubkey,             //32
+    pub halted: bool,              //1
+    pub nonce: u8,                 //1
+    pub debt_shares: u64,          //8
+    pub assets_list: Pubkey,       //32
+    pub health_factor: u8,         //1
+    pub max_delay: u32,            //4
+    pub fee: u32,                  //4
+    pub liquidation_rate: u8,      //1
+    pub penalty_to_liquidator: u8, //1
+    pub penalty_to_exchange: u8,   //1
+    pub liquidation_buffer: u32,   //4
+    pub account_version: u8,       //1
+    pub staking: StakingV0,        //116
+    pub bump: u8,                  //1
+}
+#[zero_copy]
+#[derive(PartialEq, Default, Debug)]
+pub struct AssetV0 {
+    pub feed_address: Pubkey, // 32
+    pub price: u64,           // 8
+    pub last_update: u64,     // 8
+    pub confidence: u32,      // 4
+}
+#[zero_copy]
+#[derive(PartialEq, Default, Debug)]
+pub struct SyntheticV0 {
+    pub asset_index: u8,       // 1
+    pub asset_address: Pubkey, // 32
+    pub supply: u64,           // 8
+    pub decimals: u8,          // 1
+    pub max_supply: u64,       // 8
+    pub settlement_slot: u64,  // 8
+}
+#[zero_copy]
+#[derive(Default)]
+pub struct AssetsListV0 {
+    pub initialized: bool,
+    pub head_assets: u8,
+    pub head_collaterals: u8,
+    pub head_synthetics: u8,
+    pub assets: [AssetV0; 30],
+    pub collaterals: [Collateral; 30], // Collateral layout is unchanged
+    pub synthetics: [SyntheticV0; 30],
+}
+#[zero_copy]
+#[derive(PartialEq, Default, Debug)]
+pub struct ExchangeAccountV0 {
+    pub owner: Pubkey,
+    pub version: u8,
+    pub debt_shares: u64,
+    pub liquidation_deadline: u64,
+    pub user_staking_data: UserStakingV0,
+    pub head: u8,
+    pub bump: u8,
+    pub collaterals: [CollateralEntry; 10],
+    pub nonce: u8, // Trailing nonce of associated account
+}
+// Packed plain data like accounts of previous version
+unsafe impl Pod for StateV0 {}
+unsafe impl Zeroable for StateV0 {}
+unsafe impl Pod for ExchangeAccountV0 {}
+unsafe impl Zeroable for ExchangeAccountV0 {}
+unsafe impl Pod for AssetsListV0 {}
+unsafe impl Zeroable for AssetsListV0 {}
+
+// Legacy account data, only accounts of this program with exact old size are accepted
+fn load_legacy<'a, T: Pod>(
+    account: &'a AccountInfo,
+    program_id: &Pubkey,
+    discriminator: [u8; 8],
+) -> Result<Ref<'a, T>> {
+    require!(account.owner.eq(program_id), InvalidLegacyAccount);
+    let data = account.try_borrow_data()?;
+    require!(
+        data.len() == 8 + std::mem::size_of::<T>() && data[..8] == discriminator,
+        InvalidLegacyAccount
+    );
+    // Packed layouts have alignment of 1 so cast cannot fail
+    Ok(Ref::map(data, |data| bytemuck::from_bytes(&data[8..])))
+}
+pub fn load_legacy_state<'a>(
+    account: &'a AccountInfo,
+    program_id: &Pubkey,
+) -> Result<Ref<'a, StateV0>> {
+    load_legacy(account, program_id, State::discriminator())
+}
+pub fn load_legacy_exchange_account<'a>(
+    account: &'a AccountInfo,
+    program_id: &Pubkey,
+) -> Result<Ref<'a, ExchangeAccountV0>> {
+    load_legacy(account, program_id, ExchangeAccount::discriminator())
+}
+// Referenced in place, copy of whole list does not fit on stack
+pub fn load_legacy_assets_list<'a>(
+    account: &'a AccountInfo,
+    program_id: &Pubkey,
+) -> Result<Ref<'a, AssetsListV0>> {
+    load_legacy(account, program_id, AssetsList::discriminator())
+}
+// New state can be created only if there is no state of previous version to migrate
+pub fn check_legacy_state_absent(account: &AccountInfo, program_id: &Pubkey) -> Result<()> {
+    let (address, _) = Pubkey::find_program_address(&[LEGACY_STATE_SEED], program_id);
+    require!(account.key.eq(&address), InvalidLegacyAccount);
+    // Closed or never created account is not owned by program
+    require!(!account.owner.eq(program_id), LegacyState);
+    Ok(())
+}
+// Moves rent to receiver and clears discriminator so account cannot be loaded again
+pub fn close_legacy_account(account: &AccountInfo, receiver: &AccountInfo) -> Result<()> {
+    let lamports = account.lamports();
+    **receiver.try_borrow_mut_lamports()? += lamports;
+    **account.try_borrow_mut_lamports()? = 0;
+    account.try_borrow_mut_data()?[..8].copy_from_slice(&[0u8; 8]);
+    Ok(())
+}
+
+fn upgrade_staking_round(legacy: &StakingRoundV0) -> StakingRound {
+    StakingRound {
+        start: legacy.start,
+        amount: legacy.amount,
+        all_points: legacy.all_points.into(),
+    }
+}
+// Fields added since baseline get the same values as in init
+pub fn upgrade_state(legacy: &StateV0, state: &mut State, slot: u64) {
+    state.admin = legacy.admin;
+    state.halted = legacy.halted;
+    state.nonce = legacy.nonce;
+    state.debt_shares = legacy.debt_shares.into();
+    state.assets_list = legacy.assets_list;
+    state.health_factor = legacy.health_factor;
+    state.max_delay = legacy.max_delay;
+    state.fee = legacy.fee;
+    state.liquidation_rate = legacy.liquidation_rate;
+    state.penalty_to_liquidator = legacy.penalty_to_liquidator;
+    state.penalty_to_exchange = legacy.penalty_to_exchange;
+    state.liquidation_buffer = legacy.liquidation_buffer;
+    state.account_version = ACCOUNT_VERSION;
+    state.staking = Staking {
+        fund_account: legacy.staking.fund_account,
+        round_length: legacy.staking.round_length,
+        amount_per_round: legacy.staking.amount_per_round,
+        finished_round: upgrade_staking_round(&legacy.staking.finished_round),
+        current_round: upgrade_staking_round(&legacy.staking.current_round),
+        next_round: upgrade_staking_round(&legacy.staking.next_round),
+    };
+    state.oracle_program = Pubkey::default();
+    state.crank = Crank::default();
+    state.debt = 0;
+    state.debt_ceiling = u64::MAX;
+    state.min_account_debt = 0;
+    state.max_account_debt = u64::MAX;
+    state.stability_fee = 0;
+    state.stability_fee_index = 10u128.pow(INTEREST_OFFSET.into());
+    state.stability_fee_slot = slot;
+    state.accumulated_stability_fee = 0;
+    state.stability_fee_vault = Pubkey::default();
+}
+// Fields added since baseline get the same values as in add_new_asset
+pub fn upgrade_asset(legacy: &AssetV0) -> Asset {
+    Asset {
+        feed_address: legacy.feed_address,
+        price: legacy.price,
+        twap: legacy.price,
+        price_mode: PRICE_MODE_SPOT,
+        last_update: legacy.last_update,
+        confidence: 0,
+        max_confidence: u32::MAX,
+        frozen: false,
+        secondary_feed_address: Pubkey::default(),
+        max_divergence: 0,
+        price_source: PRICE_SOURCE_PRIMARY,
+        max_price_change: u32::MAX,
+        breaker_cooldown: 0,
+        breaker_slot: 0,
+        in_breaker: false,
+        last_crank: 0,
+        max_delay: 0,
+        confidence_multiplier: 0,
+        asset_kind: ASSET_KIND_FEED,
+        input_feeds: [Pubkey::default(); DERIVED_MAX_INPUTS],
+        input_weights: [0; DERIVED_MAX_INPUTS],
+        entry_price: 0,
+        lower_bound: 0,
+        upper_bound: 0,
+        price_history: Pubkey::default(),
+    }
+}
+pub fn upgrade_assets_list(legacy: &AssetsListV0, assets_list: &mut AssetsList) {
+    assets_list.initialized = legacy.initialized;
+    assets_list.head_assets = legacy.head_assets;
+    assets_list.head_collaterals = legacy.head_collaterals;
+    assets_list.head_synthetics = legacy.head_synthetics;
+    for (asset, legacy_asset) in assets_list.assets.iter_mut().zip(legacy.assets.iter()) {
+        *asset = upgrade_asset(legacy_asset);
+    }
+    for (collateral, legacy_collateral) in assets_list
+        .collaterals
+        .iter_mut()
+        .zip(legacy.collaterals.iter())
+    {
+        *collateral = *legacy_collateral;
+    }
+    for (synthetic, legacy_synthetic) in assets_list
+        .synthetics
+        .iter_mut()
+        .zip(legacy.synthetics.iter())
+    {
+        *synthetic = Synthetic {
+            asset_index: legacy_synthetic.asset_index,
+            asset_address: legacy_synthetic.asset_address,
+            supply: legacy_synthetic.supply,
+            decimals: legacy_synthetic.decimals,
+            max_supply: legacy_synthetic.max_supply,
+            settlement_slot: legacy_synthetic.settlement_slot,
+            settlement_price: 0,
+        };
+    }
+}
+pub fn upgrade_exchange_account(
+    legacy: &ExchangeAccountV0,
+    exchange_account: &mut ExchangeAccount,
+) {
+    exchange_account.owner = legacy.owner;
+    exchange_account.version = ACCOUNT_VERSION;
+    exchange_account.debt_shares = legacy.debt_shares.into();
+    exchange_account.liquidation_deadline = legacy.liquidation_deadline;
+    exchange_account.user_staking_data = UserStaking {
+        amount_to_claim: legacy.user_staking_data.amount_to_claim,
+        finished_round_points: legacy.user_staking_data.finished_round_points.into(),
+        current_round_points: legacy.user_staking_data.current_round_points.into(),
+        next_round_points: legacy.user_staking_data.next_round_points.into(),
+        last_update: legacy.user_staking_data.last_update,
+    };
+    exchange_account.head = legacy.head;
+    exchange_account.collaterals = legacy.collaterals;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_layout_sizes() {
+        // Sizes of accounts created by deployed program, without discriminator
+        assert_eq!(std::mem::size_of::<StateV0>(), 208);
+        assert_eq!(std::mem::size_of::<ExchangeAccountV0>(), 502);
+        assert_eq!(std::mem::size_of::<AssetsListV0>(), 6514);
+    }
+    #[test]
+    fn test_check_legacy_state_absent() {
+        let program_id = Pubkey::new_unique();
+        let (address, _) = Pubkey::find_program_address(&[LEGACY_STATE_SEED], &program_id);
+        let system_program = Pubkey::default();
+        let mut lamports = 0;
+        let mut data = [0u8; 0];
+        let absent = AccountInfo::new(
+            &address,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &system_program,
+            false,
+            0,
+        );
+        assert!(check_legacy_state_absent(&absent, &program_id).is_ok());
+
+        let mut lamports = 1;
+        let mut data = [0u8; 8 + 208];
+        let legacy = AccountInfo::new(
+            &address,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &program_id,
+            false,
+            0,
+        );
+        assert!(check_legacy_state_absent(&legacy, &program_id).is_err());
+
+        let other = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = [0u8; 0];
+        let wrong_address = AccountInfo::new(
+            &other,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &system_program,
+            false,
+            0,
+        );
+        assert!(check_legacy_state_absent(&wrong_address, &program_id).is_err());
+    }
+    #[test]
+    fn test_upgrade_state() {
+        let mut legacy = StateV0 {
+            admin: Pubkey::new_unique(),
+            debt_shares: u64::MAX,
+            health_factor: 50,
+            bump: 254,
+            ..Default::default()
+        };
+        legacy.staking.current_round.all_points = u64::MAX - 1;
+        legacy.staking.next_round.amount = 100;
+        let mut state = State::default();
+        upgrade_state(&legacy, &mut state, 100);
+        assert_eq!({ state.admin }, { legacy.admin });
+        assert_eq!({ state.debt_shares }, u64::MAX as u128);
+        assert_eq!({ state.health_factor }, 50);
+        assert_eq!({ state.account_version }, ACCOUNT_VERSION);
+        assert_eq!(
+            { state.staking.current_round.all_points },
+            (u64::MAX - 1) as u128
+        );
+        assert_eq!({ state.staking.next_round.amount }, 100);
+        // fields added since deployment are set like in init
+        assert_eq!({ state.crank }, Crank::default());
+        assert_eq!({ state.debt_ceiling }, u64::MAX);
+        assert_eq!({ state.min_account_debt }, 0);
+        assert_eq!({ state.max_account_debt }, u64::MAX);
+        // stability fee starts accruing from migration
+        assert_eq!({ state.stability_fee }, 0);
+        assert_eq!({ state.stability_fee_index }, 10u128.pow(18));
+        assert_eq!({ state.stability_fee_slot }, 100);
+        assert_eq!({ state.stability_fee_vault }, Pubkey::default());
+        // bump of new PDA is set by migration
+        assert_eq!({ state.bump }, 0);
+    }
+    #[test]
+    fn test_upgrade_assets_list() {
+        let mut legacy = AssetsListV0 {
+            initialized: true,
+            head_assets: 2,
+            head_collaterals: 1,
+            head_synthetics: 1,
+            ..Default::default()
+        };
+        legacy.assets[1] = AssetV0 {
+            feed_address: Pubkey::new_unique(),
+            price: 2 * 10u64.pow(6),
+            last_update: 10,
+            confidence: 5,
+        };
+        legacy.collaterals[0] = Collateral {
+            asset_index: 1,
+            collateral_address: Pubkey::new_unique(),
+            reserve_balance: 100,
+            decimals: 6,
+            collateral_ratio: 10,
+            ..Default::default()
+        };
+        legacy.synthetics[0] = SyntheticV0 {
+            asset_index: 0,
+            asset_address: Pubkey::new_unique(),
+            supply: 1000,
+            decimals: 6,
+            max_supply: u64::MAX,
+            settlement_slot: u64::MAX,
+        };
+        let mut assets_list = AssetsList::default();
+        upgrade_assets_list(&legacy, &mut assets_list);
+        assert!(assets_list.initialized);
+        assert_eq!(assets_list.head_assets, 2);
+        assert_eq!(assets_list.head_collaterals, 1);
+        assert_eq!(assets_list.head_synthetics, 1);
+
+        let asset = &assets_list.assets[1];
+        assert_eq!({ asset.feed_address }, { legacy.assets[1].feed_address });
+        assert_eq!({ asset.price }, 2 * 10u64.pow(6));
+        assert_eq!({ asset.twap }, 2 * 10u64.pow(6));
+        assert_eq!({ asset.last_update }, 10);
+        // new limits are disabled like for new asset
+        assert_eq!({ asset.max_confidence }, u32::MAX);
+        assert_eq!({ asset.max_price_change }, u32::MAX);
+        assert_eq!({ asset.asset_kind }, ASSET_KIND_FEED);
+        assert_eq!({ asset.confidence_multiplier }, 0);
+
+        assert_eq!(assets_list.collaterals[0], { legacy.collaterals[0] });
+        let synthetic = &assets_list.synthetics[0];
+        assert_eq!({ synthetic.asset_address }, {
+            legacy.synthetics[0].asset_address
+        });
+        assert_eq!({ synthetic.supply }, 1000);
+        assert_eq!({ synthetic.max_supply }, u64::MAX);
+        assert_eq!({ synthetic.settlement_slot }, u64::MAX);
+        assert_eq!({ synthetic.settlement_price }, 0);
+    }
+    #[test]
+    fn test_upgrade_exchange_account() {
+        let mut legacy = ExchangeAccountV0 {
+            owner: Pubkey::new_unique(),
+            debt_shares: 12345,
+            liquidation_deadline: u64::MAX,
+            head: 1,
+            ..Default::default()
+        };
+        legacy.user_staking_data.next_round_points = 12345;
+        legacy.user_staking_data.amount_to_claim = 10;
+        legacy.collaterals[0] = CollateralEntry {
+            amount: 100,
+            collateral_address: Pubkey::new_unique(),
+            index: 0,
+        };
+        let mut exchange_account = ExchangeAccount::default();
+        upgrade_exchange_account(&legacy, &mut exchange_account);
+        assert_eq!({ exchange_account.owner }, { legacy.owner });
+        assert_eq!({ exchange_account.version }, ACCOUNT_VERSION);
+        assert_eq!({ exchange_account.debt_shares }, 12345);
+        assert_eq!({ exchange_account.liquidation_deadline }, u64::MAX);
+        assert_eq!(
+            { exchange_account.user_staking_data.next_round_points },
+            12345
+        );
+        assert_eq!({ exchange_account.user_staking_data.amount_to_claim }, 10);
+        assert_eq!({ exchange_account.head }, 1);
+        assert_eq!({ exchange_account.collaterals }, { legacy.collaterals });
+    }
+}
@@ -1,21 +1,24 @@
+mod legacy;
 pub mod math;
 mod utils;
 use anchor_lang::prelude::*;
 use anchor_spl::token::{self, Burn, MintTo, TokenAccount, Transfer};
 // use manager::{AssetsList, SetAssetSupply};
+use legacy::*;
 use utils::*;
 const SYNTHETIFY_EXCHANGE_SEED: &str = "Synthetify";
+// Layout version of State and ExchangeAccount, 1 -> u128 debt, shares and points
+const ACCOUNT_VERSION: u8 = 1;
 #[program]
 pub mod exchange {
     use std::convert::TryInto;
 
-    use pyth::pc::Price;
-
     use crate::math::{
-        amount_to_discount, amount_to_shares_by_rounding_down, calculate_burned_shares,
-        calculate_debt, calculate_max_burned_in_xusd, calculate_max_debt_in_usd,
-        calculate_max_withdraw_in_usd, calculate_new_shares_by_rounding_up,
-        calculate_swap_out_amount, calculate_user_debt_in_usd, usd_to_token_amount, PRICE_OFFSET,
+        amount_to_discount, amount_to_shares_by_rounding_down, calculate_burned_in_usd,
+        calculate_burned_shares, calculate_debt, calculate_debt_in_usd,
+        calculate_max_burned_in_token, calculate_max_debt_in_usd, calculate_max_withdraw_in_usd,
+        calculate_new_shares_by_rounding_up, calculate_stored_debt, calculate_swap_out_amount,
+        calculate_user_debt_in_usd, usd_to_token_amount, INTEREST_OFFSET, PRICE_OFFSET,
     };
 
     use super::*;
@@ -24,7 +27,7 @@ pub mod exchange {
         let exchange_account = &mut ctx.accounts.exchange_account.load_init()?;
         exchange_account.owner = *ctx.accounts.admin.key;
         exchange_account.debt_shares = 0;
-        exchange_account.version = 0;
+        exchange_account.version = ACCOUNT_VERSION;
         exchange_account.bump = bump;
         exchange_account.liquidation_deadline = u64::MAX;
         exchange_account.user_staking_data = UserStaking::default();
@@ -35,6 +38,12 @@ pub mod exchange {
         assets_list.initialized = false;
         Ok(())
     }
+    pub fn create_price_history(ctx: Context<CreatePriceHistory>) -> ProgramResult {
+        let price_history = &mut ctx.accounts.price_history.load_init()?;
+        price_history.head = 0;
+        price_history.count = 0;
+        Ok(())
+    }
     // #[access_control(admin(&self, &ctx.accounts.signer))]
     pub fn create_list(
         ctx: Context<InitializeAssetsList>,
@@ -52,7 +61,28 @@ pub mod exchange {
             feed_address: Pubkey::default(), // unused
             last_update: u64::MAX,           // we dont update usd price
             price: 1 * 10u64.pow(PRICE_OFFSET.into()),
+            twap: 10u64.pow(PRICE_OFFSET.into()),
+            price_mode: PRICE_MODE_SPOT,
             confidence: 0,
+            max_confidence: u32::MAX, // no limit for usd asset
+            frozen: false,
+            secondary_feed_address: Pubkey::default(),
+            max_divergence: 0,
+            price_source: PRICE_SOURCE_PRIMARY,
+            max_price_change: u32::MAX,
+            breaker_cooldown: 0,
+            breaker_slot: 0,
+            in_breaker: false,
+            last_crank: 0,
+            max_delay: 0,
+            confidence_multiplier: 0,
+            asset_kind: ASSET_KIND_FEED,
+            input_feeds: [Pubkey::default(); DERIVED_MAX_INPUTS],
+            input_weights: [0; DERIVED_MAX_INPUTS],
+            entry_price: 0,
+            lower_bound: 0,
+            upper_bound: 0,
+            price_history: Pubkey::default(),
         };
         let usd_synthetic = Synthetic {
             decimals: 6,
@@ -60,13 +90,35 @@ pub mod exchange {
             supply: 0,
             max_supply: u64::MAX, // no limit for usd asset
             settlement_slot: u64::MAX,
+            settlement_price: 0,
             asset_index: 0,
         };
         let sny_asset = Asset {
             feed_address: collateral_token_feed,
             last_update: 0,
             price: 0,
+            twap: 0,
+            price_mode: PRICE_MODE_SPOT,
             confidence: 0,
+            max_confidence: u32::MAX,
+            frozen: false,
+            secondary_feed_address: Pubkey::default(),
+            max_divergence: 0,
+            price_source: PRICE_SOURCE_PRIMARY,
+            max_price_change: u32::MAX,
+            breaker_cooldown: 0,
+            breaker_slot: 0,
+            in_breaker: false,
+            last_crank: 0,
+            max_delay: 0,
+            confidence_multiplier: 0,
+            asset_kind: ASSET_KIND_FEED,
+            input_feeds: [Pubkey::default(); DERIVED_MAX_INPUTS],
+            input_weights: [0; DERIVED_MAX_INPUTS],
+            entry_price: 0,
+            lower_bound: 0,
+            upper_bound: 0,
+            price_history: Pubkey::default(),
         };
         let sny_collateral = Collateral {
             asset_index: 1,
@@ -85,44 +137,99 @@ pub mod exchange {
         assets_list.initialized = true;
         Ok(())
     }
+    #[access_control(assets_list(&ctx.accounts.state, &ctx.accounts.assets_list))]
     pub fn set_assets_prices(ctx: Context<SetAssetsPrices>) -> Result<()> {
         msg!("SYNTHETIFY: SET ASSETS PRICES");
+        let state = &mut ctx.accounts.state.load_mut()?;
+        let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
+        let slot = Clock::get()?.slot;
+
+        refresh_assets_prices(
+            state,
+            assets_list,
+            ctx.remaining_accounts,
+            ctx.program_id,
+            slot,
+        )
+    }
+    #[access_control(assets_list(&ctx.accounts.state, &ctx.accounts.assets_list))]
+    pub fn crank_assets_prices(ctx: Context<CrankAssetsPrices>) -> Result<()> {
+        msg!("SYNTHETIFY: CRANK ASSETS PRICES");
+        let slot = Clock::get()?.slot;
+        let state = &mut ctx.accounts.state.load_mut()?;
         let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
-        for oracle_account in ctx.remaining_accounts {
-            let price_feed = Price::load(oracle_account)?;
-            let feed_address = oracle_account.key;
-            let asset = assets_list
-                .assets
-                .iter_mut()
-                .find(|x| x.feed_address == *feed_address);
-            match asset {
-                Some(asset) => {
-                    let offset = (PRICE_OFFSET as i32).checked_add(price_feed.expo).unwrap();
-                    if offset >= 0 {
-                        let scaled_price = price_feed
-                            .agg
-                            .price
-                            .checked_mul(10i64.pow(offset.try_into().unwrap()))
-                            .unwrap();
-
-                        asset.price = scaled_price.try_into().unwrap();
-                    } else {
-                        let scaled_price = price_feed
-                            .agg
-                            .price
-                            .checked_div(10i64.pow((-offset).try_into().unwrap()))
-                            .unwrap();
-
-                        asset.price = scaled_price.try_into().unwrap();
-                    }
-
-                    asset.confidence =
-                        math::calculate_confidence(price_feed.agg.conf, price_feed.agg.price);
-                    asset.last_update = Clock::get()?.slot;
-                }
-                None => return Err(ErrorCode::NoAssetFound.into()),
+
+        if !ctx
+            .accounts
+            .crank_fund_account
+            .to_account_info()
+            .key
+            .eq(&state.crank.fund_account)
+        {
+            return Err(ErrorCode::FundAccountError.into());
+        }
+
+        // Remember which assets were outdated before update
+        let mut outdated_updates: [Option<u64>; 30] = [None; 30];
+        let head = assets_list.head_assets as usize;
+        for (index, asset) in assets_list.assets[..head].iter().enumerate() {
+            if is_crank_eligible(asset, state.crank.min_age, slot) {
+                outdated_updates[index] = Some(asset.last_update);
             }
         }
+
+        refresh_assets_prices(
+            state,
+            assets_list,
+            ctx.remaining_accounts,
+            ctx.program_id,
+            slot,
+        )?;
+
+        let reward = reward_crank_updates(
+            &mut state.crank,
+            &mut assets_list.assets[..head],
+            &outdated_updates[..head],
+            slot,
+        );
+        let reward = reward.min(ctx.accounts.crank_fund_account.amount);
+        if reward == 0 {
+            return Ok(());
+        }
+
+        let seeds = &[SYNTHETIFY_EXCHANGE_SEED.as_bytes(), &[state.nonce]];
+        let signer_seeds = &[&seeds[..]];
+
+        // Transfer reward
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.crank_fund_account.to_account_info(),
+            to: ctx.accounts.to.to_account_info(),
+            authority: ctx.accounts.exchange_authority.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer_seeds);
+        token::transfer(cpi_ctx, reward)?;
+        Ok(())
+    }
+    #[access_control(assets_list(&ctx.accounts.state, &ctx.accounts.assets_list))]
+    pub fn refresh_debt(ctx: Context<RefreshDebt>) -> Result<()> {
+        msg!("SYNTHETIFY: REFRESH DEBT");
+        let slot = Clock::get()?.slot;
+        let state = &mut ctx.accounts.state.load_mut()?;
+        let assets_list = &ctx.accounts.assets_list.load()?;
+
+        // Recalculate cached debt, all prices have to be fresh
+        state.debt = calculate_debt(assets_list, slot, state.max_delay)?;
+        Ok(())
+    }
+    #[access_control(assets_list(&ctx.accounts.state, &ctx.accounts.assets_list))]
+    pub fn accrue_stability_fee(ctx: Context<AccrueStabilityFee>) -> Result<()> {
+        msg!("SYNTHETIFY: ACCRUE STABILITY FEE");
+        let slot = Clock::get()?.slot;
+        let state = &mut ctx.accounts.state.load_mut()?;
+        let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
+
+        adjust_stability_fee(state, assets_list, slot)?;
         Ok(())
     }
     pub fn init(
@@ -132,6 +239,7 @@ pub mod exchange {
         staking_round_length: u32,
         amount_per_round: u64,
     ) -> Result<()> {
+        check_legacy_state_absent(&ctx.accounts.legacy_state, ctx.program_id)?;
         let slot = Clock::get()?.slot;
         let mut state = ctx.accounts.state.load_init()?;
 
@@ -139,8 +247,9 @@ pub mod exchange {
         state.admin = *ctx.accounts.admin.key;
         state.halted = false;
         state.nonce = nonce;
-        state.debt_shares = 0u64;
+        state.debt_shares = 0u128;
         state.assets_list = *ctx.accounts.assets_list.key;
+        state.oracle_program = *ctx.accounts.oracle_program.key;
         state.health_factor = 50;
         // once we will not be able to fit all data into one transaction we will
         // use max_delay to allow split updating oracles and exchange operation
@@ -152,7 +261,7 @@ pub mod exchange {
         // TODO decide about length of buffer
         // Maybe just couple of minutes will be enough ?
         state.liquidation_buffer = 172800; // about 24 Hours;
-        state.account_version = 0;
+        state.account_version = ACCOUNT_VERSION;
         state.staking = Staking {
             round_length: staking_round_length,
             amount_per_round: amount_per_round,
@@ -173,6 +282,65 @@ pub mod exchange {
                 start: slot.checked_add(staking_round_length.into()).unwrap(),
             },
         };
+        // crank is not rewarded until admin sets fund and reward
+        state.crank = Crank::default();
+        state.debt = 0;
+        // no ceiling until admin sets one
+        state.debt_ceiling = u64::MAX;
+        state.min_account_debt = 0;
+        state.max_account_debt = u64::MAX;
+        // no stability fee until admin sets one
+        state.stability_fee = 0;
+        state.stability_fee_index = 10u128.pow(INTEREST_OFFSET.into());
+        state.stability_fee_slot = slot;
+        state.accumulated_stability_fee = 0;
+        // fee can't be withdrawn until admin sets vault
+        state.stability_fee_vault = Pubkey::default();
+        Ok(())
+    }
+    // Assets list is migrated together with state, it has to be created with new size before
+    pub fn migrate_state(ctx: Context<MigrateState>, bump: u8) -> Result<()> {
+        msg!("Synthetify: MIGRATE STATE");
+        let slot = Clock::get()?.slot;
+        let legacy_state = &ctx.accounts.legacy_state;
+        let legacy_assets_list = &ctx.accounts.legacy_assets_list;
+        {
+            let legacy = load_legacy_state(legacy_state, ctx.program_id)?;
+            require!(ctx.accounts.admin.key.eq(&{ legacy.admin }), Unauthorized);
+            require!(
+                legacy_assets_list.key.eq(&{ legacy.assets_list }),
+                InvalidAssetsList
+            );
+            let assets_list = &mut ctx.accounts.assets_list.load_init()?;
+            upgrade_assets_list(
+                &*load_legacy_assets_list(legacy_assets_list, ctx.program_id)?,
+                assets_list,
+            );
+
+            let state = &mut ctx.accounts.state.load_init()?;
+            upgrade_state(&legacy, state, slot);
+            state.bump = bump;
+            state.assets_list = *ctx.accounts.assets_list.to_account_info().key;
+            state.oracle_program = *ctx.accounts.oracle_program.key;
+            // Cached debt at migrated prices, refreshed with next price update
+            state.debt = calculate_stored_debt(assets_list);
+        }
+        close_legacy_account(legacy_state, &ctx.accounts.payer)?;
+        close_legacy_account(legacy_assets_list, &ctx.accounts.payer)?;
+        Ok(())
+    }
+    pub fn migrate_exchange_account(ctx: Context<MigrateExchangeAccount>, bump: u8) -> Result<()> {
+        msg!("Synthetify: MIGRATE EXCHANGE ACCOUNT");
+        let legacy_account = &ctx.accounts.legacy_exchange_account;
+        {
+            let legacy = load_legacy_exchange_account(legacy_account, ctx.program_id)?;
+            require!(ctx.accounts.owner.key.eq(&{ legacy.owner }), InvalidSigner);
+
+            let exchange_account = &mut ctx.accounts.exchange_account.load_init()?;
+            upgrade_exchange_account(&legacy, exchange_account);
+            exchange_account.bump = bump;
+        }
+        close_legacy_account(legacy_account, &ctx.accounts.payer)?;
         Ok(())
     }
     #[access_control(halted(&ctx.accounts.state)
@@ -238,7 +406,6 @@ pub mod exchange {
     }
     #[access_control(halted(&ctx.accounts.state)
     version(&ctx.accounts.state,&ctx.accounts.exchange_account)
-    usd_token(&ctx.accounts.usd_token,&ctx.accounts.assets_list)
     assets_list(&ctx.accounts.state,&ctx.accounts.assets_list))]
     pub fn mint(ctx: Context<Mint>, amount: u64) -> Result<()> {
         msg!("Synthetify: MINT");
@@ -254,8 +421,14 @@ pub mod exchange {
         adjust_staking_account(exchange_account, &state.staking);
 
         let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
-
-        let total_debt = calculate_debt(assets_list, slot, state.max_delay).unwrap();
+        // Debt grows by stability fee before it is shared
+        adjust_stability_fee(state, assets_list, slot)?;
+
+        let total_debt = state.debt;
+        // Only collaterals of user have to be fresh, debt is cached
+        check_collaterals_update(exchange_account, assets_list, state.max_delay, slot)?;
+        // Prices used for debt and collateral have to be precise enough
+        check_account_confidence(exchange_account, assets_list)?;
         let user_debt = calculate_user_debt_in_usd(exchange_account, total_debt, state.debt_shares);
         let max_debt = calculate_max_debt_in_usd(exchange_account, assets_list);
         let max_borrow = max_debt
@@ -264,19 +437,33 @@ pub mod exchange {
             .checked_div(100)
             .unwrap();
 
-        let synthetics = &mut assets_list.synthetics;
+        check_collaterals_frozen(exchange_account, assets_list)?;
 
-        // We can only mint xUSD
-        // Both xUSD and collateral token have static index in assets array
-        let mut xusd_synthetic = &mut synthetics[0];
+        let (assets, _, synthetics) = assets_list.split_borrow();
 
-        if max_borrow < amount.checked_add(user_debt).unwrap().into() {
+        let synthetic_token = ctx.accounts.synthetic_token.key;
+        let minted_synthetic = match synthetics
+            .iter_mut()
+            .find(|x| x.asset_address.eq(synthetic_token))
+        {
+            Some(v) => v,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        };
+        check_mintable_synthetic(assets, minted_synthetic, state.max_delay, slot)?;
+
+        // New debt is valued through oracle of minted synthetic
+        let minted_asset = &assets[minted_synthetic.asset_index as usize];
+        let amount_in_usd = calculate_debt_in_usd(minted_asset, minted_synthetic, amount);
+        if max_borrow < amount_in_usd.checked_add(user_debt).unwrap().into() {
             return Err(ErrorCode::MintLimit.into());
         }
+        check_debt_ceiling(state, amount_in_usd)?;
+        check_account_debt(state, user_debt.checked_add(amount_in_usd).unwrap())?;
 
         // Adjust program and user debt_shares
         // Rounding up - debt is created in favor of the system
-        let new_shares = calculate_new_shares_by_rounding_up(state.debt_shares, total_debt, amount);
+        let new_shares =
+            calculate_new_shares_by_rounding_up(state.debt_shares, total_debt, amount_in_usd);
         state.debt_shares = state.debt_shares.checked_add(new_shares).unwrap();
         exchange_account.debt_shares = exchange_account
             .debt_shares
@@ -286,11 +473,11 @@ pub mod exchange {
         exchange_account.user_staking_data.next_round_points = exchange_account.debt_shares;
         state.staking.next_round.all_points = state.debt_shares;
 
-        let new_supply = xusd_synthetic.supply.checked_add(amount).unwrap();
-        set_synthetic_supply(&mut xusd_synthetic, new_supply)?;
+        let new_supply = minted_synthetic.supply.checked_add(amount).unwrap();
+        update_synthetic_supply(state, minted_asset, minted_synthetic, new_supply)?;
         let seeds = &[SYNTHETIFY_EXCHANGE_SEED.as_bytes(), &[state.nonce]];
         let signer = &[&seeds[..]];
-        // Mint xUSD to user
+        // Mint synthetic to user
         let mint_cpi_ctx = CpiContext::from(&*ctx.accounts).with_signer(signer);
         token::mint_to(mint_cpi_ctx, amount)?;
         Ok(())
@@ -320,7 +507,12 @@ pub mod exchange {
 
         // Calculate debt
         let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
-        let total_debt = calculate_debt(assets_list, slot, state.max_delay).unwrap();
+        // Debt grows by stability fee before collateral is checked
+        adjust_stability_fee(state, assets_list, slot)?;
+        let total_debt = state.debt;
+        check_collaterals_update(exchange_account, assets_list, state.max_delay, slot)?;
+        // Prices used for debt and collateral have to be precise enough
+        check_account_confidence(exchange_account, assets_list)?;
         let user_debt = calculate_user_debt_in_usd(exchange_account, total_debt, state.debt_shares);
         let max_debt = calculate_max_debt_in_usd(exchange_account, assets_list);
         let max_borrow = max_debt
@@ -329,7 +521,7 @@ pub mod exchange {
             .checked_div(100)
             .unwrap();
         let (assets, collaterals, _) = assets_list.split_borrow();
-        let mut collateral = match collaterals
+        let collateral = match collaterals
             .iter_mut()
             .find(|x| x.collateral_address.eq(&user_collateral_account.mint))
         {
@@ -337,7 +529,7 @@ pub mod exchange {
             None => return Err(ErrorCode::NoAssetFound.into()),
         };
 
-        let (entry_index, mut exchange_account_collateral) = match exchange_account
+        let (entry_index, exchange_account_collateral) = match exchange_account
             .collaterals
             .iter_mut()
             .enumerate()
@@ -423,16 +615,13 @@ pub mod exchange {
             .iter()
             .position(|x| x.asset_address == *token_address_for)
             .unwrap();
-
-        // Check is oracles have been updated
-        check_feed_update(
+        check_swap_synthetics(
             assets,
-            synthetics[synthetic_in_index].asset_index as usize,
-            synthetics[synthetic_for_index].asset_index as usize,
+            &synthetics[synthetic_in_index],
+            &synthetics[synthetic_for_index],
             state.max_delay,
             slot,
-        )
-        .unwrap();
+        )?;
         let sny_collateral = &mut collaterals[0];
 
         let collateral_amount = get_user_sny_collateral_balance(&exchange_account, &sny_collateral);
@@ -467,13 +656,23 @@ pub mod exchange {
             .supply
             .checked_add(amount_for)
             .unwrap();
-        set_synthetic_supply(&mut synthetics[synthetic_for_index], new_supply_output)?;
+        update_synthetic_supply(
+            state,
+            &assets[synthetics[synthetic_for_index].asset_index as usize],
+            &mut synthetics[synthetic_for_index],
+            new_supply_output,
+        )?;
         // Set new supply input token
         let new_supply_input = synthetics[synthetic_in_index]
             .supply
             .checked_sub(amount)
             .unwrap();
-        set_synthetic_supply(&mut synthetics[synthetic_in_index], new_supply_input)?;
+        update_synthetic_supply(
+            state,
+            &assets[synthetics[synthetic_in_index].asset_index as usize],
+            &mut synthetics[synthetic_in_index],
+            new_supply_input,
+        )?;
         // Burn input token
         let cpi_ctx_burn: CpiContext<Burn> = CpiContext::from(&*ctx.accounts).with_signer(signer);
         token::burn(cpi_ctx_burn, amount)?;
@@ -484,9 +683,64 @@ pub mod exchange {
         Ok(())
     }
     #[access_control(halted(&ctx.accounts.state)
-    version(&ctx.accounts.state,&ctx.accounts.exchange_account)
     assets_list(&ctx.accounts.state,&ctx.accounts.assets_list)
     usd_token(&ctx.accounts.usd_token,&ctx.accounts.assets_list))]
+    pub fn settle_synthetic(ctx: Context<SettleSynthetic>, amount: u64) -> Result<()> {
+        msg!("Synthetify: SETTLE SYNTHETIC");
+        let slot = Clock::get()?.slot;
+        let state = &mut ctx.accounts.state.load_mut()?;
+        let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
+        let (assets, _, synthetics) = assets_list.split_borrow();
+
+        // Signer need to be owner of source account
+        if !ctx
+            .accounts
+            .owner
+            .key
+            .eq(&ctx.accounts.user_settled_token_account.owner)
+        {
+            return Err(ErrorCode::InvalidSigner.into());
+        }
+        let settled_token = ctx.accounts.settled_token.key;
+        let settled_index = match synthetics
+            .iter()
+            .position(|x| x.asset_address.eq(settled_token))
+        {
+            Some(v) => v,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        };
+        let amount_usd =
+            convert_settled_synthetic(state, assets, synthetics, settled_index, amount, slot)?;
+
+        let seeds = &[SYNTHETIFY_EXCHANGE_SEED.as_bytes(), &[state.nonce]];
+        let signer_seeds = &[&seeds[..]];
+        {
+            // burn settled synthetic
+            let burn_accounts = Burn {
+                mint: ctx.accounts.settled_token.to_account_info(),
+                to: ctx.accounts.user_settled_token_account.to_account_info(),
+                authority: ctx.accounts.exchange_authority.to_account_info(),
+            };
+            let token_program = ctx.accounts.token_program.to_account_info();
+            let burn = CpiContext::new(token_program, burn_accounts).with_signer(signer_seeds);
+            token::burn(burn, amount)?;
+        }
+        {
+            // mint xUSD
+            let mint_accounts = MintTo {
+                mint: ctx.accounts.usd_token.to_account_info(),
+                to: ctx.accounts.user_usd_token_account.to_account_info(),
+                authority: ctx.accounts.exchange_authority.to_account_info(),
+            };
+            let token_program = ctx.accounts.token_program.to_account_info();
+            let mint = CpiContext::new(token_program, mint_accounts).with_signer(signer_seeds);
+            token::mint_to(mint, amount_usd)?;
+        }
+        Ok(())
+    }
+    #[access_control(halted(&ctx.accounts.state)
+    version(&ctx.accounts.state,&ctx.accounts.exchange_account)
+    assets_list(&ctx.accounts.state,&ctx.accounts.assets_list))]
     pub fn burn(ctx: Context<BurnToken>, amount: u64) -> Result<()> {
         msg!("Synthetify: BURN");
         let slot = Clock::get()?.slot;
@@ -500,8 +754,10 @@ pub mod exchange {
         adjust_staking_account(exchange_account, &state.staking);
 
         let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
-        let debt = calculate_debt(assets_list, slot, state.max_delay).unwrap();
-        let (assets, collaterals, synthetics) = assets_list.split_borrow();
+        // Debt grows by stability fee before it is shared
+        adjust_stability_fee(state, assets_list, slot)?;
+        let debt = state.debt;
+        let (assets, _, synthetics) = assets_list.split_borrow();
 
         let tx_signer = ctx.accounts.owner.key;
         let user_token_account_burn = &ctx.accounts.user_token_account_burn;
@@ -510,9 +766,22 @@ pub mod exchange {
         if !tx_signer.eq(&user_token_account_burn.owner) {
             return Err(ErrorCode::InvalidSigner.into());
         }
-        // xUSD got static index 0
-        let burn_asset = &mut assets[0];
-        let burn_synthetic = &mut synthetics[0];
+        let synthetic_token = ctx.accounts.synthetic_token.key;
+        let burn_synthetic = match synthetics
+            .iter_mut()
+            .find(|x| x.asset_address.eq(synthetic_token))
+        {
+            Some(v) => v,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        };
+        // Burned synthetic is valued through its oracle, unless price is frozen by settlement
+        let asset_index = burn_synthetic.asset_index as usize;
+        if !is_settlement_priced(burn_synthetic) {
+            check_feed_update(assets, asset_index, asset_index, state.max_delay, slot)?;
+            check_feed_frozen(assets, asset_index, asset_index)?;
+            check_feed_breaker(assets, asset_index, asset_index)?;
+        }
+        let burn_asset = &assets[asset_index];
 
         let user_debt = calculate_user_debt_in_usd(exchange_account, debt, state.debt_shares);
 
@@ -528,10 +797,15 @@ pub mod exchange {
         let seeds = &[SYNTHETIFY_EXCHANGE_SEED.as_bytes(), &[state.nonce]];
         let signer = &[&seeds[..]];
 
+        // Burn leaving dust on account repays whole debt
+        let remaining_debt =
+            user_debt.saturating_sub(calculate_burned_in_usd(burn_asset, burn_synthetic, amount));
+
         // Check if user burned more than debt
-        if burned_shares >= exchange_account.debt_shares {
+        if burned_shares >= exchange_account.debt_shares || is_dust_debt(state, remaining_debt) {
             // Burn adjusted amount
-            let burned_amount = calculate_max_burned_in_xusd(&burn_asset, user_debt);
+            let burned_amount =
+                calculate_max_burned_in_token(burn_asset, burn_synthetic, user_debt);
             state.debt_shares = state
                 .debt_shares
                 .checked_sub(exchange_account.debt_shares)
@@ -553,10 +827,8 @@ pub mod exchange {
             exchange_account.user_staking_data.current_round_points = 0;
 
             // Change supply
-            set_synthetic_supply(
-                burn_synthetic,
-                burn_synthetic.supply.checked_sub(burned_amount).unwrap(),
-            )?;
+            let new_supply = burn_synthetic.supply.checked_sub(burned_amount).unwrap();
+            update_synthetic_supply(state, burn_asset, burn_synthetic, new_supply)?;
             // Burn token
             // We do not use full allowance maybe its better to burn full allowance
             // and mint matching amount
@@ -598,10 +870,8 @@ pub mod exchange {
             }
 
             // Change supply
-            set_synthetic_supply(
-                burn_synthetic,
-                burn_synthetic.supply.checked_sub(amount).unwrap(),
-            )?;
+            let new_supply = burn_synthetic.supply.checked_sub(amount).unwrap();
+            update_synthetic_supply(state, burn_asset, burn_synthetic, new_supply)?;
             // Burn token
             let cpi_ctx = CpiContext::from(&*ctx.accounts).with_signer(signer);
             token::burn(cpi_ctx, amount)?;
@@ -626,11 +896,13 @@ pub mod exchange {
         adjust_staking_account(exchange_account, &state.staking);
 
         let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
+        // Debt grows by stability fee before it is shared
+        adjust_stability_fee(state, assets_list, slot)?;
         let signer = ctx.accounts.signer.key;
         let reserve_account = &ctx.accounts.reserve_account;
         let liquidator_usd_account = &ctx.accounts.liquidator_usd_account;
 
-        let debt = calculate_debt(assets_list, slot, state.max_delay).unwrap();
+        let debt = state.debt;
 
         // Signer need to be owner of source amount
         if !signer.eq(&liquidator_usd_account.owner) {
@@ -642,8 +914,10 @@ pub mod exchange {
             return Err(ErrorCode::LiquidationDeadline.into());
         }
 
-        let total_debt = calculate_debt(assets_list, slot, state.max_delay).unwrap();
-        let user_debt = calculate_user_debt_in_usd(exchange_account, total_debt, state.debt_shares);
+        check_collaterals_update(exchange_account, assets_list, state.max_delay, slot)?;
+        // Prices used for debt and collateral have to be precise enough
+        check_account_confidence(exchange_account, assets_list)?;
+        let user_debt = calculate_user_debt_in_usd(exchange_account, debt, state.debt_shares);
         let max_debt = calculate_max_debt_in_usd(exchange_account, assets_list);
 
         // Check collateral ratio
@@ -656,10 +930,19 @@ pub mod exchange {
             .unwrap()
             .checked_div(100)
             .unwrap();
+        // Whole debt can be repaid if rest would be dust
+        let max_repay = match is_dust_debt(state, user_debt.saturating_sub(max_repay)) {
+            true => user_debt,
+            false => max_repay,
+        };
 
         if amount.gt(&max_repay) {
             return Err(ErrorCode::InvalidLiquidation.into());
         }
+        // Liquidation cannot leave dust on account
+        if is_dust_debt(state, user_debt.checked_sub(amount).unwrap()) {
+            return Err(ErrorCode::AccountDebtDust.into());
+        }
         let (assets, collaterals, synthetics) = assets_list.split_borrow();
 
         let liquidated_collateral = match collaterals
@@ -703,14 +986,13 @@ pub mod exchange {
             seized_collateral_in_usd.try_into().unwrap(),
         );
 
-        let mut exchange_account_collateral =
-            match exchange_account.collaterals.iter_mut().find(|x| {
-                x.collateral_address
-                    .eq(&liquidated_collateral.collateral_address)
-            }) {
-                Some(v) => v,
-                None => return Err(ErrorCode::NoAssetFound.into()),
-            };
+        let exchange_account_collateral = match exchange_account.collaterals.iter_mut().find(|x| {
+            x.collateral_address
+                .eq(&liquidated_collateral.collateral_address)
+        }) {
+            Some(v) => v,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        };
         exchange_account_collateral.amount = exchange_account_collateral
             .amount
             .checked_sub(seized_collateral_in_token)
@@ -749,8 +1031,8 @@ pub mod exchange {
             .all_points
             .checked_sub(exchange_account.user_staking_data.finished_round_points)
             .unwrap();
-        exchange_account.user_staking_data.finished_round_points = 0u64;
-        exchange_account.user_staking_data.current_round_points = 0u64;
+        exchange_account.user_staking_data.finished_round_points = 0u128;
+        exchange_account.user_staking_data.current_round_points = 0u128;
         exchange_account.user_staking_data.next_round_points = exchange_account.debt_shares;
 
         let seeds = &[SYNTHETIFY_EXCHANGE_SEED.as_bytes(), &[state.nonce]];
@@ -791,11 +1073,8 @@ pub mod exchange {
         }
         {
             // burn xUSD
-            let new_supply = assets_list.synthetics[0]
-                .supply
-                .checked_sub(amount)
-                .unwrap();
-            set_synthetic_supply(&mut assets_list.synthetics[0], new_supply)?;
+            let new_supply = synthetics[0].supply.checked_sub(amount).unwrap();
+            update_synthetic_supply(state, &assets[0], &mut synthetics[0], new_supply)?;
             let burn_accounts = Burn {
                 mint: ctx.accounts.usd_token.to_account_info(),
                 to: ctx.accounts.liquidator_usd_account.to_account_info(),
@@ -824,9 +1103,12 @@ pub mod exchange {
         // adjust current staking points for exchange account
         adjust_staking_account(exchange_account, &state.staking);
 
-        let assets_list = &ctx.accounts.assets_list.load_mut()?;
+        let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
+        // Debt grows by stability fee before collateral is checked
+        adjust_stability_fee(state, assets_list, slot)?;
 
-        let total_debt = calculate_debt(assets_list, slot, state.max_delay).unwrap();
+        let total_debt = state.debt;
+        check_collaterals_update(exchange_account, assets_list, state.max_delay, slot)?;
         let user_debt = calculate_user_debt_in_usd(exchange_account, total_debt, state.debt_shares);
         let max_debt = calculate_max_debt_in_usd(exchange_account, assets_list);
 
@@ -860,13 +1142,12 @@ pub mod exchange {
         adjust_staking_account(exchange_account, &state.staking);
 
         if state.staking.finished_round.amount > 0 {
-            let reward_amount = state
-                .staking
-                .finished_round
-                .amount
+            let reward_amount: u64 = (state.staking.finished_round.amount as u128)
                 .checked_mul(exchange_account.user_staking_data.finished_round_points)
                 .unwrap()
                 .checked_div(state.staking.finished_round.all_points)
+                .unwrap()
+                .try_into()
                 .unwrap();
 
             exchange_account.user_staking_data.amount_to_claim = exchange_account
@@ -958,7 +1239,91 @@ pub mod exchange {
             feed_address: new_asset_feed_address,
             last_update: 0,
             price: 0,
+            twap: 0,
+            price_mode: PRICE_MODE_SPOT,
+            confidence: 0,
+            max_confidence: u32::MAX,
+            frozen: false,
+            secondary_feed_address: Pubkey::default(),
+            max_divergence: 0,
+            price_source: PRICE_SOURCE_PRIMARY,
+            max_price_change: u32::MAX,
+            breaker_cooldown: 0,
+            breaker_slot: 0,
+            in_breaker: false,
+            last_crank: 0,
+            max_delay: 0,
+            confidence_multiplier: 0,
+            asset_kind: ASSET_KIND_FEED,
+            input_feeds: [Pubkey::default(); DERIVED_MAX_INPUTS],
+            input_weights: [0; DERIVED_MAX_INPUTS],
+            entry_price: 0,
+            lower_bound: 0,
+            upper_bound: 0,
+            price_history: Pubkey::default(),
+        };
+
+        assets_list.append_asset(new_asset);
+        Ok(())
+    }
+    // Input feeds of derived asset are passed in remaining_accounts
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
+    pub fn add_derived_asset(
+        ctx: Context<AddNewAsset>,
+        asset_address: Pubkey,
+        asset_kind: u8,
+        input_weights: Vec<u64>,
+    ) -> Result<()> {
+        msg!("Synthetify:Admin: ADD DERIVED ASSET");
+        let state = ctx.accounts.state.load()?;
+        let mut assets_list = ctx.accounts.assets_list.load_mut()?;
+        if !assets_list.initialized {
+            return Err(ErrorCode::Uninitialized.into());
+        }
+        check_asset_kind(asset_kind, ctx.remaining_accounts.len(), &input_weights)?;
+        // asset_address identifies derived asset in place of feed address
+        let head = assets_list.head_assets as usize;
+        if assets_list.assets[..head]
+            .iter()
+            .any(|x| x.feed_address == asset_address)
+        {
+            return Err(ErrorCode::InvalidAssetKind.into());
+        }
+
+        let mut input_feeds = [Pubkey::default(); DERIVED_MAX_INPUTS];
+        for (index, input_feed) in ctx.remaining_accounts.iter().enumerate() {
+            load_price_feed(input_feed, &state.oracle_program)?;
+            input_feeds[index] = *input_feed.key;
+        }
+        let mut weights = [0; DERIVED_MAX_INPUTS];
+        weights[..input_weights.len()].copy_from_slice(&input_weights);
+
+        let new_asset = Asset {
+            feed_address: asset_address,
+            last_update: 0,
+            price: 0,
+            twap: 0,
+            price_mode: PRICE_MODE_SPOT,
             confidence: 0,
+            max_confidence: u32::MAX,
+            frozen: false,
+            secondary_feed_address: Pubkey::default(),
+            max_divergence: 0,
+            price_source: PRICE_SOURCE_PRIMARY,
+            max_price_change: u32::MAX,
+            breaker_cooldown: 0,
+            breaker_slot: 0,
+            in_breaker: false,
+            last_crank: 0,
+            max_delay: 0,
+            confidence_multiplier: 0,
+            asset_kind,
+            input_feeds,
+            input_weights: weights,
+            entry_price: 0,
+            lower_bound: 0,
+            upper_bound: 0,
+            price_history: Pubkey::default(),
         };
 
         assets_list.append_asset(new_asset);
@@ -1036,6 +1401,124 @@ pub mod exchange {
         state.staking.round_length = round_length;
         Ok(())
     }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.admin))]
+    pub fn set_oracle_program(ctx: Context<SetOracleProgram>) -> Result<()> {
+        msg!("Synthetify:Admin: SET ORACLE PROGRAM");
+        let state = &mut ctx.accounts.state.load_mut()?;
+
+        state.oracle_program = *ctx.accounts.oracle_program.key;
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.admin))]
+    pub fn set_crank_fund(ctx: Context<SetCrankFund>) -> Result<()> {
+        msg!("Synthetify:Admin:Crank: SET FUND");
+        let state = &mut ctx.accounts.state.load_mut()?;
+
+        state.crank.fund_account = *ctx.accounts.crank_fund_account.to_account_info().key;
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.admin))]
+    pub fn set_crank_reward(
+        ctx: Context<AdminAction>,
+        reward: u64,
+        min_age: u32,
+        max_rewards_per_slot: u8,
+    ) -> Result<()> {
+        msg!("Synthetify:Admin:Crank: SET REWARD");
+        let state = &mut ctx.accounts.state.load_mut()?;
+
+        state.crank.reward = reward;
+        state.crank.min_age = min_age;
+        state.crank.max_rewards_per_slot = max_rewards_per_slot;
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.admin))]
+    pub fn set_debt_ceiling(ctx: Context<AdminAction>, debt_ceiling: u64) -> Result<()> {
+        msg!("Synthetify:Admin: SET DEBT CEILING");
+        let state = &mut ctx.accounts.state.load_mut()?;
+
+        state.debt_ceiling = debt_ceiling;
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.admin))]
+    pub fn set_account_debt_limits(
+        ctx: Context<AdminAction>,
+        min_account_debt: u64,
+        max_account_debt: u64,
+    ) -> Result<()> {
+        msg!("Synthetify:Admin: SET ACCOUNT DEBT LIMITS");
+        let state = &mut ctx.accounts.state.load_mut()?;
+
+        if min_account_debt > max_account_debt {
+            return Err(ErrorCode::InvalidAccountDebtLimits.into());
+        }
+        state.min_account_debt = min_account_debt;
+        state.max_account_debt = max_account_debt;
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer)
+        assets_list(&ctx.accounts.state,&ctx.accounts.assets_list))]
+    pub fn set_stability_fee(ctx: Context<SetStabilityFee>, stability_fee: u32) -> Result<()> {
+        msg!("Synthetify:Admin: SET STABILITY FEE");
+        let slot = Clock::get()?.slot;
+        let state = &mut ctx.accounts.state.load_mut()?;
+        let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
+
+        // Max 100% yearly
+        if stability_fee > 100000 {
+            return Err(ErrorCode::InvalidStabilityFee.into());
+        }
+        // Fee until now is charged at previous rate
+        adjust_stability_fee(state, assets_list, slot)?;
+        state.stability_fee = stability_fee;
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer)
+        assets_list(&ctx.accounts.state,&ctx.accounts.assets_list)
+        usd_token(&ctx.accounts.usd_token,&ctx.accounts.assets_list)
+        stability_fee_vault(&ctx.accounts.state,&ctx.accounts.to))]
+    pub fn withdraw_stability_fee(ctx: Context<WithdrawStabilityFee>) -> Result<()> {
+        msg!("Synthetify:Admin: WITHDRAW STABILITY FEE");
+        let slot = Clock::get()?.slot;
+        let state = &mut ctx.accounts.state.load_mut()?;
+        let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
+
+        adjust_stability_fee(state, assets_list, slot)?;
+        // xUSD backing accrued fee is already in supply
+        let amount = state.accumulated_stability_fee;
+        state.accumulated_stability_fee = 0;
+
+        let seeds = &[SYNTHETIFY_EXCHANGE_SEED.as_bytes(), &[state.nonce]];
+        let signer_seeds = &[&seeds[..]];
+        let mint_accounts = MintTo {
+            mint: ctx.accounts.usd_token.to_account_info(),
+            to: ctx.accounts.to.to_account_info(),
+            authority: ctx.accounts.exchange_authority.to_account_info(),
+        };
+        let token_program = ctx.accounts.token_program.to_account_info();
+        let mint = CpiContext::new(token_program, mint_accounts).with_signer(signer_seeds);
+        token::mint_to(mint, amount)?;
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.admin)
+        assets_list(&ctx.accounts.state,&ctx.accounts.assets_list))]
+    pub fn set_stability_fee_vault(ctx: Context<SetStabilityFeeVault>) -> Result<()> {
+        msg!("Synthetify:Admin: SET STABILITY FEE VAULT");
+        let state = &mut ctx.accounts.state.load_mut()?;
+        let assets_list = ctx.accounts.assets_list.load()?;
+
+        // Fee is withdrawn in xUSD
+        if !ctx
+            .accounts
+            .stability_fee_vault
+            .mint
+            .eq(&assets_list.synthetics[0].asset_address)
+        {
+            return Err(ErrorCode::NotSyntheticUsd.into());
+        }
+        state.stability_fee_vault = *ctx.accounts.stability_fee_vault.to_account_info().key;
+        Ok(())
+    }
 
     #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
     pub fn set_max_supply(
@@ -1057,50 +1540,351 @@ pub mod exchange {
         Ok(())
     }
     #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
-    pub fn set_price_feed(ctx: Context<SetPriceFeed>, old_feed_address: Pubkey) -> Result<()> {
+    pub fn set_settlement_slot(
+        ctx: Context<SetSettlementSlot>,
+        asset_address: Pubkey,
+        settlement_slot: u64,
+    ) -> Result<()> {
+        msg!("Synthetify:Admin: SET SETTLEMENT SLOT");
+        let slot = Clock::get()?.slot;
+        let mut assets_list = ctx.accounts.assets_list.load_mut()?;
+
+        let head = assets_list.head_synthetics as usize;
+        let synthetic_index = match assets_list.synthetics[..head]
+            .iter()
+            .position(|x| x.asset_address == asset_address)
+        {
+            Some(v) => v,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        };
+        // xUSD is never settled and settlement cannot be in past
+        if synthetic_index == 0 || settlement_slot < slot {
+            return Err(ErrorCode::InvalidSettlement.into());
+        }
+        let synthetic = &mut assets_list.synthetics[synthetic_index];
+        if is_settled(synthetic, slot) {
+            return Err(ErrorCode::SettledSynthetic.into());
+        }
+        // u64::MAX cancels scheduled settlement
+        synthetic.settlement_slot = settlement_slot;
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
+    pub fn set_max_confidence(
+        ctx: Context<SetMaxConfidence>,
+        feed_address: Pubkey,
+        max_confidence: u32,
+    ) -> Result<()> {
+        msg!("Synthetify:Admin: SET MAX CONFIDENCE");
         let mut assets_list = ctx.accounts.assets_list.load_mut()?;
 
         let asset = assets_list
             .assets
             .iter_mut()
-            .find(|x| x.feed_address == old_feed_address);
+            .find(|x| x.feed_address == feed_address);
 
         match asset {
-            Some(asset) => asset.feed_address = *ctx.accounts.price_feed.key,
+            Some(asset) => asset.max_confidence = max_confidence,
             None => return Err(ErrorCode::NoAssetFound.into()),
         }
         Ok(())
     }
-    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.admin))]
-    pub fn set_liquidation_penalties(
-        ctx: Context<AdminAction>,
-        penalty_to_exchange: u8,
-        penalty_to_liquidator: u8,
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
+    pub fn set_confidence_multiplier(
+        ctx: Context<SetConfidenceMultiplier>,
+        feed_address: Pubkey,
+        confidence_multiplier: u16,
     ) -> Result<()> {
-        msg!("Synthetify:Admin: SET LIQUIDATION PENALTIES");
+        msg!("Synthetify:Admin: SET CONFIDENCE MULTIPLIER");
         let state = &mut ctx.accounts.state.load_mut()?;
+        let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
 
-        state.penalty_to_exchange = penalty_to_exchange;
-        state.penalty_to_liquidator = penalty_to_liquidator;
-
+        let head_synthetics = assets_list.head_synthetics as usize;
+        let (assets, _, synthetics) = assets_list.split_borrow();
+        let asset_index = match assets.iter().position(|x| x.feed_address == feed_address) {
+            Some(asset_index) => asset_index,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        };
+        // Debt price depends on multiplier
+        revalue_asset(
+            state,
+            &mut assets[asset_index],
+            asset_index,
+            &synthetics[..head_synthetics],
+            |asset| asset.confidence_multiplier = confidence_multiplier,
+        );
         Ok(())
     }
-    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.admin))]
-    pub fn add_collateral(
-        ctx: Context<AddCollateral>,
-        reserve_balance: u64,
-        decimals: u8,
-        collateral_ratio: u8,
+    // Sets new price band of inverse asset without settled or scheduled synthetics
+    // Price is reset to entry and stays outdated, so positions priced by asset
+    // can't be used until next price update
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
+    pub fn set_inverse_price_band(
+        ctx: Context<SetInversePriceBand>,
+        asset_address: Pubkey,
+        entry_price: u64,
+        lower_bound: u64,
+        upper_bound: u64,
     ) -> Result<()> {
-        msg!("Synthetify:Admin: ADD COLLATERAL");
-        let mut assets_list = ctx.accounts.assets_list.load_mut()?;
+        msg!("Synthetify:Admin: SET INVERSE PRICE BAND");
+        let state = &mut ctx.accounts.state.load_mut()?;
+        let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
+        if !(lower_bound < entry_price && entry_price < upper_bound) {
+            return Err(ErrorCode::InvalidPriceBand.into());
+        }
 
-        let asset_index = match assets_list
-            .assets
-            .iter_mut()
-            .position(|x| x.feed_address == *ctx.accounts.feed_address.key)
-        {
-            Some(asset) => asset,
+        let head_synthetics = assets_list.head_synthetics as usize;
+        let (assets, _, synthetics) = assets_list.split_borrow();
+        let asset_index = match assets.iter().position(|x| x.feed_address == asset_address) {
+            Some(asset_index) => asset_index,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        };
+        let asset = &mut assets[asset_index];
+        if asset.asset_kind != ASSET_KIND_INVERSE_SYNTHETIC {
+            return Err(ErrorCode::InvalidAssetKind.into());
+        }
+        // Settlement can't be cancelled or undone by new band
+        if synthetics[..head_synthetics]
+            .iter()
+            .any(|x| x.asset_index as usize == asset_index && x.settlement_slot != u64::MAX)
+        {
+            return Err(ErrorCode::SettledSynthetic.into());
+        }
+        revalue_asset(
+            state,
+            asset,
+            asset_index,
+            &synthetics[..head_synthetics],
+            |asset| {
+                asset.entry_price = entry_price;
+                asset.lower_bound = lower_bound;
+                asset.upper_bound = upper_bound;
+                // Price at entry, outdated until next oracle update
+                asset.price = entry_price;
+                asset.twap = entry_price;
+                asset.last_update = 0;
+            },
+        );
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
+    pub fn set_price_history(ctx: Context<SetPriceHistory>, feed_address: Pubkey) -> Result<()> {
+        msg!("Synthetify:Admin: SET PRICE HISTORY");
+        let mut assets_list = ctx.accounts.assets_list.load_mut()?;
+        let price_history = &mut ctx.accounts.price_history.load_mut()?;
+
+        let asset = assets_list
+            .assets
+            .iter_mut()
+            .find(|x| x.feed_address == feed_address);
+
+        match asset {
+            Some(asset) => asset.price_history = *ctx.accounts.price_history.to_account_info().key,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        }
+        // History of previous asset is dropped
+        price_history.feed_address = feed_address;
+        price_history.head = 0;
+        price_history.count = 0;
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
+    pub fn set_asset_max_delay(
+        ctx: Context<SetAssetMaxDelay>,
+        feed_address: Pubkey,
+        max_delay: u32,
+    ) -> Result<()> {
+        msg!("Synthetify:Admin: SET ASSET MAX DELAY");
+        let mut assets_list = ctx.accounts.assets_list.load_mut()?;
+
+        let asset = assets_list
+            .assets
+            .iter_mut()
+            .find(|x| x.feed_address == feed_address);
+
+        match asset {
+            Some(asset) => asset.max_delay = max_delay,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        }
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
+    pub fn set_price_mode(
+        ctx: Context<SetPriceMode>,
+        feed_address: Pubkey,
+        price_mode: u8,
+    ) -> Result<()> {
+        msg!("Synthetify:Admin: SET PRICE MODE");
+        if price_mode > PRICE_MODE_CONSERVATIVE {
+            return Err(ErrorCode::InvalidPriceMode.into());
+        }
+        let state = &mut ctx.accounts.state.load_mut()?;
+        let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
+
+        let head_synthetics = assets_list.head_synthetics as usize;
+        let (assets, _, synthetics) = assets_list.split_borrow();
+        let asset_index = match assets.iter().position(|x| x.feed_address == feed_address) {
+            Some(asset_index) => asset_index,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        };
+        // Debt price depends on mode
+        revalue_asset(
+            state,
+            &mut assets[asset_index],
+            asset_index,
+            &synthetics[..head_synthetics],
+            |asset| asset.price_mode = price_mode,
+        );
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
+    pub fn set_secondary_feed(
+        ctx: Context<SetSecondaryFeed>,
+        feed_address: Pubkey,
+        max_divergence: u32,
+    ) -> Result<()> {
+        msg!("Synthetify:Admin: SET SECONDARY FEED");
+        let state = ctx.accounts.state.load()?;
+        let mut assets_list = ctx.accounts.assets_list.load_mut()?;
+
+        // Make sure secondary feed is valid price account
+        load_price_feed(&ctx.accounts.secondary_feed, &state.oracle_program)?;
+
+        let asset = assets_list
+            .assets
+            .iter_mut()
+            .find(|x| x.feed_address == feed_address);
+
+        match asset {
+            Some(asset) => {
+                asset.secondary_feed_address = *ctx.accounts.secondary_feed.key;
+                asset.max_divergence = max_divergence;
+            }
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        }
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
+    pub fn set_price_breaker(
+        ctx: Context<SetPriceBreaker>,
+        feed_address: Pubkey,
+        max_price_change: u32,
+        breaker_cooldown: u64,
+    ) -> Result<()> {
+        msg!("Synthetify:Admin: SET PRICE BREAKER");
+        let mut assets_list = ctx.accounts.assets_list.load_mut()?;
+
+        let asset = assets_list
+            .assets
+            .iter_mut()
+            .find(|x| x.feed_address == feed_address);
+
+        match asset {
+            Some(asset) => {
+                asset.max_price_change = max_price_change;
+                asset.breaker_cooldown = breaker_cooldown;
+            }
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        }
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
+    pub fn reset_price_breaker(ctx: Context<ResetPriceBreaker>) -> Result<()> {
+        msg!("Synthetify:Admin: RESET PRICE BREAKER");
+        let state = &mut ctx.accounts.state.load_mut()?;
+        let assets_list = &mut ctx.accounts.assets_list.load_mut()?;
+
+        let price_feed = load_price_feed(&ctx.accounts.price_feed, &state.oracle_program)?;
+        let feed_price = read_feed_price(&price_feed)?;
+        if !feed_price.trading {
+            return Err(ErrorCode::FrozenAsset.into());
+        }
+
+        let slot = Clock::get()?.slot;
+        let head_synthetics = assets_list.head_synthetics as usize;
+        let (assets, _, synthetics) = assets_list.split_borrow();
+        let asset_index = match assets
+            .iter()
+            .position(|x| x.feed_address == *ctx.accounts.price_feed.key)
+        {
+            Some(asset_index) => asset_index,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        };
+        let asset = &mut assets[asset_index];
+        // Derived price is not read from single feed
+        if asset.asset_kind != ASSET_KIND_FEED {
+            return Err(ErrorCode::InvalidAssetKind.into());
+        }
+        // Admin accepts current feed price, asset frozen for other reason stays frozen
+        revalue_asset(
+            state,
+            asset,
+            asset_index,
+            &synthetics[..head_synthetics],
+            |asset| {
+                asset.in_breaker = false;
+                asset.price = feed_price.price;
+                asset.twap = feed_price.twap;
+                asset.confidence = feed_price.confidence;
+                asset.price_source = PRICE_SOURCE_PRIMARY;
+                asset.last_update = feed_price.valid_slot.min(slot);
+            },
+        );
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.signer))]
+    pub fn set_price_feed(ctx: Context<SetPriceFeed>, old_feed_address: Pubkey) -> Result<()> {
+        let state = ctx.accounts.state.load()?;
+        let mut assets_list = ctx.accounts.assets_list.load_mut()?;
+
+        // Make sure new feed is valid price account
+        load_price_feed(&ctx.accounts.price_feed, &state.oracle_program)?;
+
+        let asset = assets_list
+            .assets
+            .iter_mut()
+            .find(|x| x.feed_address == old_feed_address);
+
+        match asset {
+            Some(asset) => asset.feed_address = *ctx.accounts.price_feed.key,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        }
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.admin))]
+    pub fn set_liquidation_penalties(
+        ctx: Context<AdminAction>,
+        penalty_to_exchange: u8,
+        penalty_to_liquidator: u8,
+    ) -> Result<()> {
+        msg!("Synthetify:Admin: SET LIQUIDATION PENALTIES");
+        let state = &mut ctx.accounts.state.load_mut()?;
+
+        state.penalty_to_exchange = penalty_to_exchange;
+        state.penalty_to_liquidator = penalty_to_liquidator;
+
+        Ok(())
+    }
+    #[access_control(admin(&ctx.accounts.state, &ctx.accounts.admin))]
+    pub fn add_collateral(
+        ctx: Context<AddCollateral>,
+        reserve_balance: u64,
+        decimals: u8,
+        collateral_ratio: u8,
+    ) -> Result<()> {
+        msg!("Synthetify:Admin: ADD COLLATERAL");
+        let state = ctx.accounts.state.load()?;
+        let mut assets_list = ctx.accounts.assets_list.load_mut()?;
+
+        // Make sure feed is valid price account
+        load_price_feed(&ctx.accounts.feed_address, &state.oracle_program)?;
+
+        let asset_index = match assets_list
+            .assets
+            .iter_mut()
+            .position(|x| x.feed_address == *ctx.accounts.feed_address.key)
+        {
+            Some(asset) => asset,
             None => return Err(ErrorCode::NoAssetFound.into()),
         };
         let new_collateral = Collateral {
@@ -1117,7 +1901,9 @@ pub mod exchange {
     }
     #[access_control(admin(&ctx.accounts.state, &ctx.accounts.admin))]
     pub fn add_synthetic(ctx: Context<AddSynthetic>, max_supply: u64, decimals: u8) -> Result<()> {
+        let state = ctx.accounts.state.load()?;
         let mut assets_list = ctx.accounts.assets_list.load_mut()?;
+
         let asset_index = match assets_list
             .assets
             .iter_mut()
@@ -1126,18 +1912,61 @@ pub mod exchange {
             Some(asset) => asset,
             None => return Err(ErrorCode::NoAssetFound.into()),
         };
+        // Make sure feed is valid price account, derived asset is identified by its address
+        if assets_list.assets[asset_index].asset_kind == ASSET_KIND_FEED {
+            load_price_feed(&ctx.accounts.feed_address, &state.oracle_program)?;
+        }
         let new_synthetic = Synthetic {
             asset_index: asset_index as u8,
             decimals: decimals,
             asset_address: *ctx.accounts.asset_address.key,
             max_supply: max_supply,
             settlement_slot: u64::MAX,
+            settlement_price: 0,
             supply: 0,
         };
         assets_list.append_synthetic(new_synthetic);
         Ok(())
     }
 }
+#[zero_copy]
+#[derive(PartialEq, Default, Debug)]
+pub struct PriceEntry {
+    pub slot: u64,       // 8 Oracle publish slot
+    pub price: u64,      // 8
+    pub confidence: u32, // 4
+}
+pub const PRICE_HISTORY_SIZE: usize = 256;
+#[account(zero_copy)]
+pub struct PriceHistory {
+    pub feed_address: Pubkey,       // 32 Asset of history
+    pub head: u16,                  // 2 Index of next entry
+    pub count: u16,                 // 2 Number of stored entries
+    pub entries: [PriceEntry; 256], // 5120 Ring buffer of prices
+}
+impl PriceHistory {
+    fn append(&mut self, entry: PriceEntry) {
+        self.entries[self.head as usize] = entry;
+        self.head = ((self.head as usize + 1) % PRICE_HISTORY_SIZE) as u16;
+        self.count = (self.count as usize + 1).min(PRICE_HISTORY_SIZE) as u16;
+    }
+    // Entries from oldest to newest
+    pub fn ordered_entries(&self) -> Vec<PriceEntry> {
+        let count = self.count as usize;
+        let start = (self.head as usize + PRICE_HISTORY_SIZE - count) % PRICE_HISTORY_SIZE;
+        (0..count)
+            .map(|offset| self.entries[(start + offset) % PRICE_HISTORY_SIZE])
+            .collect()
+    }
+    pub fn last_entry(&self) -> Option<PriceEntry> {
+        match self.count {
+            0 => None,
+            _ => Some(
+                self.entries[(self.head as usize + PRICE_HISTORY_SIZE - 1) % PRICE_HISTORY_SIZE],
+            ),
+        }
+    }
+}
 #[account(zero_copy)]
 #[derive(Default)]
 pub struct AssetsList {
@@ -1183,6 +2012,23 @@ pub struct CreateAssetsList<'info> {
     pub rent: Sysvar<'info, Rent>,
 }
 #[derive(Accounts)]
+pub struct CreatePriceHistory<'info> {
+    #[account(init)]
+    pub price_history: Loader<'info, PriceHistory>,
+    pub rent: Sysvar<'info, Rent>,
+}
+#[derive(Accounts)]
+pub struct SetPriceHistory<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+    #[account(mut)]
+    pub price_history: Loader<'info, PriceHistory>,
+}
+#[derive(Accounts)]
 pub struct InitializeAssetsList<'info> {
     #[account(mut)]
     pub assets_list: Loader<'info, AssetsList>,
@@ -1191,12 +2037,61 @@ pub struct InitializeAssetsList<'info> {
 }
 #[derive(Accounts)]
 pub struct SetAssetsPrices<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
     #[account(mut)]
     pub assets_list: Loader<'info, AssetsList>,
 }
 #[derive(Accounts)]
+pub struct RefreshDebt<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    pub assets_list: Loader<'info, AssetsList>,
+}
+#[derive(Accounts)]
+pub struct AccrueStabilityFee<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+}
+#[derive(Accounts)]
+pub struct SetStabilityFee<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+}
+#[derive(Accounts)]
+pub struct WithdrawStabilityFee<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    pub exchange_authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+    #[account(mut)]
+    pub usd_token: AccountInfo<'info>,
+    #[account(mut)]
+    pub to: CpiAccount<'info, TokenAccount>,
+    #[account("token_program.key == &token::ID")]
+    pub token_program: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct SetStabilityFeeVault<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+    pub assets_list: Loader<'info, AssetsList>,
+    pub stability_fee_vault: CpiAccount<'info, TokenAccount>,
+}
+#[derive(Accounts)]
 pub struct AddNewAsset<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(signer)]
     pub signer: AccountInfo<'info>,
@@ -1205,7 +2100,81 @@ pub struct AddNewAsset<'info> {
 }
 #[derive(Accounts)]
 pub struct SetMaxSupply<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+}
+#[derive(Accounts)]
+pub struct SetSettlementSlot<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+}
+#[derive(Accounts)]
+pub struct SettleSynthetic<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    pub exchange_authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+    #[account("token_program.key == &token::ID")]
+    pub token_program: AccountInfo<'info>,
+    #[account(mut)]
+    pub settled_token: AccountInfo<'info>,
+    #[account(mut)]
+    pub usd_token: AccountInfo<'info>,
+    #[account(mut)]
+    pub user_settled_token_account: CpiAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user_usd_token_account: AccountInfo<'info>,
+    #[account(signer)]
+    pub owner: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct SetMaxConfidence<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+}
+#[derive(Accounts)]
+pub struct SetConfidenceMultiplier<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+}
+#[derive(Accounts)]
+pub struct SetInversePriceBand<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+}
+#[derive(Accounts)]
+pub struct SetAssetMaxDelay<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+}
+#[derive(Accounts)]
+pub struct SetPriceMode<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(signer)]
     pub signer: AccountInfo<'info>,
@@ -1213,8 +2182,37 @@ pub struct SetMaxSupply<'info> {
     pub assets_list: Loader<'info, AssetsList>,
 }
 #[derive(Accounts)]
+pub struct SetSecondaryFeed<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+    pub secondary_feed: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct SetPriceBreaker<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+}
+#[derive(Accounts)]
+pub struct ResetPriceBreaker<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+    pub price_feed: AccountInfo<'info>,
+}
+#[derive(Accounts)]
 pub struct SetPriceFeed<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(signer)]
     pub signer: AccountInfo<'info>,
@@ -1224,7 +2222,7 @@ pub struct SetPriceFeed<'info> {
 }
 #[derive(Accounts)]
 pub struct AddCollateral<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(signer)]
     pub admin: AccountInfo<'info>,
@@ -1237,7 +2235,7 @@ pub struct AddCollateral<'info> {
 }
 #[derive(Accounts)]
 pub struct AddSynthetic<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(signer)]
     pub admin: AccountInfo<'info>,
@@ -1256,7 +2254,7 @@ pub struct New<'info> {
 #[derive(Accounts)]
 #[instruction(bump: u8)]
 pub struct CreateExchangeAccount<'info> {
-    #[account(init,seeds = [b"accountv1", admin.key.as_ref(), &[bump]], payer=payer )]
+    #[account(init,seeds = [b"accountv2", admin.key.as_ref(), &[bump]], payer=payer )]
     pub exchange_account: Loader<'info, ExchangeAccount>,
     pub admin: AccountInfo<'info>,
     #[account(mut, signer)]
@@ -1265,12 +2263,27 @@ pub struct CreateExchangeAccount<'info> {
     pub system_program: AccountInfo<'info>,
 }
 
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct MigrateExchangeAccount<'info> {
+    #[account(init,seeds = [b"accountv2", owner.key.as_ref(), &[bump]], payer=payer )]
+    pub exchange_account: Loader<'info, ExchangeAccount>,
+    #[account(mut)]
+    pub legacy_exchange_account: AccountInfo<'info>,
+    #[account(signer)]
+    pub owner: AccountInfo<'info>,
+    #[account(mut, signer)]
+    pub payer: AccountInfo<'info>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: AccountInfo<'info>,
+}
+
 #[associated(zero_copy)]
 #[derive(PartialEq, Default, Debug)]
 pub struct ExchangeAccount {
     pub owner: Pubkey,                  // Identity controling account
     pub version: u8,                    // Version of account struct
-    pub debt_shares: u64,               // Shares representing part of entire debt pool
+    pub debt_shares: u128,              // Shares representing part of entire debt pool
     pub liquidation_deadline: u64,      // Slot number after which account can be liquidated
     pub user_staking_data: UserStaking, // Staking information
     pub head: u8,
@@ -1299,7 +2312,7 @@ impl ExchangeAccount {
 }
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(mut)]
     pub assets_list: Loader<'info, AssetsList>,
@@ -1328,13 +2341,13 @@ impl<'a, 'b, 'c, 'info> From<&Withdraw<'info>> for CpiContext<'a, 'b, 'c, 'info,
 }
 #[derive(Accounts)]
 pub struct Mint<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(mut)]
     pub assets_list: Loader<'info, AssetsList>,
     pub exchange_authority: AccountInfo<'info>,
     #[account(mut)]
-    pub usd_token: AccountInfo<'info>,
+    pub synthetic_token: AccountInfo<'info>,
     #[account(mut)]
     pub to: AccountInfo<'info>,
     #[account("token_program.key == &token::ID")]
@@ -1347,7 +2360,7 @@ pub struct Mint<'info> {
 impl<'a, 'b, 'c, 'info> From<&Mint<'info>> for CpiContext<'a, 'b, 'c, 'info, MintTo<'info>> {
     fn from(accounts: &Mint<'info>) -> CpiContext<'a, 'b, 'c, 'info, MintTo<'info>> {
         let cpi_accounts = MintTo {
-            mint: accounts.usd_token.to_account_info(),
+            mint: accounts.synthetic_token.to_account_info(),
             to: accounts.to.to_account_info(),
             authority: accounts.exchange_authority.to_account_info(),
         };
@@ -1357,7 +2370,7 @@ impl<'a, 'b, 'c, 'info> From<&Mint<'info>> for CpiContext<'a, 'b, 'c, 'info, Min
 }
 #[derive(Accounts)]
 pub struct Deposit<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(mut)]
     pub exchange_account: Loader<'info, ExchangeAccount>,
@@ -1387,7 +2400,7 @@ impl<'a, 'b, 'c, 'info> From<&Deposit<'info>> for CpiContext<'a, 'b, 'c, 'info,
 }
 #[derive(Accounts)]
 pub struct Liquidate<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     pub exchange_authority: AccountInfo<'info>,
     #[account(mut)]
@@ -1411,7 +2424,7 @@ pub struct Liquidate<'info> {
 }
 #[derive(Accounts)]
 pub struct BurnToken<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     pub exchange_authority: AccountInfo<'info>,
     #[account(mut)]
@@ -1419,7 +2432,7 @@ pub struct BurnToken<'info> {
     #[account("token_program.key == &token::ID")]
     pub token_program: AccountInfo<'info>,
     #[account(mut)]
-    pub usd_token: AccountInfo<'info>,
+    pub synthetic_token: AccountInfo<'info>,
     #[account(mut)]
     pub user_token_account_burn: CpiAccount<'info, TokenAccount>,
     #[account(mut, has_one = owner)]
@@ -1430,7 +2443,7 @@ pub struct BurnToken<'info> {
 impl<'a, 'b, 'c, 'info> From<&BurnToken<'info>> for CpiContext<'a, 'b, 'c, 'info, Burn<'info>> {
     fn from(accounts: &BurnToken<'info>) -> CpiContext<'a, 'b, 'c, 'info, Burn<'info>> {
         let cpi_accounts = Burn {
-            mint: accounts.usd_token.to_account_info(),
+            mint: accounts.synthetic_token.to_account_info(),
             to: accounts.user_token_account_burn.to_account_info(),
             authority: accounts.exchange_authority.to_account_info(),
         };
@@ -1440,7 +2453,7 @@ impl<'a, 'b, 'c, 'info> From<&BurnToken<'info>> for CpiContext<'a, 'b, 'c, 'info
 }
 #[derive(Accounts)]
 pub struct Swap<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     pub exchange_authority: AccountInfo<'info>,
     #[account(mut)]
@@ -1485,22 +2498,23 @@ impl<'a, 'b, 'c, 'info> From<&Swap<'info>> for CpiContext<'a, 'b, 'c, 'info, Min
 
 #[derive(Accounts)]
 pub struct CheckCollateralization<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(mut)]
     pub exchange_account: Loader<'info, ExchangeAccount>,
+    #[account(mut)]
     pub assets_list: Loader<'info, AssetsList>,
 }
 #[derive(Accounts)]
 pub struct ClaimRewards<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(mut)]
     pub exchange_account: Loader<'info, ExchangeAccount>,
 }
 #[derive(Accounts)]
 pub struct WithdrawRewards<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(mut, has_one = owner)]
     pub exchange_account: Loader<'info, ExchangeAccount>,
@@ -1515,8 +2529,39 @@ pub struct WithdrawRewards<'info> {
     pub staking_fund_account: CpiAccount<'info, TokenAccount>,
 }
 #[derive(Accounts)]
+pub struct CrankAssetsPrices<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(mut)]
+    pub assets_list: Loader<'info, AssetsList>,
+    pub exchange_authority: AccountInfo<'info>,
+    #[account("token_program.key == &token::ID")]
+    pub token_program: AccountInfo<'info>,
+    #[account(mut)]
+    pub crank_fund_account: CpiAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub to: CpiAccount<'info, TokenAccount>,
+}
+#[derive(Accounts)]
+pub struct SetOracleProgram<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+    #[account(executable)]
+    pub oracle_program: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct SetCrankFund<'info> {
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
+    pub state: Loader<'info, State>,
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+    pub crank_fund_account: CpiAccount<'info, TokenAccount>,
+}
+#[derive(Accounts)]
 pub struct WithdrawLiquidationPenalty<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(signer)]
     pub admin: AccountInfo<'info>,
@@ -1532,7 +2577,7 @@ pub struct WithdrawLiquidationPenalty<'info> {
 }
 #[derive(Accounts)]
 pub struct AdminAction<'info> {
-    #[account(mut, seeds = [b"statev1".as_ref(), &[state.load()?.bump]])]
+    #[account(mut, seeds = [b"statev2".as_ref(), &[state.load()?.bump]])]
     pub state: Loader<'info, State>,
     #[account(signer)]
     pub admin: AccountInfo<'info>,
@@ -1540,9 +2585,19 @@ pub struct AdminAction<'info> {
 #[zero_copy]
 #[derive(PartialEq, Default, Debug)]
 pub struct StakingRound {
-    pub start: u64,      // 8 Slot when round starts
-    pub amount: u64,     // 8 Amount of SNY distributed in this round
-    pub all_points: u64, // 8 All points used to calculate user share in staking rewards
+    pub start: u64,       // 8 Slot when round starts
+    pub amount: u64,      // 8 Amount of SNY distributed in this round
+    pub all_points: u128, // 16 All points used to calculate user share in staking rewards
+}
+#[zero_copy]
+#[derive(PartialEq, Default, Debug)]
+pub struct Crank {
+    pub fund_account: Pubkey,     //32 Source account of crank rewards
+    pub reward: u64,              //8 Reward per refreshed asset
+    pub min_age: u32,             //4 Only assets older than min_age slots are rewarded
+    pub max_rewards_per_slot: u8, //1
+    pub last_slot: u64,           //8 Slot of last rewarded update
+    pub rewards_in_slot: u8,      //1 Rewarded updates in last_slot
 }
 #[zero_copy]
 #[derive(PartialEq, Default, Debug)]
@@ -1550,26 +2605,62 @@ pub struct Staking {
     pub fund_account: Pubkey,         //32 Source account of SNY tokens
     pub round_length: u32,            //4 Length of round in slots
     pub amount_per_round: u64,        //8 Amount of SNY distributed per round
-    pub finished_round: StakingRound, //24
-    pub current_round: StakingRound,  //24
-    pub next_round: StakingRound,     //24
+    pub finished_round: StakingRound, //32
+    pub current_round: StakingRound,  //32
+    pub next_round: StakingRound,     //32
 }
 #[zero_copy]
 #[derive(PartialEq, Default, Debug)]
 pub struct UserStaking {
-    pub amount_to_claim: u64,       //8 Amount of SNY accumulated by account
-    pub finished_round_points: u64, //8 Points are based on debt_shares in specific round
-    pub current_round_points: u64,  //8
-    pub next_round_points: u64,     //8
-    pub last_update: u64,           //8
+    pub amount_to_claim: u64,        //8 Amount of SNY accumulated by account
+    pub finished_round_points: u128, //16 Points are based on debt_shares in specific round
+    pub current_round_points: u128,  //16
+    pub next_round_points: u128,     //16
+    pub last_update: u64,            //8
 }
+// Asset.price_mode values
+pub const PRICE_MODE_SPOT: u8 = 0;
+pub const PRICE_MODE_TWAP: u8 = 1;
+// lower of spot and twap for collateral, higher for debt
+pub const PRICE_MODE_CONSERVATIVE: u8 = 2;
+// Asset.price_source values
+pub const PRICE_SOURCE_PRIMARY: u8 = 0;
+pub const PRICE_SOURCE_SECONDARY: u8 = 1;
+pub const ASSET_KIND_FEED: u8 = 0; // Price of feed_address
+pub const ASSET_KIND_PRODUCT: u8 = 1; // input0 * input1
+pub const ASSET_KIND_RATIO: u8 = 2; // input0 / input1
+pub const ASSET_KIND_INVERSE: u8 = 3; // 1 / input0
+pub const ASSET_KIND_BASKET: u8 = 4; // Sum of weight * input
+pub const ASSET_KIND_INVERSE_SYNTHETIC: u8 = 5; // 2 * entry_price - input0 within price band
+pub const DERIVED_MAX_INPUTS: usize = 4;
 #[zero_copy]
 #[derive(PartialEq, Default, Debug)]
 pub struct Asset {
-    pub feed_address: Pubkey, // 32 Pyth oracle account address
-    pub price: u64,           // 8
-    pub last_update: u64,     // 8
-    pub confidence: u32,      // 4 unused
+    pub feed_address: Pubkey,           // 32 Pyth oracle account address
+    pub price: u64,                     // 8
+    pub twap: u64,                      // 8 Time-weighted average price
+    pub price_mode: u8,                 // 1 Price used to value asset (spot, twap, conservative)
+    pub last_update: u64,               // 8
+    pub confidence: u32,                // 4 Relative to price, 10000 -> 1%
+    pub max_confidence: u32,            // 4 Confidence above which price cannot be used
+    pub frozen: bool,                   // 1 Price feed is not trading
+    pub secondary_feed_address: Pubkey, // 32 Fallback oracle account address
+    pub max_divergence: u32,            // 4 Max deviation of fallback from primary price
+    pub price_source: u8,               // 1 Feed used in last price update
+    pub max_price_change: u32,          // 4 Max price move per update, 10000 -> 1%
+    pub breaker_cooldown: u64,          // 8 Slots before price in limit clears breaker, 0 -> admin
+    pub breaker_slot: u64,              // 8 Slot in which breaker was triggered
+    pub in_breaker: bool,               // 1 Price jump exceeded max_price_change
+    pub last_crank: u64,                // 8 Slot of last rewarded crank update
+    pub max_delay: u32,                 // 4 Staleness tolerance, 0 uses State.max_delay
+    pub confidence_multiplier: u16,     // 2 Value at price -/+ k*conf, 100 -> k = 1, 0 -> mid price
+    pub asset_kind: u8,                 // 1 Formula of price, see ASSET_KIND_*
+    pub input_feeds: [Pubkey; 4],       // 128 Feeds of derived price
+    pub input_weights: [u64; 4],        // 32 Basket weights, 10^6 -> 1
+    pub entry_price: u64,               // 8 Inverse synthetic entry price
+    pub lower_bound: u64,               // 8 Inverse synthetic settles at or below
+    pub upper_bound: u64,               // 8 Inverse synthetic settles at or above
+    pub price_history: Pubkey,          // 32 Optional PriceHistory account
 }
 #[zero_copy]
 #[derive(PartialEq, Default, Debug)]
@@ -1590,7 +2681,8 @@ pub struct Synthetic {
     pub supply: u64,           // 8
     pub decimals: u8,          // 1
     pub max_supply: u64,       // 8
-    pub settlement_slot: u64,  // 8 unused
+    pub settlement_slot: u64,  // 8 Since then only swaps out and settling allowed
+    pub settlement_price: u64, // 8 Conversion price frozen at settlement, 0 until fresh price
 }
 #[account(zero_copy)]
 #[derive(PartialEq, Default, Debug)]
@@ -1599,7 +2691,7 @@ pub struct State {
     pub admin: Pubkey,             //32
     pub halted: bool,              //1
     pub nonce: u8,                 //1
-    pub debt_shares: u64,          //8
+    pub debt_shares: u128,         //16
     pub assets_list: Pubkey,       //32
     pub health_factor: u8,         //1   In % 1-100% modifier for debt
     pub max_delay: u32,            //4   Delay bettwen last oracle update 100 blocks ~ 1 min
@@ -1609,21 +2701,57 @@ pub struct State {
     pub penalty_to_exchange: u8,   //1   In % range 0-25%
     pub liquidation_buffer: u32,   //4   Time given user to fix collateralization ratio
     pub account_version: u8,       //1 Version of account supported by program
-    pub staking: Staking,          //116
+    pub staking: Staking,          //140
     pub bump: u8,
+    pub oracle_program: Pubkey,         //32 Program owning price feeds
+    pub crank: Crank,                   //54
+    pub debt: u128,                     //16 Cached debt of all synthetics in usd
+    pub debt_ceiling: u64,              //8  Max debt of all synthetics in usd
+    pub min_account_debt: u64,          //8  Smaller non zero debt of account is dust
+    pub max_account_debt: u64,          //8  Max debt of single account in usd
+    pub stability_fee: u32,             //4  Annual interest on debt, 100000 -> 100%
+    pub stability_fee_index: u128,      //16 Compounded growth of debt by fee, 10^18 -> 1
+    pub stability_fee_slot: u64,        //8  Slot of last fee accrual
+    pub accumulated_stability_fee: u64, //8  Accrued xUSD not yet withdrawn to fee vault
+    pub stability_fee_vault: Pubkey,    //32 xUSD account receiving withdrawn fee
 }
 #[derive(Accounts)]
 #[instruction(bump: u8)]
 pub struct Init<'info> {
-    #[account(init, seeds = [b"statev1".as_ref(), &[bump]], payer = payer)]
+    #[account(init, seeds = [b"statev2".as_ref(), &[bump]], payer = payer)]
     pub state: Loader<'info, State>,
     pub payer: AccountInfo<'info>,
     pub admin: AccountInfo<'info>,
     pub assets_list: AccountInfo<'info>,
     pub staking_fund_account: CpiAccount<'info, TokenAccount>,
+    pub oracle_program: AccountInfo<'info>,
+    pub legacy_state: AccountInfo<'info>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct MigrateState<'info> {
+    #[account(init, seeds = [b"statev2".as_ref(), &[bump]], payer = payer)]
+    pub state: Loader<'info, State>,
+    #[account(mut)]
+    pub legacy_state: AccountInfo<'info>,
+    #[account(init)]
+    pub assets_list: Loader<'info, AssetsList>,
+    #[account(mut)]
+    pub legacy_assets_list: AccountInfo<'info>,
+    #[account(signer)]
+    pub admin: AccountInfo<'info>,
+    pub oracle_program: AccountInfo<'info>,
+    #[account(mut, signer)]
+    pub payer: AccountInfo<'info>,
     pub rent: Sysvar<'info, Rent>,
     pub system_program: AccountInfo<'info>,
 }
+#[event]
+pub struct SkippedPriceFeeds {
+    pub feeds: Vec<Pubkey>, // Feeds and derived assets left unchanged by invalid price
+}
 
 #[error]
 pub enum ErrorCode {
@@ -1673,6 +2801,50 @@ pub enum ErrorCode {
     NotCollateral,
     #[msg("Asset is already a collateral")]
     AlreadyACollateral,
+    #[msg("Oracle price is too uncertain")]
+    UncertainOracle,
+    #[msg("Asset is frozen")]
+    FrozenAsset,
+    #[msg("Price feed is not owned by oracle program")]
+    InvalidOracleProgram,
+    #[msg("Invalid price feed account")]
+    InvalidOracleAccount,
+    #[msg("Invalid price feed type")]
+    InvalidPriceType,
+    #[msg("Invalid price mode")]
+    InvalidPriceMode,
+    #[msg("Asset price is in circuit breaker")]
+    PriceBreaker,
+    #[msg("Oracle price is negative")]
+    NegativePrice,
+    #[msg("Oracle price is zero")]
+    ZeroPrice,
+    #[msg("Oracle price is out of range")]
+    PriceOverflow,
+    #[msg("Invalid asset kind or inputs")]
+    InvalidAssetKind,
+    #[msg("Invalid price band")]
+    InvalidPriceBand,
+    #[msg("Synthetic is settled")]
+    SettledSynthetic,
+    #[msg("Debt ceiling reached")]
+    DebtCeiling,
+    #[msg("Account debt below minimum")]
+    AccountDebtDust,
+    #[msg("Account debt above maximum")]
+    AccountDebtLimit,
+    #[msg("Invalid account debt limits")]
+    InvalidAccountDebtLimits,
+    #[msg("Invalid settlement of synthetic")]
+    InvalidSettlement,
+    #[msg("Synthetic is not settled")]
+    NotSettledSynthetic,
+    #[msg("Invalid legacy account")]
+    InvalidLegacyAccount,
+    #[msg("Invalid stability fee")]
+    InvalidStabilityFee,
+    #[msg("State of previous version has to be migrated")]
+    LegacyState,
 }
 
 // Access control modifiers.
@@ -1729,6 +2901,19 @@ fn fund_account<'info>(
     );
     Ok(())
 }
+// Assert fee is withdrawn only to stability fee vault
+fn stability_fee_vault<'info>(
+    state_loader: &Loader<State>,
+    vault: &CpiAccount<'info, TokenAccount>,
+) -> Result<()> {
+    let state = state_loader.load()?;
+
+    require!(
+        vault.to_account_info().key.eq(&state.stability_fee_vault),
+        FundAccountError
+    );
+    Ok(())
+}
 // Check is user account have correct version
 fn version<'info>(
     state_loader: &Loader<State>,
@@ -1,32 +1,67 @@
-use std::{cell::RefMut, convert::TryInto};
+use std::convert::TryInto;
 
+use crate::utils::{is_price_outdated, is_settlement_priced};
 use crate::*;
 
 // Min decimals for asset = 6
 pub const ACCURACY: u8 = 6;
 pub const PRICE_OFFSET: u8 = 6;
+// Basket weight 10^6 -> 1 unit of input
+pub const WEIGHT_OFFSET: u8 = 6;
+// Stability fee index 10^18 -> 1
+pub const INTEREST_OFFSET: u8 = 18;
+// About 0.5s per slot
+pub const SLOTS_PER_YEAR: u64 = 63_072_000;
 
-pub fn calculate_debt(assets_list: &RefMut<AssetsList>, slot: u64, max_delay: u32) -> Result<u64> {
-    let mut debt = 0u128;
+pub fn calculate_debt(assets_list: &AssetsList, slot: u64, max_delay: u32) -> Result<u128> {
     let synthetics = &assets_list.synthetics;
     let head = assets_list.head_synthetics as usize;
     for synthetic in synthetics[..head].iter() {
         let asset = &assets_list.assets[synthetic.asset_index as usize];
-        if asset.last_update < (slot - max_delay as u64) {
+        if !is_settlement_priced(synthetic) && is_price_outdated(asset, max_delay, slot) {
             return Err(ErrorCode::OutdatedOracle.into());
         }
-
-        // rounding up to be sure that debt is not less than minted tokens
-        debt += div_up(
-            (asset.price as u128)
-                .checked_mul(synthetic.supply as u128)
-                .unwrap(),
-            10u128
-                .checked_pow((synthetic.decimals + PRICE_OFFSET - ACCURACY).into())
-                .unwrap(),
-        );
     }
-    Ok(debt as u64)
+    Ok(calculate_stored_debt(assets_list))
+}
+// Debt at last stored prices, regardless of their age
+pub fn calculate_stored_debt(assets_list: &AssetsList) -> u128 {
+    let synthetics = &assets_list.synthetics;
+    let head = assets_list.head_synthetics as usize;
+    synthetics[..head].iter().fold(0u128, |debt, synthetic| {
+        let asset = &assets_list.assets[synthetic.asset_index as usize];
+        debt.checked_add(calculate_synthetic_debt(asset, synthetic))
+            .unwrap()
+    })
+}
+pub fn calculate_synthetic_debt(asset: &Asset, synthetic: &Synthetic) -> u128 {
+    calculate_debt_value(asset, synthetic, synthetic.supply)
+}
+// Debt of synthetics priced by asset
+pub fn calculate_asset_debt(asset: &Asset, asset_index: usize, synthetics: &[Synthetic]) -> u128 {
+    synthetics
+        .iter()
+        .filter(|x| x.asset_index as usize == asset_index)
+        .fold(0u128, |debt, synthetic| {
+            debt.checked_add(calculate_synthetic_debt(asset, synthetic))
+                .unwrap()
+        })
+}
+pub fn calculate_debt_in_usd(asset: &Asset, synthetic: &Synthetic, amount: u64) -> u64 {
+    calculate_debt_value(asset, synthetic, amount)
+        .try_into()
+        .unwrap()
+}
+fn calculate_debt_value(asset: &Asset, synthetic: &Synthetic, amount: u64) -> u128 {
+    // rounding up to be sure that debt is not less than minted tokens
+    div_up(
+        (synthetic_debt_price(asset, synthetic) as u128)
+            .checked_mul(amount as u128)
+            .unwrap(),
+        10u128
+            .checked_pow((synthetic.decimals + PRICE_OFFSET - ACCURACY).into())
+            .unwrap(),
+    )
 }
 pub fn calculate_max_debt_in_usd(account: &ExchangeAccount, assets_list: &AssetsList) -> u128 {
     let mut max_debt = 0u128;
@@ -35,7 +70,7 @@ pub fn calculate_max_debt_in_usd(account: &ExchangeAccount, assets_list: &Assets
         let collateral = &assets_list.collaterals[collateral_entry.index as usize];
         let asset = &assets_list.assets[collateral.asset_index as usize];
         // rounding up to be sure that debt is not less than minted tokens
-        max_debt += (asset.price as u128)
+        max_debt += (collateral_price(asset) as u128)
             .checked_mul(collateral_entry.amount as u128)
             .unwrap()
             .checked_mul(collateral.collateral_ratio.into())
@@ -53,55 +88,47 @@ pub fn calculate_max_debt_in_usd(account: &ExchangeAccount, assets_list: &Assets
 }
 pub fn calculate_user_debt_in_usd(
     user_account: &ExchangeAccount,
-    debt: u64,
-    debt_shares: u64,
+    debt: u128,
+    debt_shares: u128,
 ) -> u64 {
     if debt_shares == 0 {
         return 0;
     }
     // rounding up to be sure that user debt is not less than user minted tokens
     let user_debt = div_up(
-        (debt as u128)
-            .checked_mul(user_account.debt_shares as u128)
-            .unwrap(),
-        debt_shares as u128,
+        debt.checked_mul(user_account.debt_shares).unwrap(),
+        debt_shares,
     );
-    return user_debt as u64;
+    user_debt.try_into().unwrap()
 }
 pub fn calculate_new_shares_by_rounding_down(
-    all_shares: u64,
-    full_amount: u64,
+    all_shares: u128,
+    full_amount: u128,
     new_amount: u64,
-) -> u64 {
+) -> u128 {
     //  full_amount is always != 0 if all_shares > 0
-    if all_shares == 0u64 {
-        return new_amount;
+    if all_shares == 0u128 {
+        return new_amount.into();
     }
-    let new_shares = (all_shares as u128)
+    all_shares
         .checked_mul(new_amount as u128)
         .unwrap()
-        .checked_div(full_amount as u128)
-        .unwrap();
-
-    return new_shares.try_into().unwrap();
+        .checked_div(full_amount)
+        .unwrap()
 }
 pub fn calculate_new_shares_by_rounding_up(
-    all_shares: u64,
-    full_amount: u64,
+    all_shares: u128,
+    full_amount: u128,
     new_amount: u64,
-) -> u64 {
+) -> u128 {
     //  full_amount is always != 0 if all_shares > 0
-    if all_shares == 0u64 {
-        return new_amount;
+    if all_shares == 0u128 {
+        return new_amount.into();
     }
-    let new_shares = div_up(
-        (all_shares as u128)
-            .checked_mul(new_amount as u128)
-            .unwrap(),
-        full_amount as u128,
-    );
-
-    return new_shares.try_into().unwrap();
+    div_up(
+        all_shares.checked_mul(new_amount as u128).unwrap(),
+        full_amount,
+    )
 }
 pub fn calculate_max_withdraw_in_usd(
     max_user_debt_in_usd: u64,
@@ -120,28 +147,26 @@ pub fn calculate_max_withdraw_in_usd(
         .checked_div(health_factor.into())
         .unwrap();
 }
-pub fn amount_to_shares_by_rounding_down(all_shares: u64, full_amount: u64, amount: u64) -> u64 {
+pub fn amount_to_shares_by_rounding_down(all_shares: u128, full_amount: u128, amount: u64) -> u128 {
     // full_amount is always != 0 if all_shares > 0
     if all_shares == 0 {
         return 0;
     }
-    let shares = (amount as u128)
-        .checked_mul(all_shares as u128)
+    (amount as u128)
+        .checked_mul(all_shares)
+        .unwrap()
+        .checked_div(full_amount)
         .unwrap()
-        .checked_div(full_amount as u128)
-        .unwrap();
-    return shares.try_into().unwrap();
 }
-pub fn amount_to_shares_by_rounding_up(all_shares: u64, full_amount: u64, amount: u64) -> u64 {
+pub fn amount_to_shares_by_rounding_up(all_shares: u128, full_amount: u128, amount: u64) -> u128 {
     // full_amount is always != 0 if all_shares > 0
     if all_shares == 0 {
         return 0;
     }
-    let shares = div_up(
-        (amount as u128).checked_mul(all_shares as u128).unwrap(),
-        full_amount as u128,
-    );
-    return shares.try_into().unwrap();
+    div_up(
+        (amount as u128).checked_mul(all_shares).unwrap(),
+        full_amount,
+    )
 }
 pub fn amount_to_discount(amount: u64) -> u8 {
     // decimals of token = 6
@@ -166,10 +191,10 @@ pub fn calculate_swap_out_amount(
     fee: u32, // in range from 0-99 | 30/10000 => 0.3% fee
 ) -> u64 {
     // maybe we should calculate decimal difference first not to lose precision
-    let amount_before_fee = (asset_in.price as u128)
+    let amount_before_fee = (synthetic_price(asset_in, synthetic_in) as u128)
         .checked_mul(amount as u128)
         .unwrap()
-        .checked_div(asset_for.price as u128)
+        .checked_div(synthetic_price(asset_for, synthetic_for) as u128)
         .unwrap();
     let amount = amount_before_fee
         .checked_sub(
@@ -196,14 +221,24 @@ pub fn calculate_burned_shares(
     asset: &Asset,
     synthetic: &Synthetic,
     all_debt: u64,
-    all_shares: u64,
+    all_shares: u128,
     amount: u64,
-) -> u64 {
+) -> u128 {
     if all_debt == 0 {
-        return 0u64;
+        return 0u128;
     }
 
-    let burn_amount_in_usd = (asset.price as u128)
+    let burn_amount_in_usd = calculate_burned_in_usd(asset, synthetic, amount) as u128;
+    burn_amount_in_usd
+        .checked_mul(all_shares)
+        .unwrap()
+        .checked_div(all_debt as u128)
+        .unwrap()
+}
+
+// Rounding down - debt is burned in favor of the system
+pub fn calculate_burned_in_usd(asset: &Asset, synthetic: &Synthetic, amount: u64) -> u64 {
+    (synthetic_burn_price(asset, synthetic) as u128)
         .checked_mul(amount as u128)
         .unwrap()
         .checked_div(
@@ -211,28 +246,27 @@ pub fn calculate_burned_shares(
                 .checked_pow((synthetic.decimals + PRICE_OFFSET - ACCURACY).into())
                 .unwrap(),
         )
-        .unwrap();
-    let burned_shares = burn_amount_in_usd
-        .checked_mul(all_shares as u128)
         .unwrap()
-        .checked_div(all_debt as u128)
-        .unwrap();
-    return burned_shares.try_into().unwrap();
+        .try_into()
+        .unwrap()
 }
-
-// This should always retur user_debt if xusd === 1 USD
-// Should we remove this funtion ?
-pub fn calculate_max_burned_in_xusd(asset: &Asset, user_debt: u64) -> u64 {
+// Amount of synthetic worth whole user debt
+pub fn calculate_max_burned_in_token(asset: &Asset, synthetic: &Synthetic, user_debt: u64) -> u64 {
     // rounding up to be sure that burned amount is not less than user debt
     let burned_amount_token = div_up(
         (user_debt as u128)
-            .checked_mul(10u128.pow(PRICE_OFFSET.into()))
+            .checked_mul(
+                10u128
+                    .checked_pow((synthetic.decimals + PRICE_OFFSET - ACCURACY).into())
+                    .unwrap(),
+            )
             .unwrap(),
-        asset.price as u128,
+        synthetic_burn_price(asset, synthetic) as u128,
     );
     return burned_amount_token.try_into().unwrap();
 }
 pub fn usd_to_token_amount(asset: &Asset, collateral: &Collateral, amount: u64) -> u64 {
+    let price = collateral_price(asset);
     let decimal_difference = collateral.decimals as i32 - ACCURACY as i32;
     if decimal_difference < 0 {
         let amount = (amount as u128)
@@ -240,7 +274,7 @@ pub fn usd_to_token_amount(asset: &Asset, collateral: &Collateral, amount: u64)
             .unwrap()
             .checked_div(10u128.pow(decimal_difference.try_into().unwrap()))
             .unwrap()
-            .checked_div(asset.price as u128)
+            .checked_div(price as u128)
             .unwrap();
         return amount.try_into().unwrap();
     } else {
@@ -249,11 +283,86 @@ pub fn usd_to_token_amount(asset: &Asset, collateral: &Collateral, amount: u64)
             .unwrap()
             .checked_mul(10u128.pow(decimal_difference.try_into().unwrap()))
             .unwrap()
-            .checked_div(asset.price as u128)
+            .checked_div(price as u128)
             .unwrap();
         return amount.try_into().unwrap();
     }
 }
+// Price used to value asset as a collateral
+pub fn collateral_price(asset: &Asset) -> u64 {
+    let price = match asset.price_mode {
+        PRICE_MODE_TWAP => asset.twap,
+        PRICE_MODE_CONSERVATIVE => asset.price.min(asset.twap),
+        _ => asset.price,
+    };
+    // price - k * conf
+    price.saturating_sub(confidence_adjustment(asset, price))
+}
+// Price used to value asset as a debt
+pub fn debt_price(asset: &Asset) -> u64 {
+    let price = match asset.price_mode {
+        PRICE_MODE_TWAP => asset.twap,
+        PRICE_MODE_CONSERVATIVE => asset.price.max(asset.twap),
+        _ => asset.price,
+    };
+    // price + k * conf
+    price.saturating_add(confidence_adjustment(asset, price))
+}
+// Settled synthetic keeps price frozen at settlement
+pub fn synthetic_price(asset: &Asset, synthetic: &Synthetic) -> u64 {
+    match synthetic.settlement_price {
+        0 => asset.price,
+        settlement_price => settlement_price,
+    }
+}
+pub fn synthetic_debt_price(asset: &Asset, synthetic: &Synthetic) -> u64 {
+    match synthetic.settlement_price {
+        0 => debt_price(asset),
+        settlement_price => settlement_price,
+    }
+}
+// Burn repays no more than synthetic costs by mint (debt price) or by swap (spot),
+// so neither mint nor swap followed by burn can shift debt to other stakers
+pub fn synthetic_burn_price(asset: &Asset, synthetic: &Synthetic) -> u64 {
+    synthetic_price(asset, synthetic).min(synthetic_debt_price(asset, synthetic))
+}
+// k * conf, confidence_multiplier 100 -> k = 1
+pub fn confidence_adjustment(asset: &Asset, price: u64) -> u64 {
+    let adjustment = (price as u128)
+        .checked_mul(asset.confidence as u128)
+        .unwrap()
+        .checked_mul(asset.confidence_multiplier as u128)
+        .unwrap()
+        .checked_div(10u128.pow(CONFIDENCE_OFFSET.into()) * 100)
+        .unwrap();
+    adjustment.try_into().unwrap_or(u64::MAX)
+}
+// Scale oracle price to PRICE_OFFSET decimals
+pub fn scale_price(price: i64, expo: i32) -> Result<u64> {
+    if price < 0 {
+        return Err(ErrorCode::NegativePrice.into());
+    }
+    let offset = match (PRICE_OFFSET as i32).checked_add(expo) {
+        Some(offset) => offset,
+        None => return Err(ErrorCode::PriceOverflow.into()),
+    };
+    let scaled_price = if offset >= 0 {
+        10u128
+            .checked_pow(offset as u32)
+            .and_then(|scale| (price as u128).checked_mul(scale))
+    } else {
+        // scale above u128 range rounds price down to 0
+        Some(
+            10u128
+                .checked_pow((-offset) as u32)
+                .map_or(0, |scale| price as u128 / scale),
+        )
+    };
+    match scaled_price.and_then(|scaled_price| scaled_price.try_into().ok()) {
+        Some(scaled_price) => Ok(scaled_price),
+        None => Err(ErrorCode::PriceOverflow.into()),
+    }
+}
 pub const CONFIDENCE_OFFSET: u8 = 6u8;
 
 // confidence is in range 0 - 1000000
@@ -261,14 +370,205 @@ pub const CONFIDENCE_OFFSET: u8 = 6u8;
 // 100 -> 0.01% accuracy
 // 1000 -> 0.1% accuracy
 // 10000 -> 1% accuracy
-pub fn calculate_confidence(conf: u64, price: i64) -> u32 {
-    return (conf as u128)
+pub fn calculate_confidence(conf: u64, price: i64) -> Result<u32> {
+    if price < 0 {
+        return Err(ErrorCode::NegativePrice.into());
+    }
+    if price == 0 {
+        return Err(ErrorCode::ZeroPrice.into());
+    }
+    let confidence = (conf as u128)
+        .checked_mul(10u128.pow(CONFIDENCE_OFFSET.into()))
+        .unwrap()
+        .checked_div(price as u128)
+        .unwrap();
+    // confidence wider than u32 range is as bad as u32::MAX
+    Ok(confidence.try_into().unwrap_or(u32::MAX))
+}
+// Relative difference between prices, 10000 -> 1%
+pub fn calculate_price_divergence(reference_price: u64, price: u64) -> u32 {
+    if reference_price == 0 {
+        return 0;
+    }
+    let divergence = (price.abs_diff(reference_price) as u128)
         .checked_mul(10u128.pow(CONFIDENCE_OFFSET.into()))
         .unwrap()
-        .checked_div(price.try_into().unwrap())
+        .checked_div(reference_price as u128)
+        .unwrap();
+    divergence.try_into().unwrap_or(u32::MAX)
+}
+fn to_price(price: u128) -> Result<u64> {
+    match price.try_into() {
+        Ok(price) => Ok(price),
+        Err(_) => Err(ErrorCode::PriceOverflow.into()),
+    }
+}
+pub fn calculate_price_product(price_a: u64, price_b: u64) -> Result<u64> {
+    to_price(
+        (price_a as u128)
+            .checked_mul(price_b as u128)
+            .unwrap()
+            .checked_div(10u128.pow(PRICE_OFFSET.into()))
+            .unwrap(),
+    )
+}
+// Unknown denominator (twap not published yet) gives unknown price
+pub fn calculate_price_ratio(price_a: u64, price_b: u64) -> Result<u64> {
+    if price_b == 0 {
+        return Ok(0);
+    }
+    to_price(
+        (price_a as u128)
+            .checked_mul(10u128.pow(PRICE_OFFSET.into()))
+            .unwrap()
+            .checked_div(price_b as u128)
+            .unwrap(),
+    )
+}
+pub fn calculate_basket_price(prices: &[u64], weights: &[u64]) -> Result<u64> {
+    let value = prices
+        .iter()
+        .zip(weights.iter())
+        .fold(0u128, |acc, (price, weight)| {
+            acc.checked_add((*price as u128).checked_mul(*weight as u128).unwrap())
+                .unwrap()
+        });
+    to_price(value / 10u128.pow(WEIGHT_OFFSET.into()))
+}
+// Confidence of basket is value weighted confidence of inputs
+pub fn calculate_basket_confidence(prices: &[u64], confidences: &[u32], weights: &[u64]) -> u32 {
+    let mut value = 0u128;
+    let mut uncertainty = 0u128;
+    for ((price, confidence), weight) in prices.iter().zip(confidences.iter()).zip(weights.iter()) {
+        let input_value = (*price as u128).checked_mul(*weight as u128).unwrap();
+        value = value.checked_add(input_value).unwrap();
+        uncertainty = uncertainty
+            .checked_add(input_value.checked_mul(*confidence as u128).unwrap())
+            .unwrap();
+    }
+    if value == 0 {
+        return 0;
+    }
+    (uncertainty / value).try_into().unwrap_or(u32::MAX)
+}
+// 2 * entry - spot, kept within price band
+pub fn calculate_inverse_price(asset: &Asset, spot_price: u64) -> u64 {
+    let price = (asset.entry_price as u128)
+        .checked_mul(2)
         .unwrap()
-        .try_into()
+        .saturating_sub(spot_price as u128);
+    // upper_bound fits u64
+    price
+        .max(asset.lower_bound.into())
+        .min(asset.upper_bound.into()) as u64
+}
+// Absolute confidence of inverse price is the same as of spot price
+pub fn calculate_inverse_confidence(spot_price: u64, spot_confidence: u32, price: u64) -> u32 {
+    if price == 0 {
+        return u32::MAX;
+    }
+    let confidence = (spot_price as u128)
+        .checked_mul(spot_confidence as u128)
+        .unwrap()
+        .checked_div(price as u128)
         .unwrap();
+    confidence.try_into().unwrap_or(u32::MAX)
+}
+// Time weighted price over window, entries from oldest to newest
+pub fn calculate_history_twap(entries: &[PriceEntry], window: u64, slot: u64) -> Option<u64> {
+    let start = slot.saturating_sub(window);
+    let mut weighted_price = 0u128;
+    let mut duration = 0u128;
+    for (index, entry) in entries.iter().enumerate() {
+        // entry price holds until next entry
+        let end = entries.get(index + 1).map_or(slot, |next| next.slot);
+        let begin = entry.slot.max(start);
+        if end <= begin {
+            continue;
+        }
+        let period = (end - begin) as u128;
+        weighted_price = weighted_price
+            .checked_add((entry.price as u128).checked_mul(period).unwrap())
+            .unwrap();
+        duration += period;
+    }
+    match duration {
+        // last price was published in current slot
+        0 => entries.last().map(|entry| entry.price),
+        _ => Some((weighted_price / duration) as u64),
+    }
+}
+// Square root of sum of squared returns in window, 10000 -> 1%
+pub fn calculate_history_volatility(entries: &[PriceEntry], window: u64, slot: u64) -> Option<u32> {
+    let start = slot.saturating_sub(window);
+    let mut squared_returns = 0u128;
+    let mut returns = 0;
+    for pair in entries.windows(2) {
+        if pair[0].slot < start || pair[0].price == 0 {
+            continue;
+        }
+        let change = calculate_price_divergence(pair[0].price, pair[1].price) as u128;
+        squared_returns = squared_returns.saturating_add(change * change);
+        returns += 1;
+    }
+    match returns {
+        0 => None,
+        _ => Some(sqrt(squared_returns).try_into().unwrap_or(u32::MAX)),
+    }
+}
+// Growth of debt compounded every slot, annual stability_fee 100000 -> 100%
+pub fn calculate_compounded_interest(stability_fee: u32, elapsed: u64) -> u128 {
+    let one = 10u128.pow(INTEREST_OFFSET.into());
+    let slot_rate = (stability_fee as u128)
+        .checked_mul(one)
+        .unwrap()
+        .checked_div(100000 * SLOTS_PER_YEAR as u128)
+        .unwrap();
+    // exponentiation by squaring
+    let mut base = one.checked_add(slot_rate).unwrap();
+    let mut growth = one;
+    let mut exponent = elapsed;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            growth = growth.checked_mul(base).unwrap() / one;
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = base.checked_mul(base).unwrap() / one;
+        }
+    }
+    growth
+}
+// Index compounded by growth, both 10^INTEREST_OFFSET -> 1
+pub fn calculate_grown_index(index: u128, growth: u128) -> u128 {
+    let one = 10u128.pow(INTEREST_OFFSET.into());
+    // Whole and fractional part are multiplied apart, so large index doesn't overflow
+    (index / one)
+        .checked_mul(growth)
+        .unwrap()
+        .checked_add((index % one).checked_mul(growth).unwrap() / one)
+        .unwrap()
+}
+// Rounding down - interest is charged in favor of users
+pub fn calculate_accrued_interest(debt: u128, growth: u128) -> u128 {
+    let one = 10u128.pow(INTEREST_OFFSET.into());
+    debt.checked_mul(growth.checked_sub(one).unwrap())
+        .unwrap()
+        .checked_div(one)
+        .unwrap()
+}
+pub fn sqrt(value: u128) -> u128 {
+    if value < 2 {
+        return value;
+    }
+    // Newton's method
+    let mut x = value;
+    let mut y = value / 2;
+    while y < x {
+        x = y;
+        y = (x + value / x) / 2;
+    }
+    x
 }
 #[cfg(test)]
 mod tests {
@@ -279,8 +579,8 @@ mod tests {
     fn test_calculate_new_shares() {
         // Initialize shares
         {
-            let collateral_shares = 0u64;
-            let collateral_amount = 0u64;
+            let collateral_shares = 0u128;
+            let collateral_amount = 0u128;
             let to_deposit_amount = 10u64.pow(6);
             let new_shares_rounding_down = calculate_new_shares_by_rounding_down(
                 collateral_shares,
@@ -293,13 +593,13 @@ mod tests {
                 to_deposit_amount,
             );
             // Initial shares = deposited amount
-            assert_eq!(new_shares_rounding_down, to_deposit_amount);
-            assert_eq!(new_shares_rounding_up, to_deposit_amount);
+            assert_eq!(new_shares_rounding_down, to_deposit_amount as u128);
+            assert_eq!(new_shares_rounding_up, to_deposit_amount as u128);
         }
         // With existing shares
         {
-            let collateral_shares = 10u64.pow(6);
-            let collateral_amount = 10u64.pow(6);
+            let collateral_shares = 10u128.pow(6);
+            let collateral_amount = 10u128.pow(6);
             let to_deposit_amount = 10u64.pow(6);
             let new_shares_rounding_down = calculate_new_shares_by_rounding_down(
                 collateral_shares,
@@ -317,8 +617,8 @@ mod tests {
         }
         // Zero new shares
         {
-            let collateral_shares = 10u64.pow(6);
-            let collateral_amount = 10u64.pow(6);
+            let collateral_shares = 10u128.pow(6);
+            let collateral_amount = 10u128.pow(6);
             let to_deposit_amount = 0u64;
             let new_shares_rounding_down = calculate_new_shares_by_rounding_down(
                 collateral_shares,
@@ -331,13 +631,13 @@ mod tests {
                 to_deposit_amount,
             );
             // deposit 0
-            assert_eq!(new_shares_rounding_down, 0u64);
-            assert_eq!(new_shares_rounding_up, 0u64);
+            assert_eq!(new_shares_rounding_down, 0);
+            assert_eq!(new_shares_rounding_up, 0);
         }
         // Valid rounding
         {
-            let collateral_shares = 10_001 * 10u64.pow(6);
-            let collateral_amount = 988_409 * 10u64.pow(6);
+            let collateral_shares = 10_001 * 10u128.pow(6);
+            let collateral_amount = 988_409 * 10u128.pow(6);
             let to_deposit_amount = 579_112;
             let new_shares_rounding_down = calculate_new_shares_by_rounding_down(
                 collateral_shares,
@@ -355,8 +655,8 @@ mod tests {
         }
         // Test on big numbers
         {
-            let collateral_shares = 100_000_000 * 10u64.pow(6);
-            let collateral_amount = 100_000_000 * 10u64.pow(6);
+            let collateral_shares = 100_000_000 * 10u128.pow(6);
+            let collateral_amount = 100_000_000 * 10u128.pow(6);
             let to_deposit_amount = 10_000_000 * 10u64.pow(6);
             let new_shares_rounding_down = calculate_new_shares_by_rounding_down(
                 collateral_shares,
@@ -406,6 +706,57 @@ mod tests {
         }
     }
     #[test]
+    fn test_calculate_stored_debt() {
+        let slot = 100;
+        let mut assets_list = AssetsList {
+            ..Default::default()
+        };
+        // debt 1000
+        assets_list.append_asset(Asset {
+            price: 10 * 10u64.pow(PRICE_OFFSET.into()),
+            last_update: slot,
+            ..Default::default()
+        });
+        assets_list.append_synthetic(Synthetic {
+            supply: 100 * 10u64.pow(6),
+            decimals: 6,
+            asset_index: 0,
+            ..Default::default()
+        });
+        // debt 2400 at outdated price
+        assets_list.append_asset(Asset {
+            price: 12 * 10u64.pow(PRICE_OFFSET.into()),
+            last_update: 0,
+            ..Default::default()
+        });
+        assets_list.append_synthetic(Synthetic {
+            supply: 200 * 10u64.pow(8),
+            decimals: 8,
+            asset_index: 1,
+            ..Default::default()
+        });
+        assert_eq!(
+            calculate_synthetic_debt(&assets_list.assets[1], &assets_list.synthetics[1]),
+            2400_000000
+        );
+        assert_eq!(calculate_stored_debt(&assets_list), 3400_000000);
+        // 0.5 of synthetic with 8 decimals
+        assert_eq!(
+            calculate_debt_in_usd(
+                &assets_list.assets[1],
+                &assets_list.synthetics[1],
+                5000_0000
+            ),
+            6_000000
+        );
+        // Stored debt ignores outdated prices
+        assert!(calculate_debt(&assets_list, slot, 10).is_err());
+        assert_eq!(
+            calculate_debt(&assets_list, slot, 100).ok(),
+            Some(3400_000000)
+        );
+    }
+    #[test]
     fn test_calculate_debt() {
         {
             let slot = 100;
@@ -688,6 +1039,18 @@ mod tests {
         // debt 2400
         let result = calculate_debt(&assets_ref.borrow_mut(), slot, 0);
         assert!(result.is_err());
+
+        // max_delay longer than chain history
+        let result = calculate_debt(&assets_ref.borrow_mut(), slot, 1000);
+        assert!(result.is_ok());
+
+        // asset max_delay overrides global one
+        assets_ref.borrow_mut().assets[0].max_delay = 20;
+        let result = calculate_debt(&assets_ref.borrow_mut(), slot, 0);
+        assert!(result.is_ok());
+        assets_ref.borrow_mut().assets[0].max_delay = 5;
+        let result = calculate_debt(&assets_ref.borrow_mut(), slot, 1000);
+        assert!(result.is_err());
     }
     #[test]
     fn test_calculate_max_debt_in_usd() {
@@ -893,7 +1256,7 @@ mod tests {
         // zero amount
         {
             let all_shares = 100;
-            let full_amount = 100 * 10u64.pow(6);
+            let full_amount = 100 * 10u128.pow(6);
             let amount = 0;
 
             let amount_by_rounding_down =
@@ -919,8 +1282,8 @@ mod tests {
         }
         // large numbers
         {
-            let all_shares = 10u64.pow(6);
-            let full_amount = 1_000_000_000 * 10u64.pow(10);
+            let all_shares = 10u128.pow(6);
+            let full_amount = 1_000_000_000 * 10u128.pow(10);
             let amount = 198_112 * 10u64.pow(10);
 
             let amount_by_rounding_down =
@@ -1051,6 +1414,36 @@ mod tests {
         }
     }
     #[test]
+    fn test_calculate_max_burned_in_token() {
+        // xUSD
+        {
+            let asset = Asset {
+                price: 10u64.pow(PRICE_OFFSET.into()),
+                ..Default::default()
+            };
+            let synthetic = Synthetic {
+                decimals: 6,
+                ..Default::default()
+            };
+            let burned = calculate_max_burned_in_token(&asset, &synthetic, 100_000000);
+            assert_eq!(burned, 100_000000);
+        }
+        // 8 decimals, rounding up
+        {
+            let asset = Asset {
+                price: 30000 * 10u64.pow(PRICE_OFFSET.into()),
+                ..Default::default()
+            };
+            let synthetic = Synthetic {
+                decimals: 8,
+                ..Default::default()
+            };
+            // 100 / 30000 = 0.00333...
+            let burned = calculate_max_burned_in_token(&asset, &synthetic, 100_000000);
+            assert_eq!(burned, 333334);
+        }
+    }
+    #[test]
     fn test_usd_to_token_amount() {
         // round down
         {
@@ -1086,35 +1479,426 @@ mod tests {
         }
     }
     #[test]
+    fn test_price_mode() {
+        let spot = Asset {
+            price: 10 * 10u64.pow(PRICE_OFFSET.into()),
+            twap: 12 * 10u64.pow(PRICE_OFFSET.into()),
+            price_mode: PRICE_MODE_SPOT,
+            ..Default::default()
+        };
+        assert_eq!(collateral_price(&spot), 10 * 10u64.pow(PRICE_OFFSET.into()));
+        assert_eq!(debt_price(&spot), 10 * 10u64.pow(PRICE_OFFSET.into()));
+
+        let twap = Asset {
+            price_mode: PRICE_MODE_TWAP,
+            ..spot
+        };
+        assert_eq!(collateral_price(&twap), 12 * 10u64.pow(PRICE_OFFSET.into()));
+        assert_eq!(debt_price(&twap), 12 * 10u64.pow(PRICE_OFFSET.into()));
+
+        // lower for collateral, higher for debt
+        let conservative = Asset {
+            price_mode: PRICE_MODE_CONSERVATIVE,
+            ..spot
+        };
+        assert_eq!(
+            collateral_price(&conservative),
+            10 * 10u64.pow(PRICE_OFFSET.into())
+        );
+        assert_eq!(
+            debt_price(&conservative),
+            12 * 10u64.pow(PRICE_OFFSET.into())
+        );
+    }
+    #[test]
+    fn test_confidence_adjusted_price() {
+        // 1% confidence
+        let asset = Asset {
+            price: 10 * 10u64.pow(PRICE_OFFSET.into()),
+            twap: 12 * 10u64.pow(PRICE_OFFSET.into()),
+            confidence: 10_000,
+            price_mode: PRICE_MODE_SPOT,
+            ..Default::default()
+        };
+        // Risk mode disabled
+        assert_eq!(confidence_adjustment(&asset, asset.price), 0);
+        assert_eq!(
+            collateral_price(&asset),
+            10 * 10u64.pow(PRICE_OFFSET.into())
+        );
+        assert_eq!(debt_price(&asset), 10 * 10u64.pow(PRICE_OFFSET.into()));
+
+        // k = 2
+        let risky = Asset {
+            confidence_multiplier: 200,
+            ..asset
+        };
+        assert_eq!(confidence_adjustment(&risky, risky.price), 200_000);
+        assert_eq!(collateral_price(&risky), 9_800_000);
+        assert_eq!(debt_price(&risky), 10_200_000);
+
+        // k = 1.5 on top of conservative mode
+        let conservative = Asset {
+            confidence_multiplier: 150,
+            price_mode: PRICE_MODE_CONSERVATIVE,
+            ..asset
+        };
+        assert_eq!(collateral_price(&conservative), 9_850_000);
+        assert_eq!(debt_price(&conservative), 12_180_000);
+
+        // Confidence wider than price
+        let uncertain = Asset {
+            confidence: 2_000_000,
+            confidence_multiplier: 100,
+            ..asset
+        };
+        assert_eq!(collateral_price(&uncertain), 0);
+        assert_eq!(debt_price(&uncertain), 30 * 10u64.pow(PRICE_OFFSET.into()));
+    }
+    #[test]
+    fn test_calculate_debt_with_price_mode() {
+        let slot = 100;
+        let mut assets_list = AssetsList {
+            ..Default::default()
+        };
+        // debt 1000 at spot, 1200 at twap
+        assets_list.append_asset(Asset {
+            price: 10 * 10u64.pow(PRICE_OFFSET.into()),
+            twap: 12 * 10u64.pow(PRICE_OFFSET.into()),
+            last_update: slot,
+            ..Default::default()
+        });
+        assets_list.append_synthetic(Synthetic {
+            supply: 100 * 10u64.pow(6),
+            decimals: 6,
+            asset_index: assets_list.head_assets - 1,
+            ..Default::default()
+        });
+        {
+            let assets_ref = RefCell::new(assets_list);
+            let result = calculate_debt(&assets_ref.borrow_mut(), slot, 0);
+            assert_eq!(result.unwrap(), 1000_000000);
+        }
+        {
+            assets_list.assets[0].price_mode = PRICE_MODE_CONSERVATIVE;
+            let assets_ref = RefCell::new(assets_list);
+            let result = calculate_debt(&assets_ref.borrow_mut(), slot, 0);
+            assert_eq!(result.unwrap(), 1200_000000);
+        }
+    }
+    #[test]
+    fn test_calculate_debt_of_settled_synthetic() {
+        let slot = 100;
+        let mut assets_list = AssetsList {
+            ..Default::default()
+        };
+        // outdated asset at 10
+        assets_list.append_asset(Asset {
+            price: 10 * 10u64.pow(PRICE_OFFSET.into()),
+            twap: 12 * 10u64.pow(PRICE_OFFSET.into()),
+            price_mode: PRICE_MODE_TWAP,
+            last_update: 0,
+            ..Default::default()
+        });
+        // settled at 8
+        assets_list.append_synthetic(Synthetic {
+            supply: 100 * 10u64.pow(6),
+            decimals: 6,
+            asset_index: 0,
+            settlement_slot: 50,
+            settlement_price: 8 * 10u64.pow(PRICE_OFFSET.into()),
+            ..Default::default()
+        });
+        let synthetic = assets_list.synthetics[0];
+        let asset = assets_list.assets[0];
+        assert_eq!(synthetic_price(&asset, &synthetic), 8_000000);
+        assert_eq!(synthetic_debt_price(&asset, &synthetic), 8_000000);
+        assert_eq!(
+            calculate_burned_in_usd(&asset, &synthetic, 10_000000),
+            80_000000
+        );
+        // Frozen price doesn't get outdated
+        {
+            let assets_ref = RefCell::new(assets_list);
+            let result = calculate_debt(&assets_ref.borrow_mut(), slot, 10);
+            assert_eq!(result.unwrap(), 800_000000);
+        }
+        // Before settlement price asset price is used
+        assets_list.synthetics[0].settlement_price = 0;
+        {
+            let assets_ref = RefCell::new(assets_list);
+            let result = calculate_debt(&assets_ref.borrow_mut(), slot, 10);
+            assert!(result.is_err());
+        }
+        assert_eq!(calculate_stored_debt(&assets_list), 1200_000000);
+    }
+    #[test]
+    fn test_scale_price() {
+        // Pyth price with 4 decimals
+        assert_eq!(scale_price(123_4567, -4).unwrap(), 123_456700);
+        // Pyth price with 8 decimals
+        assert_eq!(scale_price(123_45678901, -8).unwrap(), 123_456789);
+        // Pyth price without decimals
+        assert_eq!(scale_price(50000, 0).unwrap(), 50000_000000);
+        // Zero price
+        assert_eq!(scale_price(0, -8).unwrap(), 0);
+        // Scale far below price decimals
+        assert_eq!(scale_price(i64::MAX, -100).unwrap(), 0);
+        // Negative price
+        assert!(matches!(
+            scale_price(-1, -4),
+            Err(Error::ErrorCode(ErrorCode::NegativePrice))
+        ));
+        // Scaled price out of u64 range
+        assert!(matches!(
+            scale_price(i64::MAX, 10),
+            Err(Error::ErrorCode(ErrorCode::PriceOverflow))
+        ));
+        assert!(matches!(
+            scale_price(1, 100),
+            Err(Error::ErrorCode(ErrorCode::PriceOverflow))
+        ));
+        assert!(matches!(
+            scale_price(1, i32::MAX),
+            Err(Error::ErrorCode(ErrorCode::PriceOverflow))
+        ));
+    }
+    #[test]
     fn test_calculate_confidence() {
         let offset = 10u32.pow(CONFIDENCE_OFFSET.into());
         // 100% -> 1 * 10 ** CONFIDENCE_OFFSET
         {
             let price = 100_000_000i64;
             let conf = 100_000_000u64;
-            let confidence = calculate_confidence(conf, price);
+            let confidence = calculate_confidence(conf, price).unwrap();
             assert_eq!(confidence, (1f64 * f64::from(offset)) as u32)
         }
         // 1% -> 0.01 * 10 ** CONFIDENCE_OFFSET
         {
             let price = 100_000_000i64;
             let conf = 1_000_000u64;
-            let confidence = calculate_confidence(conf, price);
+            let confidence = calculate_confidence(conf, price).unwrap();
             assert_eq!(confidence, (0.01 * f64::from(offset)) as u32)
         }
         // 0.1% -> 0.001 * 10 ** CONFIDENCE_OFFSET
         {
             let price = 100_000_000i64;
             let conf = 100_000u64;
-            let confidence = calculate_confidence(conf, price);
+            let confidence = calculate_confidence(conf, price).unwrap();
             assert_eq!(confidence, (0.001 * f64::from(offset)) as u32)
         }
         // 0.01% -> 0.0001 * 10 ** CONFIDENCE_OFFSET
         {
             let price = 100_000_000i64;
             let conf = 10_000u64;
-            let confidence = calculate_confidence(conf, price);
+            let confidence = calculate_confidence(conf, price).unwrap();
             assert_eq!(confidence, (0.0001 * f64::from(offset)) as u32)
         }
     }
+    #[test]
+    fn test_calculate_confidence_invalid_price() {
+        assert!(matches!(
+            calculate_confidence(1_000, 0),
+            Err(Error::ErrorCode(ErrorCode::ZeroPrice))
+        ));
+        assert!(matches!(
+            calculate_confidence(1_000, -1_000),
+            Err(Error::ErrorCode(ErrorCode::NegativePrice))
+        ));
+        // Confidence far wider than price
+        assert_eq!(calculate_confidence(u64::MAX, 1).unwrap(), u32::MAX);
+    }
+    #[test]
+    fn test_calculate_price_divergence() {
+        // same price
+        {
+            let divergence = calculate_price_divergence(2_000_000, 2_000_000);
+            assert_eq!(divergence, 0);
+        }
+        // 1% up
+        {
+            let divergence = calculate_price_divergence(2_000_000, 2_020_000);
+            assert_eq!(divergence, 10_000);
+        }
+        // 5% down
+        {
+            let divergence = calculate_price_divergence(2_000_000, 1_900_000);
+            assert_eq!(divergence, 50_000);
+        }
+        // no reference price
+        {
+            let divergence = calculate_price_divergence(0, 1_900_000);
+            assert_eq!(divergence, 0);
+        }
+        // overflow
+        {
+            let divergence = calculate_price_divergence(1, u64::MAX);
+            assert_eq!(divergence, u32::MAX);
+        }
+    }
+    #[test]
+    fn test_calculate_derived_prices() {
+        let eth = 2_000 * 10u64.pow(PRICE_OFFSET.into());
+        let btc = 40_000 * 10u64.pow(PRICE_OFFSET.into());
+        // product
+        {
+            let price = calculate_price_product(eth, 1_500_000).unwrap();
+            assert_eq!(price, 3_000 * 10u64.pow(PRICE_OFFSET.into()));
+            let result = calculate_price_product(u64::MAX, u64::MAX);
+            assert!(matches!(
+                result,
+                Err(Error::ErrorCode(ErrorCode::PriceOverflow))
+            ));
+        }
+        // ratio
+        {
+            let price = calculate_price_ratio(eth, btc).unwrap();
+            assert_eq!(price, 50_000);
+            // inverse
+            let price = calculate_price_ratio(10u64.pow(PRICE_OFFSET.into()), 1_250_000).unwrap();
+            assert_eq!(price, 800_000);
+            // unknown denominator
+            let price = calculate_price_ratio(eth, 0).unwrap();
+            assert_eq!(price, 0);
+        }
+        // basket 0.5 BTC + 10 ETH
+        {
+            let prices = [btc, eth];
+            let weights = [500_000, 10_000_000];
+            let price = calculate_basket_price(&prices, &weights).unwrap();
+            assert_eq!(price, 40_000 * 10u64.pow(PRICE_OFFSET.into()));
+            // 1% on btc half, 3% on eth half
+            let confidence = calculate_basket_confidence(&prices, &[10_000, 30_000], &weights);
+            assert_eq!(confidence, 20_000);
+            let confidence = calculate_basket_confidence(&[0, 0], &[10_000, 30_000], &weights);
+            assert_eq!(confidence, 0);
+        }
+    }
+    #[test]
+    fn test_calculate_inverse_price() {
+        let asset = Asset {
+            entry_price: 100 * 10u64.pow(PRICE_OFFSET.into()),
+            lower_bound: 50 * 10u64.pow(PRICE_OFFSET.into()),
+            upper_bound: 150 * 10u64.pow(PRICE_OFFSET.into()),
+            ..Default::default()
+        };
+        // spot at entry
+        {
+            let price = calculate_inverse_price(&asset, 100 * 10u64.pow(PRICE_OFFSET.into()));
+            assert_eq!(price, 100 * 10u64.pow(PRICE_OFFSET.into()));
+        }
+        // spot up
+        {
+            let price = calculate_inverse_price(&asset, 120 * 10u64.pow(PRICE_OFFSET.into()));
+            assert_eq!(price, 80 * 10u64.pow(PRICE_OFFSET.into()));
+        }
+        // clamped at lower bound
+        {
+            let price = calculate_inverse_price(&asset, 190 * 10u64.pow(PRICE_OFFSET.into()));
+            assert_eq!(price, { asset.lower_bound });
+            let price = calculate_inverse_price(&asset, u64::MAX);
+            assert_eq!(price, { asset.lower_bound });
+        }
+        // clamped at upper bound
+        {
+            let price = calculate_inverse_price(&asset, 10 * 10u64.pow(PRICE_OFFSET.into()));
+            assert_eq!(price, { asset.upper_bound });
+        }
+        // confidence
+        {
+            assert_eq!(calculate_inverse_confidence(150, 10_000, 50), 30_000);
+            assert_eq!(calculate_inverse_confidence(150, 10_000, 0), u32::MAX);
+        }
+    }
+    #[test]
+    fn test_calculate_history_twap() {
+        let entry = |slot, price| PriceEntry {
+            slot,
+            price,
+            confidence: 0,
+        };
+        let entries = [entry(10, 100), entry(20, 200), entry(40, 400)];
+        // 100 for 10 slots, 200 for 20 slots and 400 for 10 slots
+        assert_eq!(calculate_history_twap(&entries, 100, 50), Some(225));
+        // window starts at 30
+        assert_eq!(calculate_history_twap(&entries, 20, 50), Some(300));
+        // only last price in window
+        assert_eq!(calculate_history_twap(&entries, 5, 50), Some(400));
+        // price published in current slot
+        assert_eq!(calculate_history_twap(&entries, 0, 40), Some(400));
+        assert_eq!(calculate_history_twap(&[], 100, 50), None);
+    }
+    #[test]
+    fn test_calculate_history_volatility() {
+        let entry = |slot, price| PriceEntry {
+            slot,
+            price,
+            confidence: 0,
+        };
+        // returns of 3% and 4%
+        let entries = [
+            entry(10, 1_000_000),
+            entry(20, 1_030_000),
+            entry(30, 988_800),
+        ];
+        assert_eq!(
+            calculate_history_volatility(&entries, 100, 30),
+            Some(50_000)
+        );
+        // only last return in window
+        assert_eq!(calculate_history_volatility(&entries, 15, 30), Some(40_000));
+        assert_eq!(calculate_history_volatility(&entries[..1], 100, 30), None);
+        assert_eq!(calculate_history_volatility(&entries, 5, 30), None);
+    }
+    #[test]
+    fn test_calculate_compounded_interest() {
+        let one = 10u128.pow(INTEREST_OFFSET.into());
+        assert_eq!(calculate_compounded_interest(0, SLOTS_PER_YEAR), one);
+        assert_eq!(calculate_compounded_interest(10000, 0), one);
+        // per slot rate of 10% yearly
+        assert_eq!(calculate_compounded_interest(10000, 1), one + 1_585_489_599);
+        // 10% yearly compounded every slot ~ e^0.1
+        let growth = calculate_compounded_interest(10000, SLOTS_PER_YEAR);
+        assert!(growth > 1_105_170_000 * 10u128.pow(9));
+        assert!(growth < 1_105_171_000 * 10u128.pow(9));
+        // 100% yearly over two years ~ e^2
+        let growth = calculate_compounded_interest(100000, 2 * SLOTS_PER_YEAR);
+        assert!(growth > 7_389_000 * 10u128.pow(12));
+        assert!(growth < 7_390_000 * 10u128.pow(12));
+    }
+    #[test]
+    fn test_calculate_grown_index() {
+        let one = 10u128.pow(INTEREST_OFFSET.into());
+        assert_eq!(calculate_grown_index(one, one), one);
+        assert_eq!(calculate_grown_index(one, 2 * one), 2 * one);
+        assert_eq!(calculate_grown_index(3 * one / 2, 3 * one / 2), 9 * one / 4);
+        // Index far above one grows without overflow
+        let index = 10u128.pow(12) * one;
+        assert_eq!(calculate_grown_index(index, 3 * one), 3 * index);
+    }
+    #[test]
+    fn test_calculate_accrued_interest() {
+        let one = 10u128.pow(INTEREST_OFFSET.into());
+        let debt = 1_000_000 * 10u128.pow(ACCURACY.into());
+        assert_eq!(calculate_accrued_interest(debt, one), 0);
+        assert_eq!(calculate_accrued_interest(0, 2 * one), 0);
+        // 10% of debt
+        let growth = one + one / 10;
+        assert_eq!(
+            calculate_accrued_interest(debt, growth),
+            100_000 * 10u128.pow(ACCURACY.into())
+        );
+        // rounding down
+        assert_eq!(calculate_accrued_interest(9, growth), 0);
+        assert_eq!(calculate_accrued_interest(19, growth), 1);
+    }
+    #[test]
+    fn test_sqrt() {
+        assert_eq!(sqrt(0), 0);
+        assert_eq!(sqrt(1), 1);
+        assert_eq!(sqrt(15), 3);
+        assert_eq!(sqrt(16), 4);
+        assert_eq!(sqrt(10u128.pow(18)), 10u128.pow(9));
+        assert_eq!(sqrt(u128::MAX), u64::MAX as u128);
+    }
 }
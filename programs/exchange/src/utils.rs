@@ -1,12 +1,33 @@
-use std::cell::RefMut;
+use std::{cell::RefMut, convert::TryInto};
 
+use crate::math::{
+    calculate_accrued_interest, calculate_asset_debt, calculate_basket_confidence,
+    calculate_basket_price, calculate_compounded_interest, calculate_confidence,
+    calculate_grown_index, calculate_inverse_confidence, calculate_inverse_price,
+    calculate_price_divergence, calculate_price_product, calculate_price_ratio,
+    calculate_swap_out_amount, calculate_synthetic_debt, scale_price, INTEREST_OFFSET,
+    PRICE_OFFSET, SLOTS_PER_YEAR,
+};
 use crate::*;
+use pyth::pc::{AccountType, Price, PriceStatus, PriceType, MAGIC, VERSION};
 
 const BITS: u64 = (core::mem::size_of::<u64>() * 8) as u64;
 pub const fn log2(n: u64) -> u64 {
     (BITS - 1) - n.leading_zeros() as u64
 }
 
+// Global max_delay is used for assets without own tolerance
+pub fn asset_max_delay(asset: &Asset, default_max_delay: u32) -> u32 {
+    match asset.max_delay {
+        0 => default_max_delay,
+        max_delay => max_delay,
+    }
+}
+pub fn is_price_outdated(asset: &Asset, default_max_delay: u32, slot: u64) -> bool {
+    // last_update is the slot in which oracle published the price
+    let max_delay = asset_max_delay(asset, default_max_delay);
+    asset.last_update < slot.saturating_sub(max_delay.into())
+}
 pub fn check_feed_update(
     assets: &[Asset],
     index_a: usize,
@@ -15,15 +36,629 @@ pub fn check_feed_update(
     slot: u64,
 ) -> Result<()> {
     // Check assetA
-    if (assets[index_a].last_update as u64) < slot - max_delay as u64 {
+    if is_price_outdated(&assets[index_a], max_delay, slot) {
         return Err(ErrorCode::OutdatedOracle.into());
     }
     // Check assetB
-    if (assets[index_b].last_update as u64) < slot - max_delay as u64 {
+    if is_price_outdated(&assets[index_b], max_delay, slot) {
         return Err(ErrorCode::OutdatedOracle.into());
     }
     return Ok(());
 }
+// Minted synthetic, derived one included, has to be priced by fresh and trusted price
+pub fn check_mintable_synthetic(
+    assets: &[Asset],
+    synthetic: &Synthetic,
+    max_delay: u32,
+    slot: u64,
+) -> Result<()> {
+    if is_settled(synthetic, slot) {
+        return Err(ErrorCode::SettledSynthetic.into());
+    }
+    let asset_index = synthetic.asset_index as usize;
+    check_feed_update(assets, asset_index, asset_index, max_delay, slot)?;
+    check_feed_frozen(assets, asset_index, asset_index)?;
+    check_feed_breaker(assets, asset_index, asset_index)
+}
+// Settled synthetic can only be swapped out, frozen price of it doesn't depend on its asset
+pub fn check_swap_synthetics(
+    assets: &[Asset],
+    synthetic_in: &Synthetic,
+    synthetic_for: &Synthetic,
+    max_delay: u32,
+    slot: u64,
+) -> Result<()> {
+    if is_settled(synthetic_for, slot) {
+        return Err(ErrorCode::SettledSynthetic.into());
+    }
+    let asset_for_index = synthetic_for.asset_index as usize;
+    let asset_in_index = match is_settlement_priced(synthetic_in) {
+        true => asset_for_index,
+        false => synthetic_in.asset_index as usize,
+    };
+    check_feed_update(assets, asset_in_index, asset_for_index, max_delay, slot)?;
+    check_feed_frozen(assets, asset_in_index, asset_for_index)?;
+    check_feed_breaker(assets, asset_in_index, asset_for_index)?;
+    check_feed_confidence(assets, asset_in_index, asset_for_index)
+}
+pub fn check_feed_confidence(assets: &[Asset], index_a: usize, index_b: usize) -> Result<()> {
+    // Check assetA
+    if assets[index_a].confidence > assets[index_a].max_confidence {
+        return Err(ErrorCode::UncertainOracle.into());
+    }
+    // Check assetB
+    if assets[index_b].confidence > assets[index_b].max_confidence {
+        return Err(ErrorCode::UncertainOracle.into());
+    }
+    Ok(())
+}
+pub fn check_feed_frozen(assets: &[Asset], index_a: usize, index_b: usize) -> Result<()> {
+    if assets[index_a].frozen || assets[index_b].frozen {
+        return Err(ErrorCode::FrozenAsset.into());
+    }
+    Ok(())
+}
+pub fn check_feed_breaker(assets: &[Asset], index_a: usize, index_b: usize) -> Result<()> {
+    if assets[index_a].in_breaker || assets[index_b].in_breaker {
+        return Err(ErrorCode::PriceBreaker.into());
+    }
+    Ok(())
+}
+pub fn check_collaterals_frozen(
+    exchange_account: &ExchangeAccount,
+    assets_list: &AssetsList,
+) -> Result<()> {
+    let head = exchange_account.head as usize;
+    for collateral_entry in exchange_account.collaterals[..head].iter() {
+        let collateral = &assets_list.collaterals[collateral_entry.index as usize];
+        if assets_list.assets[collateral.asset_index as usize].frozen {
+            return Err(ErrorCode::FrozenAsset.into());
+        }
+    }
+    Ok(())
+}
+pub fn check_collaterals_update(
+    exchange_account: &ExchangeAccount,
+    assets_list: &AssetsList,
+    max_delay: u32,
+    slot: u64,
+) -> Result<()> {
+    let head = exchange_account.head as usize;
+    for collateral_entry in exchange_account.collaterals[..head].iter() {
+        let collateral = &assets_list.collaterals[collateral_entry.index as usize];
+        if is_price_outdated(
+            &assets_list.assets[collateral.asset_index as usize],
+            max_delay,
+            slot,
+        ) {
+            return Err(ErrorCode::OutdatedOracle.into());
+        }
+    }
+    Ok(())
+}
+pub fn check_debt_ceiling(state: &State, new_debt: u64) -> Result<()> {
+    match state.debt.checked_add(new_debt.into()) {
+        Some(debt) if debt <= state.debt_ceiling.into() => Ok(()),
+        _ => Err(ErrorCode::DebtCeiling.into()),
+    }
+}
+// Debt left on account is not worth liquidating
+pub fn is_dust_debt(state: &State, account_debt: u64) -> bool {
+    account_debt > 0 && account_debt < state.min_account_debt
+}
+pub fn check_account_debt(state: &State, account_debt: u64) -> Result<()> {
+    if account_debt > state.max_account_debt {
+        return Err(ErrorCode::AccountDebtLimit.into());
+    }
+    if is_dust_debt(state, account_debt) {
+        return Err(ErrorCode::AccountDebtDust.into());
+    }
+    Ok(())
+}
+pub fn check_account_confidence(
+    exchange_account: &ExchangeAccount,
+    assets_list: &AssetsList,
+) -> Result<()> {
+    // Debt is based on prices of all synthetics
+    let head = assets_list.head_synthetics as usize;
+    for synthetic in assets_list.synthetics[..head].iter() {
+        let asset = &assets_list.assets[synthetic.asset_index as usize];
+        if asset.confidence > asset.max_confidence {
+            return Err(ErrorCode::UncertainOracle.into());
+        }
+    }
+    // Max debt is based on prices of user collaterals
+    let head = exchange_account.head as usize;
+    for collateral_entry in exchange_account.collaterals[..head].iter() {
+        let collateral = &assets_list.collaterals[collateral_entry.index as usize];
+        let asset = &assets_list.assets[collateral.asset_index as usize];
+        if asset.confidence > asset.max_confidence {
+            return Err(ErrorCode::UncertainOracle.into());
+        }
+    }
+    Ok(())
+}
+
+pub fn load_price_feed<'a>(
+    price_feed: &'a AccountInfo,
+    oracle_program: &Pubkey,
+) -> Result<RefMut<'a, Price>> {
+    if !price_feed.owner.eq(oracle_program) {
+        return Err(ErrorCode::InvalidOracleProgram.into());
+    }
+    let price = match Price::load(price_feed) {
+        Ok(price) => price,
+        Err(_) => return Err(ErrorCode::InvalidOracleAccount.into()),
+    };
+    check_price_header(&price)?;
+    Ok(price)
+}
+pub fn check_price_header(price: &Price) -> Result<()> {
+    if price.magic != MAGIC || price.ver != VERSION || price.atype != AccountType::Price as u32 {
+        return Err(ErrorCode::InvalidOracleAccount.into());
+    }
+    if price.ptype != PriceType::Price as u32 {
+        return Err(ErrorCode::InvalidPriceType.into());
+    }
+    Ok(())
+}
+
+// Price read from a single feed, already scaled
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct FeedPrice {
+    pub price: u64,
+    pub twap: u64,
+    pub confidence: u32,
+    pub valid_slot: u64, // Slot in which aggregate price was published
+    pub trading: bool,
+}
+pub fn read_feed_price(price_feed: &Price) -> Result<FeedPrice> {
+    // Only prices from trading feeds can be used,
+    // last aggregate of other feeds is kept as reference for fallback feed
+    if price_feed.agg.status != PriceStatus::Trading as u32 {
+        return Ok(FeedPrice {
+            price: scale_price(price_feed.agg.price, price_feed.expo).unwrap_or(0),
+            valid_slot: price_feed.valid_slot,
+            ..Default::default()
+        });
+    }
+    let price = scale_price(price_feed.agg.price, price_feed.expo)?;
+    if price == 0 {
+        return Err(ErrorCode::ZeroPrice.into());
+    }
+    Ok(FeedPrice {
+        price,
+        twap: scale_price(price_feed.twap, price_feed.expo)?,
+        confidence: calculate_confidence(price_feed.agg.conf, price_feed.agg.price)?,
+        valid_slot: price_feed.valid_slot,
+        trading: true,
+    })
+}
+pub fn check_asset_kind(asset_kind: u8, inputs: usize, weights: &[u64]) -> Result<()> {
+    let valid = match asset_kind {
+        ASSET_KIND_PRODUCT | ASSET_KIND_RATIO => inputs == 2 && weights.is_empty(),
+        ASSET_KIND_INVERSE | ASSET_KIND_INVERSE_SYNTHETIC => inputs == 1 && weights.is_empty(),
+        ASSET_KIND_BASKET => {
+            inputs > 0
+                && inputs <= DERIVED_MAX_INPUTS
+                && weights.len() == inputs
+                && weights.iter().all(|weight| *weight > 0)
+        }
+        _ => false,
+    };
+    if !valid {
+        return Err(ErrorCode::InvalidAssetKind.into());
+    }
+    Ok(())
+}
+// Derived price is as fresh as the oldest input and trades only if all inputs do
+pub fn derive_feed_price(asset: &Asset, inputs: &[FeedPrice]) -> Result<FeedPrice> {
+    let valid_slot = inputs.iter().map(|x| x.valid_slot).min().unwrap_or(0);
+    // Inverse synthetic without price band cannot be priced
+    let unconfigured = asset.asset_kind == ASSET_KIND_INVERSE_SYNTHETIC && asset.entry_price == 0;
+    if inputs.is_empty() || inputs.iter().any(|x| !x.trading) || unconfigured {
+        return Ok(FeedPrice {
+            valid_slot,
+            ..Default::default()
+        });
+    }
+    let weights = asset.input_weights;
+    let weights = &weights[..inputs.len()];
+    let (price, twap, confidence) = match asset.asset_kind {
+        ASSET_KIND_PRODUCT => (
+            calculate_price_product(inputs[0].price, inputs[1].price)?,
+            calculate_price_product(inputs[0].twap, inputs[1].twap)?,
+            inputs[0].confidence.saturating_add(inputs[1].confidence),
+        ),
+        ASSET_KIND_RATIO => (
+            calculate_price_ratio(inputs[0].price, inputs[1].price)?,
+            calculate_price_ratio(inputs[0].twap, inputs[1].twap)?,
+            inputs[0].confidence.saturating_add(inputs[1].confidence),
+        ),
+        ASSET_KIND_INVERSE => {
+            let one = 10u64.pow(PRICE_OFFSET.into());
+            (
+                calculate_price_ratio(one, inputs[0].price)?,
+                calculate_price_ratio(one, inputs[0].twap)?,
+                inputs[0].confidence,
+            )
+        }
+        ASSET_KIND_BASKET => {
+            let mut prices = [0u64; DERIVED_MAX_INPUTS];
+            let mut twaps = [0u64; DERIVED_MAX_INPUTS];
+            let mut confidences = [0u32; DERIVED_MAX_INPUTS];
+            for (index, input) in inputs.iter().enumerate() {
+                prices[index] = input.price;
+                twaps[index] = input.twap;
+                confidences[index] = input.confidence;
+            }
+            let count = inputs.len();
+            (
+                calculate_basket_price(&prices[..count], weights)?,
+                calculate_basket_price(&twaps[..count], weights)?,
+                calculate_basket_confidence(&prices[..count], &confidences[..count], weights),
+            )
+        }
+        ASSET_KIND_INVERSE_SYNTHETIC => {
+            let price = calculate_inverse_price(asset, inputs[0].price);
+            (
+                price,
+                calculate_inverse_price(asset, inputs[0].twap),
+                calculate_inverse_confidence(inputs[0].price, inputs[0].confidence, price),
+            )
+        }
+        _ => return Err(ErrorCode::InvalidAssetKind.into()),
+    };
+    if price == 0 {
+        return Err(ErrorCode::ZeroPrice.into());
+    }
+    Ok(FeedPrice {
+        price,
+        twap,
+        confidence,
+        valid_slot,
+        trading: true,
+    })
+}
+// Feeds which price of asset is calculated from
+pub fn asset_input_feeds(asset: &Asset) -> &[Pubkey] {
+    match asset.asset_kind {
+        ASSET_KIND_FEED => std::slice::from_ref(&asset.feed_address),
+        ASSET_KIND_PRODUCT | ASSET_KIND_RATIO => &asset.input_feeds[..2],
+        ASSET_KIND_INVERSE | ASSET_KIND_INVERSE_SYNTHETIC => &asset.input_feeds[..1],
+        _ => {
+            let count = asset
+                .input_feeds
+                .iter()
+                .take_while(|x| **x != Pubkey::default())
+                .count();
+            &asset.input_feeds[..count]
+        }
+    }
+}
+#[derive(Debug, PartialEq)]
+pub enum PriceSource {
+    Primary(FeedPrice),
+    Secondary(FeedPrice),
+    // No price of feed was read, asset is left unchanged
+    Skipped,
+    Unavailable,
+}
+pub fn select_price_source(
+    asset: &Asset,
+    primary: Option<FeedPrice>,
+    secondary: Option<FeedPrice>,
+    max_delay: u32,
+    slot: u64,
+) -> PriceSource {
+    let is_fresh = |feed: &FeedPrice| feed.valid_slot >= slot.saturating_sub(max_delay.into());
+    // Leaving primary feed out of permissionless update cannot force fallback
+    let primary = match primary {
+        Some(primary) => primary,
+        None => return PriceSource::Skipped,
+    };
+    if primary.trading && is_fresh(&primary) {
+        return PriceSource::Primary(primary);
+    }
+    // Secondary feed replaces halted or stale primary only when it is trading,
+    // up to date and close to last primary price
+    let secondary = secondary.filter(|feed| {
+        feed.trading
+            && is_fresh(feed)
+            && primary.price != 0
+            && calculate_price_divergence(primary.price, feed.price) <= asset.max_divergence
+    });
+    match secondary {
+        Some(feed) => PriceSource::Secondary(feed),
+        None if primary.trading => PriceSource::Primary(primary),
+        // Asset is frozen only by primary feed that is not trading
+        None => PriceSource::Unavailable,
+    }
+}
+pub fn update_assets_prices(
+    state: &mut State,
+    assets_list: &mut AssetsList,
+    price_feeds: &[AccountInfo],
+    slot: u64,
+) -> Result<Vec<Pubkey>> {
+    let oracle_program = state.oracle_program;
+    let max_delay = state.max_delay;
+    let head = assets_list.head_assets as usize;
+    // Feeds and derived assets with invalid price
+    let mut skipped_feeds: Vec<Pubkey> = Vec::new();
+    let mut feed_prices: Vec<(&Pubkey, Option<FeedPrice>)> = Vec::with_capacity(price_feeds.len());
+    for oracle_account in price_feeds {
+        let price_feed = load_price_feed(oracle_account, &oracle_program)?;
+        let feed_address = oracle_account.key;
+        let is_used = assets_list.assets[..head].iter().any(|x| {
+            x.secondary_feed_address == *feed_address || asset_input_feeds(x).contains(feed_address)
+        });
+        if !is_used {
+            return Err(ErrorCode::NoAssetFound.into());
+        }
+        match read_feed_price(&price_feed) {
+            Ok(feed_price) => feed_prices.push((feed_address, Some(feed_price))),
+            // Invalid price leaves asset unchanged without failing other feeds
+            Err(err) => {
+                msg!("SYNTHETIFY: SKIPPED PRICE FEED {} {}", feed_address, err);
+                skipped_feeds.push(*feed_address);
+                feed_prices.push((feed_address, None));
+            }
+        }
+    }
+    let find_feed_price = |address: &Pubkey| {
+        feed_prices
+            .iter()
+            .find(|(key, _)| *key == address)
+            .and_then(|(_, feed_price)| *feed_price)
+    };
+
+    let head_synthetics = assets_list.head_synthetics as usize;
+    let (assets, _, synthetics) = assets_list.split_borrow();
+    let synthetics = &mut synthetics[..head_synthetics];
+
+    for (index, asset) in assets[..head].iter_mut().enumerate() {
+        let primary_price = match asset.asset_kind {
+            ASSET_KIND_FEED => find_feed_price(&asset.feed_address),
+            // Derived price is calculated only if all inputs are passed
+            _ => {
+                let mut inputs = [FeedPrice::default(); DERIVED_MAX_INPUTS];
+                let input_feeds = asset_input_feeds(asset);
+                let count = input_feeds.len();
+                let mut found = 0;
+                for (index, input_feed) in input_feeds.iter().enumerate() {
+                    if let Some(feed_price) = find_feed_price(input_feed) {
+                        inputs[index] = feed_price;
+                        found += 1;
+                    }
+                }
+                if found < count {
+                    None
+                } else {
+                    match derive_feed_price(asset, &inputs[..count]) {
+                        Ok(feed_price) => Some(feed_price),
+                        Err(err) => {
+                            msg!(
+                                "SYNTHETIFY: SKIPPED DERIVED PRICE {} {}",
+                                asset.feed_address,
+                                err
+                            );
+                            skipped_feeds.push(asset.feed_address);
+                            None
+                        }
+                    }
+                }
+            }
+        };
+        let secondary_price = match asset.asset_kind {
+            // Fallback of inverse synthetic is alternative spot feed
+            ASSET_KIND_INVERSE_SYNTHETIC => find_feed_price(&asset.secondary_feed_address)
+                .and_then(|feed_price| derive_feed_price(asset, &[feed_price]).ok()),
+            _ => find_feed_price(&asset.secondary_feed_address),
+        };
+        let feed_price = match select_price_source(
+            asset,
+            primary_price,
+            secondary_price,
+            asset_max_delay(asset, max_delay),
+            slot,
+        ) {
+            PriceSource::Primary(feed_price) => {
+                asset.price_source = PRICE_SOURCE_PRIMARY;
+                feed_price
+            }
+            PriceSource::Secondary(feed_price) => {
+                asset.price_source = PRICE_SOURCE_SECONDARY;
+                feed_price
+            }
+            PriceSource::Skipped => continue,
+            PriceSource::Unavailable => {
+                asset.frozen = true;
+                continue;
+            }
+        };
+        asset.frozen = false;
+        // Price jump above limit is not stored until breaker is cleared
+        if !update_price_breaker(asset, feed_price.price, slot) {
+            continue;
+        }
+        revalue_asset(state, asset, index, synthetics, |asset| {
+            asset.price = feed_price.price;
+            asset.twap = feed_price.twap;
+            asset.confidence = feed_price.confidence;
+            // Staleness is measured from oracle publish slot
+            asset.last_update = feed_price.valid_slot.min(slot);
+        });
+
+        // Band hit by outdated price doesn't settle
+        if asset.asset_kind == ASSET_KIND_INVERSE_SYNTHETIC
+            && is_price_band_hit(asset)
+            && !is_price_outdated(asset, max_delay, slot)
+        {
+            settle_asset(synthetics, index, slot);
+        }
+    }
+    freeze_settlement_prices(state, &assets[..head], synthetics, slot);
+    Ok(skipped_feeds)
+}
+// Price history accounts are owned by exchange, price feeds by oracle program
+pub fn split_price_accounts<'info>(
+    accounts: &[AccountInfo<'info>],
+    program_id: &Pubkey,
+) -> (Vec<AccountInfo<'info>>, Vec<AccountInfo<'info>>) {
+    accounts
+        .iter()
+        .cloned()
+        .partition(|account| !account.owner.eq(program_id))
+}
+pub fn update_price_histories(
+    assets_list: &AssetsList,
+    price_histories: &[AccountInfo],
+) -> Result<()> {
+    let head = assets_list.head_assets as usize;
+    for price_history_account in price_histories {
+        let asset = match assets_list.assets[..head]
+            .iter()
+            .find(|x| x.price_history == *price_history_account.key)
+        {
+            Some(asset) => asset,
+            None => return Err(ErrorCode::NoAssetFound.into()),
+        };
+        let loader = Loader::<PriceHistory>::try_from(price_history_account)?;
+        let price_history = &mut loader.load_mut()?;
+        append_price_entry(price_history, asset);
+    }
+    Ok(())
+}
+// Prices are updated together with cached debt, then recorded in histories
+pub fn refresh_assets_prices(
+    state: &mut State,
+    assets_list: &mut AssetsList,
+    accounts: &[AccountInfo],
+    program_id: &Pubkey,
+    slot: u64,
+) -> Result<()> {
+    let (price_feeds, price_histories) = split_price_accounts(accounts, program_id);
+    let skipped_feeds = update_assets_prices(state, assets_list, &price_feeds, slot)?;
+    if !skipped_feeds.is_empty() {
+        emit!(SkippedPriceFeeds {
+            feeds: skipped_feeds
+        });
+    }
+    update_price_histories(assets_list, &price_histories)
+}
+// Only price newer than last entry is stored
+pub fn append_price_entry(price_history: &mut PriceHistory, asset: &Asset) -> bool {
+    if let Some(last_entry) = price_history.last_entry() {
+        if last_entry.slot >= asset.last_update {
+            return false;
+        }
+    }
+    price_history.append(PriceEntry {
+        slot: asset.last_update,
+        price: asset.price,
+        confidence: asset.confidence,
+    });
+    true
+}
+pub fn is_price_band_hit(asset: &Asset) -> bool {
+    asset.price <= asset.lower_bound || asset.price >= asset.upper_bound
+}
+pub fn is_settled(synthetic: &Synthetic, slot: u64) -> bool {
+    synthetic.settlement_slot <= slot
+}
+// Settled synthetic is priced by its frozen price instead of asset
+pub fn is_settlement_priced(synthetic: &Synthetic) -> bool {
+    synthetic.settlement_price != 0
+}
+// Settles not yet settled synthetics of asset, allowing only swaps out
+pub fn settle_asset(synthetics: &mut [Synthetic], asset_index: usize, slot: u64) {
+    for synthetic in synthetics
+        .iter_mut()
+        .filter(|x| x.asset_index as usize == asset_index && !is_settled(x, slot))
+    {
+        synthetic.settlement_slot = slot;
+    }
+}
+// First fresh price since settlement becomes final price of synthetic,
+// asset itself keeps being updated for collaterals and other synthetics
+pub fn freeze_settlement_prices(
+    state: &mut State,
+    assets: &[Asset],
+    synthetics: &mut [Synthetic],
+    slot: u64,
+) {
+    for synthetic in synthetics
+        .iter_mut()
+        .filter(|x| is_settled(x, slot) && !is_settlement_priced(x))
+    {
+        let asset = &assets[synthetic.asset_index as usize];
+        if asset.frozen || asset.in_breaker || is_price_outdated(asset, state.max_delay, slot) {
+            continue;
+        }
+        let old_debt = calculate_synthetic_debt(asset, synthetic);
+        synthetic.settlement_price = asset.price;
+        let new_debt = calculate_synthetic_debt(asset, synthetic);
+        replace_cached_debt(state, old_debt, new_debt);
+    }
+}
+// Only assets outdated before update are rewarded, each at most once per min_age
+pub fn is_crank_eligible(asset: &Asset, min_age: u32, slot: u64) -> bool {
+    let min_update = slot.saturating_sub(min_age.into());
+    asset.last_update < min_update && asset.last_crank < min_update
+}
+// Returns reward for assets refreshed by crank
+pub fn reward_crank_updates(
+    crank: &mut Crank,
+    assets: &mut [Asset],
+    outdated_updates: &[Option<u64>],
+    slot: u64,
+) -> u64 {
+    if crank.last_slot != slot {
+        crank.last_slot = slot;
+        crank.rewards_in_slot = 0;
+    }
+    let mut rewarded = 0u64;
+    for (asset, outdated_update) in assets.iter_mut().zip(outdated_updates.iter()) {
+        if crank.rewards_in_slot >= crank.max_rewards_per_slot {
+            break;
+        }
+        // Update has to bring newer oracle price
+        match outdated_update {
+            Some(last_update) if asset.last_update > *last_update => {
+                asset.last_crank = slot;
+                crank.rewards_in_slot += 1;
+                rewarded += 1;
+            }
+            _ => {}
+        }
+    }
+    rewarded.checked_mul(crank.reward).unwrap()
+}
+// Returns false if new price cannot be stored
+pub fn update_price_breaker(asset: &mut Asset, new_price: u64, slot: u64) -> bool {
+    // Without stored price there is no reference, first price is always accepted
+    if asset.price == 0 {
+        return true;
+    }
+    // Jump is measured from last accepted price, so from price before breaker
+    if calculate_price_divergence(asset.price, new_price) > asset.max_price_change {
+        if !asset.in_breaker {
+            asset.in_breaker = true;
+            asset.breaker_slot = slot;
+        }
+        return false;
+    }
+    // Breaker clears after cooldown once price is back within limit,
+    // jump that persists has to be accepted by admin
+    if asset.in_breaker
+        && asset.breaker_cooldown != 0
+        && slot >= asset.breaker_slot.saturating_add(asset.breaker_cooldown)
+    {
+        asset.in_breaker = false;
+    }
+    true
+}
 
 pub fn div_up(a: u128, b: u128) -> u128 {
     return a
@@ -170,6 +805,120 @@ pub fn set_synthetic_supply(synthetic: &mut Synthetic, new_supply: u64) -> Progr
     synthetic.supply = new_supply;
     Ok(())
 }
+// Sets supply and replaces debt of synthetic in cached debt
+pub fn update_synthetic_supply(
+    state: &mut State,
+    asset: &Asset,
+    synthetic: &mut Synthetic,
+    new_supply: u64,
+) -> ProgramResult {
+    let old_synthetic_debt = calculate_synthetic_debt(asset, synthetic);
+    set_synthetic_supply(synthetic, new_supply)?;
+    let new_synthetic_debt = calculate_synthetic_debt(asset, synthetic);
+    replace_cached_debt(state, old_synthetic_debt, new_synthetic_debt);
+    Ok(())
+}
+// Holders get xUSD worth settled synthetic at final price without fee,
+// debt of burned synthetic moves to minted xUSD. Returns minted xUSD
+pub fn convert_settled_synthetic(
+    state: &mut State,
+    assets: &[Asset],
+    synthetics: &mut [Synthetic],
+    settled_index: usize,
+    amount: u64,
+    slot: u64,
+) -> Result<u64> {
+    // Conversion waits for fresh price since settlement
+    if settled_index == 0
+        || !is_settled(&synthetics[settled_index], slot)
+        || !is_settlement_priced(&synthetics[settled_index])
+    {
+        return Err(ErrorCode::NotSettledSynthetic.into());
+    }
+    let (usd_synthetics, settled_synthetics) = synthetics.split_at_mut(settled_index);
+    let usd_synthetic = &mut usd_synthetics[0];
+    let settled_synthetic = &mut settled_synthetics[0];
+    let usd_asset = &assets[usd_synthetic.asset_index as usize];
+    let settled_asset = &assets[settled_synthetic.asset_index as usize];
+    let amount_usd = calculate_swap_out_amount(
+        settled_asset,
+        usd_asset,
+        settled_synthetic,
+        usd_synthetic,
+        amount,
+        0,
+    );
+
+    let new_settled_supply = settled_synthetic.supply.checked_sub(amount).unwrap();
+    update_synthetic_supply(state, settled_asset, settled_synthetic, new_settled_supply)?;
+    let new_usd_supply = usd_synthetic.supply.checked_add(amount_usd).unwrap();
+    update_synthetic_supply(state, usd_asset, usd_synthetic, new_usd_supply)?;
+    Ok(amount_usd)
+}
+// Cached debt is moved by change of debt instead of being recalculated
+fn replace_cached_debt(state: &mut State, old_debt: u128, new_debt: u128) {
+    state.debt = state
+        .debt
+        .saturating_sub(old_debt)
+        .checked_add(new_debt)
+        .unwrap();
+}
+// Every change of stored price or its valuation goes through here,
+// so cached debt follows prices of synthetics priced by asset
+pub fn revalue_asset<F>(
+    state: &mut State,
+    asset: &mut Asset,
+    asset_index: usize,
+    synthetics: &[Synthetic],
+    update: F,
+) where
+    F: FnOnce(&mut Asset),
+{
+    let old_debt = calculate_asset_debt(asset, asset_index, synthetics);
+    update(asset);
+    let new_debt = calculate_asset_debt(asset, asset_index, synthetics);
+    replace_cached_debt(state, old_debt, new_debt);
+}
+// Accrued fee is minted as xUSD supply of fee vault, raising debt of every share
+pub fn adjust_stability_fee(
+    state: &mut State,
+    assets_list: &mut AssetsList,
+    slot: u64,
+) -> ProgramResult {
+    let elapsed = slot.saturating_sub(state.stability_fee_slot);
+    if elapsed == 0 {
+        return Ok(());
+    }
+    // Compounded a year at a time, so long period neither overflows nor is cut short
+    let mut growth = 10u128.pow(INTEREST_OFFSET.into());
+    let mut remaining = elapsed;
+    while remaining > 0 {
+        let period = remaining.min(SLOTS_PER_YEAR);
+        let period_growth = calculate_compounded_interest(state.stability_fee, period);
+        growth = calculate_grown_index(growth, period_growth);
+        remaining -= period;
+    }
+    // xUSD is priced at 1 with ACCURACY decimals so fee in usd is amount of tokens
+    let fee: u64 = calculate_accrued_interest(state.debt, growth)
+        .try_into()
+        .unwrap();
+    // Fee too small to mint keeps accruing since last slot
+    if fee == 0 && state.debt > 0 && state.stability_fee > 0 {
+        return Ok(());
+    }
+    state.stability_fee_slot = slot;
+    state.stability_fee_index = calculate_grown_index(state.stability_fee_index, growth);
+    if fee == 0 {
+        return Ok(());
+    }
+
+    let (assets, _, synthetics) = assets_list.split_borrow();
+    let usd_synthetic = &mut synthetics[0];
+    let new_supply = usd_synthetic.supply.checked_add(fee).unwrap();
+    update_synthetic_supply(state, &assets[0], usd_synthetic, new_supply)?;
+    state.accumulated_stability_fee = state.accumulated_stability_fee.checked_add(fee).unwrap();
+    Ok(())
+}
 pub fn get_user_sny_collateral_balance(
     exchange_account: &ExchangeAccount,
     sny_asset: &Collateral,
@@ -190,6 +939,7 @@ mod tests {
     use std::{cell::RefCell, u64};
 
     use super::*;
+    use pyth::pc::PriceInfo;
     #[test]
     fn test_check_liquidation() {
         {
@@ -208,6 +958,1675 @@ mod tests {
         }
     }
     #[test]
+    fn test_check_feed_update() {
+        let slot = 100;
+        let assets = [
+            Asset {
+                last_update: 95,
+                ..Default::default()
+            },
+            Asset {
+                last_update: 80,
+                ..Default::default()
+            },
+        ];
+        assert!(check_feed_update(&assets, 0, 0, 10, slot).is_ok());
+        assert!(check_feed_update(&assets, 0, 1, 10, slot).is_err());
+        assert!(check_feed_update(&assets, 1, 0, 10, slot).is_err());
+        assert!(check_feed_update(&assets, 1, 1, 20, slot).is_ok());
+        // max_delay longer than chain history
+        assert!(check_feed_update(&assets, 0, 1, 1000, slot).is_ok());
+
+        // Asset tolerance overrides global max_delay
+        let assets = [
+            Asset {
+                last_update: 95,
+                max_delay: 1,
+                ..Default::default()
+            },
+            Asset {
+                last_update: 80,
+                max_delay: 50,
+                ..Default::default()
+            },
+        ];
+        assert!(check_feed_update(&assets, 1, 1, 10, slot).is_ok());
+        assert!(check_feed_update(&assets, 0, 1, 10, slot).is_err());
+        assert!(check_feed_update(&assets, 1, 0, 1000, slot).is_err());
+    }
+    #[test]
+    fn test_check_feed_confidence() {
+        let assets = [
+            Asset {
+                confidence: 100,
+                max_confidence: 1000,
+                ..Default::default()
+            },
+            Asset {
+                confidence: 1000,
+                max_confidence: 1000,
+                ..Default::default()
+            },
+            Asset {
+                confidence: 1001,
+                max_confidence: 1000,
+                ..Default::default()
+            },
+        ];
+        // Both prices precise enough
+        assert!(check_feed_confidence(&assets, 0, 1).is_ok());
+        // One of prices is too uncertain
+        assert!(check_feed_confidence(&assets, 0, 2).is_err());
+        assert!(check_feed_confidence(&assets, 2, 1).is_err());
+    }
+    #[test]
+    fn test_check_account_confidence() {
+        let mut assets_list = AssetsList {
+            ..Default::default()
+        };
+        // xUSD
+        assets_list.append_asset(Asset {
+            confidence: 0,
+            max_confidence: u32::MAX,
+            ..Default::default()
+        });
+        assets_list.append_synthetic(Synthetic {
+            asset_index: 0,
+            ..Default::default()
+        });
+        // SNY
+        assets_list.append_asset(Asset {
+            confidence: 5000,
+            max_confidence: 10000,
+            ..Default::default()
+        });
+        assets_list.append_collateral(Collateral {
+            asset_index: 1,
+            ..Default::default()
+        });
+        // BTC
+        assets_list.append_asset(Asset {
+            confidence: 20000,
+            max_confidence: 10000,
+            ..Default::default()
+        });
+        assets_list.append_collateral(Collateral {
+            asset_index: 2,
+            ..Default::default()
+        });
+        {
+            // No collaterals
+            let exchange_account = ExchangeAccount {
+                ..Default::default()
+            };
+            assert!(check_account_confidence(&exchange_account, &assets_list).is_ok());
+        }
+        {
+            // Precise collateral
+            let mut exchange_account = ExchangeAccount {
+                ..Default::default()
+            };
+            exchange_account.append(CollateralEntry {
+                index: 0,
+                ..Default::default()
+            });
+            assert!(check_account_confidence(&exchange_account, &assets_list).is_ok());
+        }
+        {
+            // Uncertain collateral
+            let mut exchange_account = ExchangeAccount {
+                ..Default::default()
+            };
+            exchange_account.append(CollateralEntry {
+                index: 0,
+                ..Default::default()
+            });
+            exchange_account.append(CollateralEntry {
+                index: 1,
+                ..Default::default()
+            });
+            assert!(check_account_confidence(&exchange_account, &assets_list).is_err());
+        }
+        {
+            // Uncertain synthetic
+            assets_list.append_synthetic(Synthetic {
+                asset_index: 2,
+                ..Default::default()
+            });
+            let exchange_account = ExchangeAccount {
+                ..Default::default()
+            };
+            assert!(check_account_confidence(&exchange_account, &assets_list).is_err());
+        }
+    }
+    #[test]
+    fn test_check_feed_frozen() {
+        let assets = [
+            Asset {
+                ..Default::default()
+            },
+            Asset {
+                ..Default::default()
+            },
+            Asset {
+                frozen: true,
+                ..Default::default()
+            },
+        ];
+        assert!(check_feed_frozen(&assets, 0, 1).is_ok());
+        assert!(check_feed_frozen(&assets, 0, 2).is_err());
+        assert!(check_feed_frozen(&assets, 2, 1).is_err());
+    }
+    #[test]
+    fn test_check_collaterals_frozen() {
+        let mut assets_list = AssetsList {
+            ..Default::default()
+        };
+        assets_list.append_asset(Asset {
+            ..Default::default()
+        });
+        assets_list.append_collateral(Collateral {
+            asset_index: 0,
+            ..Default::default()
+        });
+        assets_list.append_asset(Asset {
+            frozen: true,
+            ..Default::default()
+        });
+        assets_list.append_collateral(Collateral {
+            asset_index: 1,
+            ..Default::default()
+        });
+
+        let mut exchange_account = ExchangeAccount {
+            ..Default::default()
+        };
+        exchange_account.append(CollateralEntry {
+            index: 0,
+            ..Default::default()
+        });
+        assert!(check_collaterals_frozen(&exchange_account, &assets_list).is_ok());
+        // Frozen collateral
+        exchange_account.append(CollateralEntry {
+            index: 1,
+            ..Default::default()
+        });
+        assert!(check_collaterals_frozen(&exchange_account, &assets_list).is_err());
+    }
+    #[test]
+    fn test_check_collaterals_update() {
+        let slot = 100;
+        let mut assets_list = AssetsList {
+            ..Default::default()
+        };
+        assets_list.append_asset(Asset {
+            last_update: slot - 10,
+            ..Default::default()
+        });
+        assets_list.append_collateral(Collateral {
+            asset_index: 0,
+            ..Default::default()
+        });
+        assets_list.append_asset(Asset {
+            last_update: slot - 50,
+            ..Default::default()
+        });
+        assets_list.append_collateral(Collateral {
+            asset_index: 1,
+            ..Default::default()
+        });
+        // Outdated asset not used by account
+        assets_list.append_asset(Asset {
+            last_update: 0,
+            ..Default::default()
+        });
+
+        let mut exchange_account = ExchangeAccount {
+            ..Default::default()
+        };
+        exchange_account.append(CollateralEntry {
+            index: 0,
+            ..Default::default()
+        });
+        assert!(check_collaterals_update(&exchange_account, &assets_list, 20, slot).is_ok());
+        exchange_account.append(CollateralEntry {
+            index: 1,
+            ..Default::default()
+        });
+        assert!(check_collaterals_update(&exchange_account, &assets_list, 100, slot).is_ok());
+        // Outdated collateral
+        assert!(check_collaterals_update(&exchange_account, &assets_list, 20, slot).is_err());
+    }
+    #[test]
+    fn test_check_debt_ceiling() {
+        let state = State {
+            debt: 900,
+            debt_ceiling: 1000,
+            ..Default::default()
+        };
+        assert!(check_debt_ceiling(&state, 100).is_ok());
+        assert!(matches!(
+            check_debt_ceiling(&state, 101),
+            Err(Error::ErrorCode(ErrorCode::DebtCeiling))
+        ));
+        // No ceiling
+        let state = State {
+            debt: 900,
+            debt_ceiling: u64::MAX,
+            ..Default::default()
+        };
+        assert!(check_debt_ceiling(&state, u64::MAX - 900).is_ok());
+        assert!(check_debt_ceiling(&state, u64::MAX).is_err());
+    }
+    #[test]
+    fn test_check_account_debt() {
+        let state = State {
+            min_account_debt: 10 * 10u64.pow(6),
+            max_account_debt: 1000 * 10u64.pow(6),
+            ..Default::default()
+        };
+        assert!(!is_dust_debt(&state, 0));
+        assert!(is_dust_debt(&state, 1));
+        assert!(!is_dust_debt(&state, 10 * 10u64.pow(6)));
+
+        assert!(check_account_debt(&state, 0).is_ok());
+        assert!(check_account_debt(&state, 10 * 10u64.pow(6)).is_ok());
+        assert!(check_account_debt(&state, 1000 * 10u64.pow(6)).is_ok());
+        assert!(matches!(
+            check_account_debt(&state, 10 * 10u64.pow(6) - 1),
+            Err(Error::ErrorCode(ErrorCode::AccountDebtDust))
+        ));
+        assert!(matches!(
+            check_account_debt(&state, 1000 * 10u64.pow(6) + 1),
+            Err(Error::ErrorCode(ErrorCode::AccountDebtLimit))
+        ));
+    }
+    #[test]
+    fn test_update_synthetic_supply() {
+        let mut state = State {
+            debt: 1000 * 10u128.pow(6),
+            ..Default::default()
+        };
+        let asset = Asset {
+            price: 2 * 10u64.pow(PRICE_OFFSET.into()),
+            ..Default::default()
+        };
+        let mut synthetic = Synthetic {
+            supply: 100 * 10u64.pow(8),
+            max_supply: 200 * 10u64.pow(8),
+            decimals: 8,
+            ..Default::default()
+        };
+        // Debt of synthetic 200 -> 300
+        let new_supply = 150 * 10u64.pow(8);
+        assert!(update_synthetic_supply(&mut state, &asset, &mut synthetic, new_supply).is_ok());
+        assert_eq!({ synthetic.supply }, new_supply);
+        assert_eq!({ state.debt }, 1100 * 10u128.pow(6));
+        // Debt of synthetic 300 -> 0
+        assert!(update_synthetic_supply(&mut state, &asset, &mut synthetic, 0).is_ok());
+        assert_eq!({ state.debt }, 800 * 10u128.pow(6));
+        // Over max supply leaves debt unchanged
+        let new_supply = 201 * 10u64.pow(8);
+        assert!(update_synthetic_supply(&mut state, &asset, &mut synthetic, new_supply).is_err());
+        assert_eq!({ synthetic.supply }, 0);
+        assert_eq!({ state.debt }, 800 * 10u128.pow(6));
+    }
+    #[test]
+    fn test_adjust_stability_fee() {
+        let mut state = State {
+            debt: 1_000_000 * 10u128.pow(6),
+            stability_fee: 10000,
+            stability_fee_index: 10u128.pow(18),
+            stability_fee_slot: 100,
+            ..Default::default()
+        };
+        let mut assets_list = AssetsList::default();
+        assets_list.assets[0].price = 10u64.pow(PRICE_OFFSET.into());
+        assets_list.synthetics[0] = Synthetic {
+            supply: 1_000_000 * 10u64.pow(6),
+            max_supply: u64::MAX,
+            decimals: 6,
+            ..Default::default()
+        };
+        // Nothing accrues within slot
+        assert!(adjust_stability_fee(&mut state, &mut assets_list, 100).is_ok());
+        assert_eq!({ state.accumulated_stability_fee }, 0);
+        // 10% yearly over one slot
+        assert!(adjust_stability_fee(&mut state, &mut assets_list, 101).is_ok());
+        let fee = 1_585u64;
+        assert_eq!({ state.accumulated_stability_fee }, fee);
+        assert_eq!(
+            { assets_list.synthetics[0].supply },
+            1_000_000 * 10u64.pow(6) + fee
+        );
+        assert_eq!({ state.debt }, 1_000_000 * 10u128.pow(6) + fee as u128);
+        assert_eq!(
+            { state.stability_fee_index },
+            10u128.pow(18) + 1_585_489_599
+        );
+        assert_eq!({ state.stability_fee_slot }, 101);
+        // Fee too small to mint is not lost
+        state.debt = 100;
+        assert!(adjust_stability_fee(&mut state, &mut assets_list, 102).is_ok());
+        assert_eq!({ state.stability_fee_slot }, 101);
+        assert_eq!(
+            { state.stability_fee_index },
+            10u128.pow(18) + 1_585_489_599
+        );
+        assert_eq!({ state.accumulated_stability_fee }, fee);
+        // Without fee only slot moves
+        state.stability_fee = 0;
+        assert!(adjust_stability_fee(&mut state, &mut assets_list, 200).is_ok());
+        assert_eq!({ state.stability_fee_slot }, 200);
+        assert_eq!(
+            { state.stability_fee_index },
+            10u128.pow(18) + 1_585_489_599
+        );
+        assert_eq!({ state.accumulated_stability_fee }, fee);
+        // Max fee over years of no accrual charges every year
+        state.debt = 1_000_000 * 10u128.pow(6);
+        state.stability_fee = 100000;
+        state.stability_fee_index = 10u128.pow(18);
+        state.accumulated_stability_fee = 0;
+        let slot = 200 + 5 * SLOTS_PER_YEAR + 1000;
+        assert!(adjust_stability_fee(&mut state, &mut assets_list, slot).is_ok());
+        assert_eq!({ state.stability_fee_slot }, slot);
+        // 100% yearly compounded every slot ~ e^5 - 1
+        let fee = { state.accumulated_stability_fee };
+        assert!(fee > 147_415_000 * 10u64.pow(6));
+        assert!(fee < 147_416_000 * 10u64.pow(6));
+        let index = { state.stability_fee_index };
+        assert!(index > 148_415 * 10u128.pow(15));
+        assert!(index < 148_416 * 10u128.pow(15));
+    }
+    #[test]
+    fn test_check_mintable_synthetic() {
+        let slot = 100;
+        let max_delay = 10;
+        let fresh = Asset {
+            price: 2 * 10u64.pow(PRICE_OFFSET.into()),
+            last_update: slot,
+            ..Default::default()
+        };
+        let assets = [
+            fresh,
+            // Derived price updated with its inputs
+            Asset {
+                asset_kind: ASSET_KIND_RATIO,
+                ..fresh
+            },
+            // Derived price never calculated
+            Asset {
+                asset_kind: ASSET_KIND_PRODUCT,
+                price: 0,
+                last_update: 0,
+                ..fresh
+            },
+            // Derived from not trading input
+            Asset {
+                asset_kind: ASSET_KIND_INVERSE_SYNTHETIC,
+                frozen: true,
+                ..fresh
+            },
+            Asset {
+                in_breaker: true,
+                ..fresh
+            },
+        ];
+        let synthetic = |asset_index: u8| Synthetic {
+            asset_index,
+            settlement_slot: u64::MAX,
+            ..Default::default()
+        };
+        assert!(check_mintable_synthetic(&assets, &synthetic(0), max_delay, slot).is_ok());
+        assert!(check_mintable_synthetic(&assets, &synthetic(1), max_delay, slot).is_ok());
+        assert!(matches!(
+            check_mintable_synthetic(&assets, &synthetic(2), max_delay, slot),
+            Err(Error::ErrorCode(ErrorCode::OutdatedOracle))
+        ));
+        assert!(matches!(
+            check_mintable_synthetic(&assets, &synthetic(3), max_delay, slot),
+            Err(Error::ErrorCode(ErrorCode::FrozenAsset))
+        ));
+        assert!(matches!(
+            check_mintable_synthetic(&assets, &synthetic(4), max_delay, slot),
+            Err(Error::ErrorCode(ErrorCode::PriceBreaker))
+        ));
+        // Outdated price
+        let later_slot = slot + max_delay as u64 + 1;
+        assert!(matches!(
+            check_mintable_synthetic(&assets, &synthetic(0), max_delay, later_slot),
+            Err(Error::ErrorCode(ErrorCode::OutdatedOracle))
+        ));
+        // Settled synthetic
+        let settled = Synthetic {
+            settlement_slot: slot,
+            ..synthetic(0)
+        };
+        assert!(matches!(
+            check_mintable_synthetic(&assets, &settled, max_delay, slot),
+            Err(Error::ErrorCode(ErrorCode::SettledSynthetic))
+        ));
+    }
+    #[test]
+    fn test_check_swap_synthetics() {
+        let slot = 100;
+        let max_delay = 10;
+        let fresh = Asset {
+            price: 2 * 10u64.pow(PRICE_OFFSET.into()),
+            last_update: slot,
+            ..Default::default()
+        };
+        let assets = [
+            fresh,
+            fresh,
+            // Asset of settled synthetic no longer updated
+            Asset {
+                last_update: 0,
+                frozen: true,
+                ..fresh
+            },
+        ];
+        let synthetic = |asset_index: u8| Synthetic {
+            asset_index,
+            settlement_slot: u64::MAX,
+            ..Default::default()
+        };
+        let settled_unpriced = Synthetic {
+            settlement_slot: slot - 1,
+            ..synthetic(2)
+        };
+        let settled = Synthetic {
+            settlement_price: 3 * 10u64.pow(PRICE_OFFSET.into()),
+            ..settled_unpriced
+        };
+        let usd = synthetic(0);
+        assert!(check_swap_synthetics(&assets, &usd, &synthetic(1), max_delay, slot).is_ok());
+        // Settled synthetic cannot be swapped for
+        assert!(matches!(
+            check_swap_synthetics(&assets, &usd, &settled, max_delay, slot),
+            Err(Error::ErrorCode(ErrorCode::SettledSynthetic))
+        ));
+        assert!(matches!(
+            check_swap_synthetics(&assets, &usd, &settled_unpriced, max_delay, slot),
+            Err(Error::ErrorCode(ErrorCode::SettledSynthetic))
+        ));
+        // Swapped out at frozen price regardless of its asset
+        assert!(check_swap_synthetics(&assets, &settled, &usd, max_delay, slot).is_ok());
+        // Without frozen price asset has to be trusted
+        assert!(matches!(
+            check_swap_synthetics(&assets, &settled_unpriced, &usd, max_delay, slot),
+            Err(Error::ErrorCode(ErrorCode::OutdatedOracle))
+        ));
+        // Asset swapped for is still checked
+        let later_slot = slot + max_delay as u64 + 1;
+        assert!(matches!(
+            check_swap_synthetics(&assets, &settled, &usd, max_delay, later_slot),
+            Err(Error::ErrorCode(ErrorCode::OutdatedOracle))
+        ));
+    }
+    #[test]
+    fn test_convert_settled_synthetic() {
+        use crate::math::calculate_synthetic_debt;
+        let slot = 100;
+        let assets = [
+            Asset {
+                price: 10u64.pow(PRICE_OFFSET.into()),
+                ..Default::default()
+            },
+            // Asset moved on since settlement
+            Asset {
+                price: 7 * 10u64.pow(PRICE_OFFSET.into()),
+                ..Default::default()
+            },
+        ];
+        let mut synthetics = [
+            Synthetic {
+                asset_index: 0,
+                supply: 1000 * 10u64.pow(6),
+                max_supply: u64::MAX,
+                decimals: 6,
+                settlement_slot: u64::MAX,
+                ..Default::default()
+            },
+            Synthetic {
+                asset_index: 1,
+                supply: 10 * 10u64.pow(6),
+                max_supply: u64::MAX,
+                decimals: 6,
+                settlement_slot: slot,
+                settlement_price: 3 * 10u64.pow(PRICE_OFFSET.into()),
+                ..Default::default()
+            },
+        ];
+        let mut state = State {
+            debt: calculate_synthetic_debt(&assets[0], &synthetics[0])
+                + calculate_synthetic_debt(&assets[1], &synthetics[1]),
+            ..Default::default()
+        };
+        assert_eq!({ state.debt }, 1030 * 10u128.pow(6));
+
+        // 5 settled synthetics are worth 15 xUSD at frozen price
+        let amount = 5 * 10u64.pow(6);
+        let amount_usd =
+            convert_settled_synthetic(&mut state, &assets, &mut synthetics, 1, amount, slot)
+                .unwrap();
+        assert_eq!(amount_usd, 15 * 10u64.pow(6));
+        assert_eq!({ synthetics[0].supply }, 1015 * 10u64.pow(6));
+        assert_eq!({ synthetics[1].supply }, 5 * 10u64.pow(6));
+        // Debt moves to xUSD without changing
+        assert_eq!({ state.debt }, 1030 * 10u128.pow(6));
+
+        // xUSD itself is never converted
+        assert!(matches!(
+            convert_settled_synthetic(&mut state, &assets, &mut synthetics, 0, amount, slot),
+            Err(Error::ErrorCode(ErrorCode::NotSettledSynthetic))
+        ));
+        // Settlement planned in future
+        assert!(matches!(
+            convert_settled_synthetic(&mut state, &assets, &mut synthetics, 1, amount, slot - 1),
+            Err(Error::ErrorCode(ErrorCode::NotSettledSynthetic))
+        ));
+        // Conversion waits for frozen price
+        synthetics[1].settlement_price = 0;
+        assert!(matches!(
+            convert_settled_synthetic(&mut state, &assets, &mut synthetics, 1, amount, slot),
+            Err(Error::ErrorCode(ErrorCode::NotSettledSynthetic))
+        ));
+        assert_eq!({ synthetics[1].supply }, 5 * 10u64.pow(6));
+        assert_eq!({ state.debt }, 1030 * 10u128.pow(6));
+    }
+    #[test]
+    fn test_mint_non_usd_synthetic() {
+        use crate::math::{
+            calculate_debt_in_usd, calculate_new_shares_by_rounding_up, calculate_user_debt_in_usd,
+        };
+        // Other account owes 1000 xUSD
+        let initial_debt = 1000 * 10u128.pow(6);
+        let mut state = State {
+            debt: initial_debt,
+            debt_shares: initial_debt,
+            ..Default::default()
+        };
+        let other_account = ExchangeAccount {
+            debt_shares: initial_debt,
+            ..Default::default()
+        };
+        let asset = Asset {
+            price: 50000 * 10u64.pow(PRICE_OFFSET.into()),
+            ..Default::default()
+        };
+        let mut synthetic = Synthetic {
+            max_supply: 10u64.pow(8),
+            decimals: 8,
+            ..Default::default()
+        };
+        let mut exchange_account = ExchangeAccount::default();
+
+        // Mint 0.1 xBTC
+        let amount = 10u64.pow(7);
+        let amount_in_usd = calculate_debt_in_usd(&asset, &synthetic, amount);
+        assert_eq!(amount_in_usd, 5000 * 10u64.pow(6));
+        let new_shares =
+            calculate_new_shares_by_rounding_up(state.debt_shares, state.debt, amount_in_usd);
+        state.debt_shares += new_shares;
+        exchange_account.debt_shares += new_shares;
+        assert!(update_synthetic_supply(&mut state, &asset, &mut synthetic, amount).is_ok());
+
+        assert_eq!({ synthetic.supply }, amount);
+        assert_eq!({ state.debt }, 6000 * 10u128.pow(6));
+        // Same share accounting as minting xUSD worth the same
+        assert_eq!(new_shares, 5000 * 10u128.pow(6));
+        assert_eq!(
+            calculate_user_debt_in_usd(&exchange_account, state.debt, state.debt_shares),
+            5000 * 10u64.pow(6)
+        );
+        assert_eq!(
+            calculate_user_debt_in_usd(&other_account, state.debt, state.debt_shares),
+            1000 * 10u64.pow(6)
+        );
+        // Max supply of minted synthetic is respected
+        assert_eq!(
+            update_synthetic_supply(&mut state, &asset, &mut synthetic, 10u64.pow(8) + 1),
+            Err(ErrorCode::MaxSupply.into())
+        );
+        assert_eq!({ synthetic.supply }, amount);
+        assert_eq!({ state.debt }, 6000 * 10u128.pow(6));
+    }
+    #[test]
+    fn test_mint_and_burn_at_debt_price() {
+        use crate::math::{
+            calculate_burned_shares, calculate_debt_in_usd, calculate_max_burned_in_token,
+            calculate_new_shares_by_rounding_up, calculate_user_debt_in_usd,
+        };
+        let initial_debt = 1000 * 10u128.pow(6);
+        let mut state = State {
+            debt: initial_debt,
+            debt_shares: initial_debt,
+            ..Default::default()
+        };
+        // Twap below spot, debt is valued at twap
+        let asset = Asset {
+            price: 50000 * 10u64.pow(PRICE_OFFSET.into()),
+            twap: 40000 * 10u64.pow(PRICE_OFFSET.into()),
+            price_mode: PRICE_MODE_TWAP,
+            ..Default::default()
+        };
+        let mut synthetic = Synthetic {
+            max_supply: u64::MAX,
+            decimals: 8,
+            ..Default::default()
+        };
+        let mut exchange_account = ExchangeAccount::default();
+        let amount = 10u64.pow(8);
+
+        // Mint
+        let amount_in_usd = calculate_debt_in_usd(&asset, &synthetic, amount);
+        let new_shares =
+            calculate_new_shares_by_rounding_up(state.debt_shares, state.debt, amount_in_usd);
+        state.debt_shares += new_shares;
+        exchange_account.debt_shares = new_shares;
+        assert!(update_synthetic_supply(&mut state, &asset, &mut synthetic, amount).is_ok());
+
+        // Burn whole minted amount
+        let user_debt =
+            calculate_user_debt_in_usd(&exchange_account, state.debt, state.debt_shares);
+        assert_eq!(user_debt, 40000 * 10u64.pow(6));
+        let burned_shares = calculate_burned_shares(
+            &asset,
+            &synthetic,
+            user_debt,
+            exchange_account.debt_shares,
+            amount,
+        );
+        assert_eq!(burned_shares, { exchange_account.debt_shares });
+        // Full repay takes back all minted tokens
+        assert_eq!(
+            calculate_max_burned_in_token(&asset, &synthetic, user_debt),
+            amount
+        );
+        state.debt_shares -= burned_shares;
+        exchange_account.debt_shares -= burned_shares;
+        assert!(update_synthetic_supply(&mut state, &asset, &mut synthetic, 0).is_ok());
+
+        let user_debt =
+            calculate_user_debt_in_usd(&exchange_account, state.debt, state.debt_shares);
+        assert_eq!(user_debt, 0);
+        assert_eq!({ state.debt }, initial_debt);
+        assert_eq!({ state.debt_shares }, initial_debt);
+    }
+    #[test]
+    fn test_swap_and_burn_at_spot() {
+        use crate::math::{
+            calculate_burned_in_usd, calculate_burned_shares, calculate_user_debt_in_usd,
+        };
+        let usd_asset = Asset {
+            price: 10u64.pow(PRICE_OFFSET.into()),
+            ..Default::default()
+        };
+        // 10% confidence, debt price 55000
+        let btc_asset = Asset {
+            price: 50000 * 10u64.pow(PRICE_OFFSET.into()),
+            confidence: 100_000,
+            confidence_multiplier: 100,
+            ..Default::default()
+        };
+        let mut usd_synthetic = Synthetic {
+            max_supply: u64::MAX,
+            supply: 51000 * 10u64.pow(6),
+            decimals: 6,
+            ..Default::default()
+        };
+        let mut btc_synthetic = Synthetic {
+            max_supply: u64::MAX,
+            decimals: 8,
+            ..Default::default()
+        };
+        // Other staker owes 1000, user 50000 held as xUSD
+        let other_shares = 1000 * 10u128.pow(6);
+        let mut state = State {
+            debt: 51000 * 10u128.pow(6),
+            debt_shares: 51000 * 10u128.pow(6),
+            ..Default::default()
+        };
+        let mut exchange_account = ExchangeAccount {
+            debt_shares: 50000 * 10u128.pow(6),
+            ..Default::default()
+        };
+
+        // Swap all xUSD at spot
+        let amount_in = 50000 * 10u64.pow(6);
+        let amount_out = calculate_swap_out_amount(
+            &usd_asset,
+            &btc_asset,
+            &usd_synthetic,
+            &btc_synthetic,
+            amount_in,
+            0,
+        );
+        assert_eq!(amount_out, 10u64.pow(8));
+        let new_supply = usd_synthetic.supply - amount_in;
+        assert!(
+            update_synthetic_supply(&mut state, &usd_asset, &mut usd_synthetic, new_supply).is_ok()
+        );
+        assert!(
+            update_synthetic_supply(&mut state, &btc_asset, &mut btc_synthetic, amount_out).is_ok()
+        );
+
+        // Burn repays no more than xUSD given for synthetic
+        let burned_in_usd = calculate_burned_in_usd(&btc_asset, &btc_synthetic, amount_out);
+        assert_eq!(burned_in_usd, amount_in);
+        let user_debt =
+            calculate_user_debt_in_usd(&exchange_account, state.debt, state.debt_shares);
+        let burned_shares = calculate_burned_shares(
+            &btc_asset,
+            &btc_synthetic,
+            user_debt,
+            exchange_account.debt_shares,
+            amount_out,
+        );
+        assert!(burned_shares < { exchange_account.debt_shares });
+        state.debt_shares -= burned_shares;
+        exchange_account.debt_shares -= burned_shares;
+        assert!(update_synthetic_supply(&mut state, &btc_asset, &mut btc_synthetic, 0).is_ok());
+
+        // No debt is moved onto other staker
+        let other_account = ExchangeAccount {
+            debt_shares: other_shares,
+            ..Default::default()
+        };
+        let other_debt = calculate_user_debt_in_usd(&other_account, state.debt, state.debt_shares);
+        assert!(other_debt <= 1000 * 10u64.pow(6));
+        let user_debt =
+            calculate_user_debt_in_usd(&exchange_account, state.debt, state.debt_shares);
+        assert!(user_debt > 0);
+    }
+    #[test]
+    fn test_mint_and_burn_after_admin_price_write() {
+        use crate::math::{
+            calculate_burned_shares, calculate_debt_in_usd, calculate_new_shares_by_rounding_up,
+            calculate_stored_debt, calculate_user_debt_in_usd,
+        };
+        let mut assets_list = AssetsList::default();
+        assets_list.append_asset(Asset {
+            price: 10u64.pow(PRICE_OFFSET.into()),
+            ..Default::default()
+        });
+        assets_list.append_asset(Asset {
+            price: 50000 * 10u64.pow(PRICE_OFFSET.into()),
+            twap: 30000 * 10u64.pow(PRICE_OFFSET.into()),
+            ..Default::default()
+        });
+        assets_list.append_synthetic(Synthetic {
+            max_supply: u64::MAX,
+            supply: 1000 * 10u64.pow(6),
+            decimals: 6,
+            asset_index: 0,
+            ..Default::default()
+        });
+        assets_list.append_synthetic(Synthetic {
+            max_supply: u64::MAX,
+            supply: 10u64.pow(8),
+            decimals: 8,
+            asset_index: 1,
+            ..Default::default()
+        });
+        let debt = calculate_stored_debt(&assets_list);
+        assert_eq!(debt, 51000 * 10u128.pow(6));
+        let mut state = State {
+            debt,
+            debt_shares: debt,
+            ..Default::default()
+        };
+        let mut exchange_account = ExchangeAccount {
+            debt_shares: debt,
+            ..Default::default()
+        };
+
+        // Admin price write moves cached debt
+        {
+            let (assets, _, synthetics) = assets_list.split_borrow();
+            revalue_asset(&mut state, &mut assets[1], 1, &synthetics[..2], |asset| {
+                asset.price = 40000 * 10u64.pow(PRICE_OFFSET.into());
+            });
+        }
+        assert_eq!({ state.debt }, 41000 * 10u128.pow(6));
+        assert_eq!({ state.debt }, calculate_stored_debt(&assets_list));
+        // So does change of price mode
+        {
+            let (assets, _, synthetics) = assets_list.split_borrow();
+            revalue_asset(&mut state, &mut assets[1], 1, &synthetics[..2], |asset| {
+                asset.price_mode = PRICE_MODE_CONSERVATIVE;
+            });
+            revalue_asset(&mut state, &mut assets[1], 1, &synthetics[..2], |asset| {
+                asset.price_mode = PRICE_MODE_TWAP;
+            });
+        }
+        assert_eq!({ state.debt }, 31000 * 10u128.pow(6));
+        assert_eq!({ state.debt }, calculate_stored_debt(&assets_list));
+
+        // Mint 1000 xUSD
+        let amount = 1000 * 10u64.pow(6);
+        let (assets, _, synthetics) = assets_list.split_borrow();
+        let amount_in_usd = calculate_debt_in_usd(&assets[0], &synthetics[0], amount);
+        let new_shares =
+            calculate_new_shares_by_rounding_up(state.debt_shares, state.debt, amount_in_usd);
+        state.debt_shares += new_shares;
+        exchange_account.debt_shares += new_shares;
+        let new_supply = synthetics[0].supply + amount;
+        assert!(
+            update_synthetic_supply(&mut state, &assets[0], &mut synthetics[0], new_supply).is_ok()
+        );
+        let user_debt =
+            calculate_user_debt_in_usd(&exchange_account, state.debt, state.debt_shares);
+        assert_eq!(user_debt, 32000 * 10u64.pow(6));
+
+        // Burn half of xBTC at 30000
+        let amount = 10u64.pow(8) / 2;
+        let burned_shares = calculate_burned_shares(
+            &assets[1],
+            &synthetics[1],
+            user_debt,
+            exchange_account.debt_shares,
+            amount,
+        );
+        state.debt_shares -= burned_shares;
+        exchange_account.debt_shares -= burned_shares;
+        let new_supply = synthetics[1].supply - amount;
+        assert!(
+            update_synthetic_supply(&mut state, &assets[1], &mut synthetics[1], new_supply).is_ok()
+        );
+        let user_debt =
+            calculate_user_debt_in_usd(&exchange_account, state.debt, state.debt_shares);
+        assert_eq!(user_debt, 17000 * 10u64.pow(6));
+        assert_eq!({ state.debt }, calculate_stored_debt(&assets_list));
+    }
+    #[test]
+    fn test_check_price_header() {
+        let valid = Price {
+            magic: MAGIC,
+            ver: VERSION,
+            atype: AccountType::Price as u32,
+            ptype: PriceType::Price as u32,
+            ..Default::default()
+        };
+        assert!(check_price_header(&valid).is_ok());
+        // Uninitialized account
+        assert!(check_price_header(&Price::default()).is_err());
+        // Wrong magic
+        assert!(check_price_header(&Price { magic: 0, ..valid }).is_err());
+        // Wrong version
+        assert!(check_price_header(&Price { ver: 2, ..valid }).is_err());
+        // Product account
+        let product = Price {
+            atype: AccountType::Product as u32,
+            ..valid
+        };
+        assert!(check_price_header(&product).is_err());
+        // Not a price
+        let twap = Price {
+            ptype: PriceType::TWAP as u32,
+            ..valid
+        };
+        assert!(check_price_header(&twap).is_err());
+        // Unknown price type read from account data
+        assert!(check_price_header(&Price { ptype: 7, ..valid }).is_err());
+    }
+    #[test]
+    fn test_read_feed_price() {
+        let feed = Price {
+            expo: -4,
+            twap: 2_0000,
+            valid_slot: 10,
+            agg: PriceInfo {
+                price: 2_0000,
+                conf: 200,
+                status: PriceStatus::Trading as u32,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            read_feed_price(&feed).unwrap(),
+            FeedPrice {
+                price: 2_000000,
+                twap: 2_000000,
+                confidence: 10_000,
+                valid_slot: 10,
+                trading: true
+            }
+        );
+        // Not trading feed is not scaled
+        let halted = Price {
+            agg: PriceInfo {
+                price: -1,
+                status: PriceStatus::Halted as u32,
+                ..feed.agg
+            },
+            ..feed
+        };
+        assert_eq!(
+            read_feed_price(&halted).unwrap(),
+            FeedPrice {
+                valid_slot: 10,
+                ..Default::default()
+            }
+        );
+        // Unknown status read from account data is not trading
+        let unknown = Price {
+            agg: PriceInfo {
+                status: 7,
+                ..feed.agg
+            },
+            ..feed
+        };
+        assert!(!read_feed_price(&unknown).unwrap().trading);
+        // Negative price
+        let negative = Price {
+            agg: PriceInfo {
+                price: -2_0000,
+                ..feed.agg
+            },
+            ..feed
+        };
+        assert!(matches!(
+            read_feed_price(&negative),
+            Err(Error::ErrorCode(ErrorCode::NegativePrice))
+        ));
+        // Price rounded down to zero
+        let zero = Price { expo: -12, ..feed };
+        assert!(matches!(
+            read_feed_price(&zero),
+            Err(Error::ErrorCode(ErrorCode::ZeroPrice))
+        ));
+        // Negative twap
+        let negative_twap = Price {
+            twap: -2_0000,
+            ..feed
+        };
+        assert!(matches!(
+            read_feed_price(&negative_twap),
+            Err(Error::ErrorCode(ErrorCode::NegativePrice))
+        ));
+        // Price out of range
+        let overflow = Price { expo: 20, ..feed };
+        assert!(matches!(
+            read_feed_price(&overflow),
+            Err(Error::ErrorCode(ErrorCode::PriceOverflow))
+        ));
+    }
+    #[test]
+    fn test_check_asset_kind() {
+        assert!(check_asset_kind(ASSET_KIND_PRODUCT, 2, &[]).is_ok());
+        assert!(check_asset_kind(ASSET_KIND_RATIO, 2, &[]).is_ok());
+        assert!(check_asset_kind(ASSET_KIND_INVERSE, 1, &[]).is_ok());
+        assert!(check_asset_kind(ASSET_KIND_INVERSE_SYNTHETIC, 1, &[]).is_ok());
+        assert!(check_asset_kind(ASSET_KIND_BASKET, 3, &[1, 2, 3]).is_ok());
+        // Wrong number of inputs
+        assert!(check_asset_kind(ASSET_KIND_RATIO, 1, &[]).is_err());
+        assert!(check_asset_kind(ASSET_KIND_INVERSE, 2, &[]).is_err());
+        assert!(check_asset_kind(ASSET_KIND_BASKET, 5, &[1, 1, 1, 1, 1]).is_err());
+        // Wrong weights
+        assert!(check_asset_kind(ASSET_KIND_PRODUCT, 2, &[1, 1]).is_err());
+        assert!(check_asset_kind(ASSET_KIND_BASKET, 2, &[1]).is_err());
+        assert!(check_asset_kind(ASSET_KIND_BASKET, 2, &[1, 0]).is_err());
+        // Not derived
+        assert!(check_asset_kind(ASSET_KIND_FEED, 1, &[]).is_err());
+        assert!(check_asset_kind(6, 1, &[]).is_err());
+    }
+    #[test]
+    fn test_derive_feed_price() {
+        let eth = FeedPrice {
+            price: 2_000_000_000,
+            twap: 1_900_000_000,
+            confidence: 1_000,
+            valid_slot: 90,
+            trading: true,
+        };
+        let btc = FeedPrice {
+            price: 40_000_000_000,
+            twap: 38_000_000_000,
+            confidence: 2_000,
+            valid_slot: 95,
+            trading: true,
+        };
+        let derived = |asset_kind| Asset {
+            asset_kind,
+            ..Default::default()
+        };
+        // ETH/BTC
+        {
+            let feed_price = derive_feed_price(&derived(ASSET_KIND_RATIO), &[eth, btc]).unwrap();
+            assert_eq!(
+                feed_price,
+                FeedPrice {
+                    price: 50_000,
+                    twap: 50_000,
+                    confidence: 3_000,
+                    valid_slot: 90,
+                    trading: true,
+                }
+            );
+        }
+        // ETH*BTC
+        {
+            let feed_price = derive_feed_price(&derived(ASSET_KIND_PRODUCT), &[eth, btc]).unwrap();
+            assert_eq!({ feed_price.price }, 80_000_000_000_000);
+            assert_eq!({ feed_price.confidence }, 3_000);
+        }
+        // 1/ETH
+        {
+            let feed_price = derive_feed_price(&derived(ASSET_KIND_INVERSE), &[eth]).unwrap();
+            assert_eq!({ feed_price.price }, 500);
+            assert_eq!({ feed_price.confidence }, 1_000);
+        }
+        // 0.5 BTC + 10 ETH
+        {
+            let basket = Asset {
+                input_weights: [500_000, 10_000_000, 0, 0],
+                ..derived(ASSET_KIND_BASKET)
+            };
+            let feed_price = derive_feed_price(&basket, &[btc, eth]).unwrap();
+            assert_eq!({ feed_price.price }, 40_000_000_000);
+            assert_eq!({ feed_price.twap }, 38_000_000_000);
+            assert_eq!({ feed_price.confidence }, 1_500);
+        }
+        // Input not trading
+        {
+            let halted = FeedPrice {
+                valid_slot: 80,
+                ..Default::default()
+            };
+            let feed_price = derive_feed_price(&derived(ASSET_KIND_RATIO), &[eth, halted]).unwrap();
+            assert!(!feed_price.trading);
+            assert_eq!({ feed_price.valid_slot }, 80);
+        }
+        // Price below precision
+        {
+            let tiny = FeedPrice { price: 1, ..eth };
+            let result = derive_feed_price(&derived(ASSET_KIND_RATIO), &[tiny, btc]);
+            assert!(matches!(
+                result,
+                Err(Error::ErrorCode(ErrorCode::ZeroPrice))
+            ));
+        }
+    }
+    #[test]
+    fn test_settle_asset() {
+        let slot = 100;
+        let asset = Asset {
+            price: 20,
+            lower_bound: 20,
+            upper_bound: 60,
+            ..Default::default()
+        };
+        let mut synthetics = [
+            Synthetic {
+                asset_index: 0,
+                settlement_slot: u64::MAX,
+                ..Default::default()
+            },
+            Synthetic {
+                asset_index: 1,
+                settlement_slot: u64::MAX,
+                ..Default::default()
+            },
+            Synthetic {
+                asset_index: 1,
+                settlement_slot: 50,
+                ..Default::default()
+            },
+        ];
+        assert!(is_price_band_hit(&asset));
+        assert!(!is_settled(&synthetics[1], slot));
+
+        settle_asset(&mut synthetics, 1, slot);
+        assert_eq!({ synthetics[0].settlement_slot }, u64::MAX);
+        assert_eq!({ synthetics[1].settlement_slot }, slot);
+        assert!(is_settled(&synthetics[1], slot));
+        // Already settled synthetic keeps its settlement
+        assert_eq!({ synthetics[2].settlement_slot }, 50);
+        // Settlement planned in future
+        assert!(!is_settled(&synthetics[1], slot - 1));
+
+        let mut asset = asset;
+        asset.price = 40;
+        assert!(!is_price_band_hit(&asset));
+        asset.price = 60;
+        assert!(is_price_band_hit(&asset));
+    }
+    #[test]
+    fn test_freeze_settlement_prices() {
+        let slot = 100;
+        let max_delay = 10;
+        let mut state = State {
+            max_delay,
+            ..Default::default()
+        };
+        let mut assets = [
+            Asset {
+                price: 30,
+                twap: 35,
+                last_update: slot,
+                ..Default::default()
+            },
+            Asset {
+                price: 40,
+                last_update: slot,
+                ..Default::default()
+            },
+        ];
+        let mut synthetics = [
+            Synthetic {
+                asset_index: 0,
+                settlement_slot: slot,
+                ..Default::default()
+            },
+            Synthetic {
+                asset_index: 1,
+                settlement_slot: slot + 1,
+                ..Default::default()
+            },
+        ];
+        // Outdated or unreliable price is not frozen
+        assets[0].last_update = slot - max_delay as u64 - 1;
+        freeze_settlement_prices(&mut state, &assets, &mut synthetics, slot);
+        assert!(!is_settlement_priced(&synthetics[0]));
+        assets[0].last_update = slot;
+        assets[0].in_breaker = true;
+        freeze_settlement_prices(&mut state, &assets, &mut synthetics, slot);
+        assert!(!is_settlement_priced(&synthetics[0]));
+        assets[0].in_breaker = false;
+        assets[0].frozen = true;
+        freeze_settlement_prices(&mut state, &assets, &mut synthetics, slot);
+        assert!(!is_settlement_priced(&synthetics[0]));
+        // Fresh price is frozen only for settled synthetic
+        assets[0].frozen = false;
+        freeze_settlement_prices(&mut state, &assets, &mut synthetics, slot);
+        assert_eq!({ synthetics[0].settlement_price }, 30);
+        assert_eq!({ synthetics[1].settlement_price }, 0);
+        // Asset is left untouched and later prices don't move frozen one
+        assert_eq!({ assets[0].twap }, 35);
+        assets[0].price = 50;
+        freeze_settlement_prices(&mut state, &assets, &mut synthetics, slot + 1);
+        assert_eq!({ synthetics[0].settlement_price }, 30);
+        assert_eq!({ synthetics[1].settlement_price }, 40);
+    }
+    #[test]
+    fn test_append_price_entry() {
+        use anchor_lang::__private::bytemuck::Zeroable;
+        let mut price_history = PriceHistory::zeroed();
+        let mut asset = Asset {
+            price: 100,
+            confidence: 10,
+            last_update: 5,
+            ..Default::default()
+        };
+        assert!(append_price_entry(&mut price_history, &asset));
+        // same oracle price is not stored twice
+        assert!(!append_price_entry(&mut price_history, &asset));
+        assert_eq!(price_history.ordered_entries().len(), 1);
+
+        // overwrite oldest entries
+        for slot in 6..(PRICE_HISTORY_SIZE as u64 + 10) {
+            asset.last_update = slot;
+            asset.price = slot * 100;
+            assert!(append_price_entry(&mut price_history, &asset));
+        }
+        let entries = price_history.ordered_entries();
+        assert_eq!(entries.len(), PRICE_HISTORY_SIZE);
+        assert_eq!({ entries[0].slot }, 10);
+        assert_eq!(
+            { entries[PRICE_HISTORY_SIZE - 1].slot },
+            PRICE_HISTORY_SIZE as u64 + 9
+        );
+        assert_eq!(
+            price_history.last_entry(),
+            Some(PriceEntry {
+                slot: PRICE_HISTORY_SIZE as u64 + 9,
+                price: (PRICE_HISTORY_SIZE as u64 + 9) * 100,
+                confidence: 10,
+            })
+        );
+    }
+    #[test]
+    fn test_select_price_source() {
+        let slot = 100;
+        let max_delay = 10;
+        let asset = Asset {
+            price: 2_000_000,
+            max_divergence: 10_000,
+            ..Default::default()
+        };
+        let fresh = FeedPrice {
+            price: 2_010_000,
+            valid_slot: 95,
+            trading: true,
+            ..Default::default()
+        };
+        let outdated = FeedPrice {
+            valid_slot: 80,
+            ..fresh
+        };
+        let halted = FeedPrice {
+            trading: false,
+            ..fresh
+        };
+        let diverged = FeedPrice {
+            price: 2_100_000,
+            ..fresh
+        };
+        // Only primary
+        assert_eq!(
+            select_price_source(&asset, Some(fresh), None, max_delay, slot),
+            PriceSource::Primary(fresh)
+        );
+        // Outdated primary without fallback is still used
+        assert_eq!(
+            select_price_source(&asset, Some(outdated), None, max_delay, slot),
+            PriceSource::Primary(outdated)
+        );
+        // Fresh primary is preferred
+        assert_eq!(
+            select_price_source(&asset, Some(fresh), Some(fresh), max_delay, slot),
+            PriceSource::Primary(fresh)
+        );
+        // Outdated primary
+        assert_eq!(
+            select_price_source(&asset, Some(outdated), Some(fresh), max_delay, slot),
+            PriceSource::Secondary(fresh)
+        );
+        // Halted primary
+        assert_eq!(
+            select_price_source(&asset, Some(halted), Some(fresh), max_delay, slot),
+            PriceSource::Secondary(fresh)
+        );
+        // Primary left out doesn't switch to secondary
+        assert_eq!(
+            select_price_source(&asset, None, Some(fresh), max_delay, slot),
+            PriceSource::Skipped
+        );
+        // Divergence is checked against primary, not stored price
+        let moved = FeedPrice {
+            price: 3_000_000,
+            valid_slot: 80,
+            ..fresh
+        };
+        let close_to_moved = FeedPrice {
+            price: 3_010_000,
+            ..fresh
+        };
+        assert_eq!(
+            select_price_source(&asset, Some(moved), Some(close_to_moved), max_delay, slot),
+            PriceSource::Secondary(close_to_moved)
+        );
+        assert_eq!(
+            select_price_source(&asset, Some(moved), Some(fresh), max_delay, slot),
+            PriceSource::Primary(moved)
+        );
+        // Halted primary without last price cannot validate secondary
+        let unpriced = FeedPrice { price: 0, ..halted };
+        assert_eq!(
+            select_price_source(&asset, Some(unpriced), Some(fresh), max_delay, slot),
+            PriceSource::Unavailable
+        );
+        // Secondary diverged too much
+        assert_eq!(
+            select_price_source(&asset, Some(halted), Some(diverged), max_delay, slot),
+            PriceSource::Unavailable
+        );
+        // Outdated primary and diverged secondary
+        assert_eq!(
+            select_price_source(&asset, Some(outdated), Some(diverged), max_delay, slot),
+            PriceSource::Primary(outdated)
+        );
+        // Both halted
+        assert_eq!(
+            select_price_source(&asset, Some(halted), Some(halted), max_delay, slot),
+            PriceSource::Unavailable
+        );
+        // Secondary outdated
+        assert_eq!(
+            select_price_source(&asset, Some(halted), Some(outdated), max_delay, slot),
+            PriceSource::Unavailable
+        );
+        // Primary not read and secondary filtered out
+        assert_eq!(
+            select_price_source(&asset, None, Some(outdated), max_delay, slot),
+            PriceSource::Skipped
+        );
+        assert_eq!(
+            select_price_source(&asset, None, Some(diverged), max_delay, slot),
+            PriceSource::Skipped
+        );
+        assert_eq!(
+            select_price_source(&asset, None, None, max_delay, slot),
+            PriceSource::Skipped
+        );
+    }
+    // u64 words keep price account aligned
+    fn price_feed_data(price: i64, status: PriceStatus, valid_slot: u64) -> Vec<u64> {
+        use anchor_lang::__private::bytemuck;
+        let feed = Price {
+            magic: MAGIC,
+            ver: VERSION,
+            atype: AccountType::Price as u32,
+            ptype: PriceType::Price as u32,
+            expo: -6,
+            valid_slot,
+            agg: PriceInfo {
+                price,
+                status: status as u32,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut data = vec![0u64; std::mem::size_of::<Price>() / 8];
+        bytemuck::cast_slice_mut::<u64, u8>(&mut data).copy_from_slice(bytemuck::bytes_of(&feed));
+        data
+    }
+    #[test]
+    fn test_update_assets_prices_settlement() {
+        use crate::math::calculate_synthetic_debt;
+        use anchor_lang::__private::bytemuck;
+        let slot = 100;
+        let max_delay = 10;
+        let oracle_program = Pubkey::new_unique();
+        let spot_feed = Pubkey::new_unique();
+        let mut assets_list = AssetsList::default();
+        let mut input_feeds = [Pubkey::default(); DERIVED_MAX_INPUTS];
+        input_feeds[0] = spot_feed;
+        // Inverse synthetic with band 3-7 around entry price 5
+        assets_list.append_asset(Asset {
+            asset_kind: ASSET_KIND_INVERSE_SYNTHETIC,
+            input_feeds,
+            price: 5_000000,
+            entry_price: 5_000000,
+            lower_bound: 3_000000,
+            upper_bound: 7_000000,
+            max_divergence: u32::MAX,
+            max_price_change: u32::MAX,
+            last_update: 50,
+            ..Default::default()
+        });
+        assets_list.append_synthetic(Synthetic {
+            asset_index: 0,
+            supply: 10_000000,
+            decimals: 6,
+            settlement_slot: u64::MAX,
+            ..Default::default()
+        });
+        let mut state = State {
+            oracle_program,
+            max_delay,
+            ..Default::default()
+        };
+        state.debt = calculate_synthetic_debt(&assets_list.assets[0], &assets_list.synthetics[0]);
+
+        let update = |state: &mut State, assets_list: &mut AssetsList, spot, valid_slot| {
+            let mut data = price_feed_data(spot, PriceStatus::Trading, valid_slot);
+            let mut lamports = 0u64;
+            let price_feed = AccountInfo::new(
+                &spot_feed,
+                false,
+                false,
+                &mut lamports,
+                bytemuck::cast_slice_mut(&mut data),
+                &oracle_program,
+                false,
+                0,
+            );
+            update_assets_prices(state, assets_list, &[price_feed], slot).unwrap();
+        };
+        // Band hit by outdated price doesn't settle
+        update(&mut state, &mut assets_list, 8_000000, 0);
+        assert_eq!({ assets_list.assets[0].price }, 3_000000);
+        assert!(!is_settled(&assets_list.synthetics[0], slot));
+        assert_eq!(
+            { state.debt },
+            calculate_synthetic_debt(&assets_list.assets[0], &assets_list.synthetics[0])
+        );
+        // Fresh price hitting band settles and freezes price at once
+        update(&mut state, &mut assets_list, 8_000000, slot);
+        assert!(is_settled(&assets_list.synthetics[0], slot));
+        assert_eq!({ assets_list.synthetics[0].settlement_price }, 3_000000);
+        let settled_debt = state.debt;
+        assert_eq!(settled_debt, 30_000000);
+        // Asset keeps being updated without moving frozen price or debt
+        update(&mut state, &mut assets_list, 4_000000, slot);
+        assert_eq!({ assets_list.assets[0].price }, 6_000000);
+        assert_eq!({ assets_list.synthetics[0].settlement_price }, 3_000000);
+        assert_eq!({ state.debt }, settled_debt);
+    }
+    #[test]
+    fn test_update_assets_prices_skipped_feeds() {
+        use anchor_lang::__private::bytemuck;
+        let slot = 100;
+        let max_delay = 10;
+        let oracle_program = Pubkey::new_unique();
+        let valid = Pubkey::new_unique();
+        let negative = Pubkey::new_unique();
+        let missing = Pubkey::new_unique();
+        let outdated = Pubkey::new_unique();
+        let halted = Pubkey::new_unique();
+        let mut assets_list = AssetsList::default();
+        for (feed_address, secondary_feed_address) in [
+            (valid, Pubkey::default()),
+            (negative, Pubkey::default()),
+            (missing, outdated),
+            (halted, Pubkey::default()),
+        ]
+        .iter()
+        {
+            assets_list.append_asset(Asset {
+                feed_address: *feed_address,
+                secondary_feed_address: *secondary_feed_address,
+                price: 1_000000,
+                max_divergence: u32::MAX,
+                max_price_change: u32::MAX,
+                last_update: 50,
+                ..Default::default()
+            });
+        }
+        let mut feeds = [
+            (valid, price_feed_data(2_000000, PriceStatus::Trading, slot)),
+            (negative, price_feed_data(-1, PriceStatus::Trading, slot)),
+            (outdated, price_feed_data(2_000000, PriceStatus::Trading, 0)),
+            (halted, price_feed_data(2_000000, PriceStatus::Halted, slot)),
+        ];
+        let mut lamports = [0u64; 4];
+        let price_feeds: Vec<AccountInfo> = feeds
+            .iter_mut()
+            .zip(lamports.iter_mut())
+            .map(|((key, data), lamports)| {
+                AccountInfo::new(
+                    key,
+                    false,
+                    false,
+                    lamports,
+                    bytemuck::cast_slice_mut(data),
+                    &oracle_program,
+                    false,
+                    0,
+                )
+            })
+            .collect();
+
+        let mut state = State {
+            oracle_program,
+            max_delay,
+            ..Default::default()
+        };
+        let skipped_feeds =
+            update_assets_prices(&mut state, &mut assets_list, &price_feeds, slot).unwrap();
+        // Only invalid price is reported
+        assert_eq!(skipped_feeds, vec![negative]);
+        let assets = &assets_list.assets;
+        assert_eq!({ assets[0].price }, 2_000000);
+        assert!(!assets[0].frozen);
+        // Invalid price leaves asset unchanged
+        assert_eq!({ assets[1].price }, 1_000000);
+        assert!(!assets[1].frozen);
+        // Missing primary with filtered out secondary doesn't freeze
+        assert_eq!({ assets[2].price }, 1_000000);
+        assert_eq!({ assets[2].last_update }, 50);
+        assert!(!assets[2].frozen);
+        // Not trading primary without fallback freezes
+        assert_eq!({ assets[3].price }, 1_000000);
+        assert!(assets[3].frozen);
+    }
+    #[test]
+    fn test_check_feed_breaker() {
+        let assets = [
+            Asset {
+                in_breaker: false,
+                ..Default::default()
+            },
+            Asset {
+                in_breaker: true,
+                ..Default::default()
+            },
+        ];
+        assert!(check_feed_breaker(&assets, 0, 0).is_ok());
+        assert!(check_feed_breaker(&assets, 0, 1).is_err());
+        assert!(check_feed_breaker(&assets, 1, 0).is_err());
+    }
+    #[test]
+    fn test_update_price_breaker() {
+        let mut asset = Asset {
+            price: 2_000_000,
+            // 10%
+            max_price_change: 100_000,
+            breaker_cooldown: 100,
+            ..Default::default()
+        };
+        // Price move within limit
+        assert!(update_price_breaker(&mut asset, 2_100_000, 10));
+        assert!(!asset.in_breaker);
+        // Price jump triggers breaker
+        assert!(!update_price_breaker(&mut asset, 3_000_000, 20));
+        assert!(asset.in_breaker);
+        assert_eq!({ asset.breaker_slot }, 20);
+        // Breaker slot is not moved by following jumps
+        assert!(!update_price_breaker(&mut asset, 3_000_000, 50));
+        assert_eq!({ asset.breaker_slot }, 20);
+        // Price within limit is stored but breaker stays
+        assert!(update_price_breaker(&mut asset, 1_900_000, 60));
+        assert!(asset.in_breaker);
+        asset.price = 1_900_000;
+        // Jump is not accepted after cooldown
+        assert!(!update_price_breaker(&mut asset, 3_000_000, 120));
+        assert!(asset.in_breaker);
+        assert_eq!({ asset.breaker_slot }, 20);
+        // Price back within limit clears breaker after cooldown
+        assert!(update_price_breaker(&mut asset, 2_000_000, 130));
+        assert!(!asset.in_breaker);
+        // No cooldown, only admin can clear breaker
+        asset.price = 2_000_000;
+        asset.breaker_cooldown = 0;
+        assert!(!update_price_breaker(&mut asset, 3_000_000, 1_000));
+        assert!(update_price_breaker(&mut asset, 2_000_000, u64::MAX));
+        assert!(asset.in_breaker);
+    }
+    #[test]
+    fn test_update_price_breaker_without_reference_price() {
+        let mut asset = Asset {
+            price: 0,
+            max_price_change: 100_000,
+            breaker_cooldown: 100,
+            ..Default::default()
+        };
+        // First price is accepted without check
+        assert!(update_price_breaker(&mut asset, 3_000_000, 10));
+        assert!(!asset.in_breaker);
+        asset.price = 3_000_000;
+        // Next jump is checked against first price
+        assert!(!update_price_breaker(&mut asset, 2_000_000, 20));
+        assert!(asset.in_breaker);
+    }
+    #[test]
+    fn test_is_crank_eligible() {
+        let slot = 1000;
+        let min_age = 100;
+        let outdated = Asset {
+            last_update: 850,
+            last_crank: 0,
+            ..Default::default()
+        };
+        assert!(is_crank_eligible(&outdated, min_age, slot));
+        // Fresh asset
+        let fresh = Asset {
+            last_update: 950,
+            ..outdated
+        };
+        assert!(!is_crank_eligible(&fresh, min_age, slot));
+        // Asset already rewarded recently
+        let rewarded = Asset {
+            last_crank: 950,
+            ..outdated
+        };
+        assert!(!is_crank_eligible(&rewarded, min_age, slot));
+        // Usd asset is never updated
+        let usd = Asset {
+            last_update: u64::MAX,
+            ..Default::default()
+        };
+        assert!(!is_crank_eligible(&usd, min_age, slot));
+    }
+    #[test]
+    fn test_reward_crank_updates() {
+        let slot = 1000;
+        let mut crank = Crank {
+            reward: 10,
+            max_rewards_per_slot: 2,
+            last_slot: slot - 1,
+            rewards_in_slot: 2,
+            ..Default::default()
+        };
+        let mut assets = [
+            // refreshed
+            Asset {
+                last_update: 990,
+                ..Default::default()
+            },
+            // not outdated before update
+            Asset {
+                last_update: 990,
+                ..Default::default()
+            },
+            // feed did not publish newer price
+            Asset {
+                last_update: 500,
+                ..Default::default()
+            },
+            // refreshed
+            Asset {
+                last_update: 995,
+                ..Default::default()
+            },
+            // refreshed above per slot limit
+            Asset {
+                last_update: 995,
+                ..Default::default()
+            },
+        ];
+        let outdated_updates = [Some(800), None, Some(500), Some(700), Some(700)];
+        let reward = reward_crank_updates(&mut crank, &mut assets, &outdated_updates, slot);
+        assert_eq!(reward, 20);
+        assert_eq!({ crank.last_slot }, slot);
+        assert_eq!({ crank.rewards_in_slot }, 2);
+        assert_eq!({ assets[0].last_crank }, slot);
+        assert_eq!({ assets[1].last_crank }, 0);
+        assert_eq!({ assets[2].last_crank }, 0);
+        assert_eq!({ assets[3].last_crank }, slot);
+        assert_eq!({ assets[4].last_crank }, 0);
+
+        // Limit is already used in this slot
+        assets[4].last_crank = 0;
+        let reward = reward_crank_updates(&mut crank, &mut assets, &outdated_updates, slot);
+        assert_eq!(reward, 0);
+    }
+    #[test]
     fn adjust_staking_account_test() {
         let staking_round_length = 100;
         let amount_per_round = 300;
@@ -335,7 +2754,7 @@ mod tests {
     fn adjust_staking_rounds_with_fixed_round_length_test() {
         let staking_round_length = 100;
         let amount_per_round = 300;
-        let debt_shares = 999u64;
+        let debt_shares = 999u128;
         let staking = Staking {
             round_length: staking_round_length,
             amount_per_round: amount_per_round,
@@ -598,7 +3017,7 @@ mod tests {
     fn adjust_staking_rounds_with_variable_round_length_test() {
         let staking_round_length = 100;
         let amount_per_round = 300;
-        let debt_shares = 999u64;
+        let debt_shares = 999u128;
         let staking = Staking {
             round_length: staking_round_length,
             amount_per_round: amount_per_round,
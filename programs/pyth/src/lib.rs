@@ -1,6 +1,6 @@
 use anchor_lang::prelude::*;
 pub mod pc;
-use pc::Price;
+use pc::{AccKey, Mapping, Price, Product};
 #[program]
 pub mod pyth {
 
@@ -8,20 +8,180 @@ pub mod pyth {
     pub fn initialize(ctx: Context<Initialize>, price: i64, expo: i32, conf: u64) -> ProgramResult {
         let oracle = &ctx.accounts.price;
 
-        let mut price_oracle = Price::load(&oracle).unwrap();
+        let mut price_oracle = Price::load(oracle)?;
 
+        price_oracle.magic = pc::MAGIC;
+        price_oracle.ver = pc::VERSION;
+        price_oracle.atype = pc::AccountType::Price as u32;
+        price_oracle.size = std::mem::size_of::<Price>() as u32;
         price_oracle.agg.price = price;
         price_oracle.agg.conf = conf;
         price_oracle.expo = expo;
-        price_oracle.ptype = pc::PriceType::Price;
+        price_oracle.ptype = pc::PriceType::Price as u32;
+        price_oracle.agg.status = pc::PriceStatus::Trading as u32;
+        price_oracle.valid_slot = Clock::get()?.slot;
+        price_oracle.agg.pub_slot = price_oracle.valid_slot;
         Ok(())
     }
     pub fn set_price(ctx: Context<SetPrice>, price: i64) -> ProgramResult {
         let oracle = &ctx.accounts.price;
-        let mut price_oracle = Price::load(&oracle).unwrap();
-        price_oracle.agg.price = price as i64;
+        let mut price_oracle = Price::load(oracle)?;
+        price_oracle.set_price(price, Clock::get()?.slot);
         Ok(())
     }
+    pub fn set_confidence(ctx: Context<SetPrice>, conf: u64) -> ProgramResult {
+        let oracle = &ctx.accounts.price;
+        let mut price_oracle = Price::load(oracle)?;
+        price_oracle.agg.conf = conf;
+        Ok(())
+    }
+    pub fn set_status(ctx: Context<SetPrice>, status: u8) -> ProgramResult {
+        let oracle = &ctx.accounts.price;
+        let mut price_oracle = Price::load(oracle)?;
+        price_oracle.set_status(status)?;
+        Ok(())
+    }
+    pub fn set_exponent(ctx: Context<SetPrice>, expo: i32) -> ProgramResult {
+        let oracle = &ctx.accounts.price;
+        let mut price_oracle = Price::load(oracle)?;
+        price_oracle.expo = expo;
+        Ok(())
+    }
+    pub fn set_twap(ctx: Context<SetPrice>, twap: i64) -> ProgramResult {
+        let oracle = &ctx.accounts.price;
+        let mut price_oracle = Price::load(oracle)?;
+        price_oracle.twap = twap;
+        Ok(())
+    }
+    // Allows simulating stale feeds
+    pub fn set_valid_slot(ctx: Context<SetPrice>, valid_slot: u64) -> ProgramResult {
+        let oracle = &ctx.accounts.price;
+        let mut price_oracle = Price::load(oracle)?;
+        price_oracle.set_valid_slot(valid_slot);
+        Ok(())
+    }
+    // Writes all given fields at once, None leaves field unchanged
+    pub fn set_price_info(
+        ctx: Context<SetPrice>,
+        price: Option<i64>,
+        conf: Option<u64>,
+        status: Option<u8>,
+        expo: Option<i32>,
+        twap: Option<i64>,
+        valid_slot: Option<u64>,
+    ) -> ProgramResult {
+        let oracle = &ctx.accounts.price;
+        let mut price_oracle = Price::load(oracle)?;
+        if let Some(price) = price {
+            price_oracle.set_price(price, Clock::get()?.slot);
+        }
+        if let Some(conf) = conf {
+            price_oracle.agg.conf = conf;
+        }
+        if let Some(status) = status {
+            price_oracle.set_status(status)?;
+        }
+        if let Some(expo) = expo {
+            price_oracle.expo = expo;
+        }
+        if let Some(twap) = twap {
+            price_oracle.twap = twap;
+        }
+        if let Some(valid_slot) = valid_slot {
+            price_oracle.set_valid_slot(valid_slot);
+        }
+        Ok(())
+    }
+    pub fn initialize_mapping(ctx: Context<InitializeMapping>) -> ProgramResult {
+        let mut mapping = Mapping::load(&ctx.accounts.mapping)?;
+
+        mapping.magic = pc::MAGIC;
+        mapping.ver = pc::VERSION;
+        mapping.atype = pc::AccountType::Mapping as u32;
+        mapping.size =
+            (std::mem::size_of::<Mapping>() - std::mem::size_of_val(&mapping.products)) as u32;
+        Ok(())
+    }
+    pub fn add_product(ctx: Context<AddProduct>, symbol: String) -> ProgramResult {
+        let mut mapping = Mapping::load(&ctx.accounts.mapping)?;
+        let mut product = Product::load(&ctx.accounts.product)?;
+
+        let index = mapping.num as usize;
+        if index >= pc::MAP_TABLE_SIZE {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        // Attributes are stored as length prefixed key/value strings
+        let key = b"symbol";
+        let value = symbol.as_bytes();
+        let attr_size = 2 + key.len() + value.len();
+        if value.len() > u8::MAX as usize || attr_size > pc::PROD_ATTR_SIZE {
+            return Err(ProgramError::InvalidArgument);
+        }
+        product.magic = pc::MAGIC;
+        product.ver = pc::VERSION;
+        product.atype = pc::AccountType::Product as u32;
+        product.size = (pc::PROD_HDR_SIZE + attr_size) as u32;
+        product.attr[0] = key.len() as u8;
+        product.attr[1..1 + key.len()].copy_from_slice(key);
+        product.attr[1 + key.len()] = value.len() as u8;
+        product.attr[2 + key.len()..attr_size].copy_from_slice(value);
+
+        mapping.products[index] = account_key(ctx.accounts.product.key);
+        mapping.num += 1;
+        mapping.size += std::mem::size_of::<AccKey>() as u32;
+        Ok(())
+    }
+    // Prepends initialized price account to product price list
+    pub fn link_price(ctx: Context<LinkPrice>) -> ProgramResult {
+        let mut product = Product::load(&ctx.accounts.product)?;
+        let mut price = Price::load(&ctx.accounts.price)?;
+
+        price.prod = account_key(ctx.accounts.product.key);
+        price.next = product.px_acc;
+        product.px_acc = account_key(ctx.accounts.price.key);
+        Ok(())
+    }
+    pub fn add_publisher(ctx: Context<SetPrice>, publisher: Pubkey) -> ProgramResult {
+        let mut price = Price::load(&ctx.accounts.price)?;
+        price.add_component(account_key(&publisher))
+    }
+    pub fn update_component_price(
+        ctx: Context<UpdateComponentPrice>,
+        price: i64,
+        conf: u64,
+        status: u8,
+    ) -> ProgramResult {
+        let mut price_oracle = Price::load(&ctx.accounts.price)?;
+        let publisher = account_key(ctx.accounts.publisher.key);
+        let num = price_oracle.comp_count();
+
+        let comp = price_oracle.comp[..num]
+            .iter_mut()
+            .find(|comp| comp.publisher.val == publisher.val);
+        match comp {
+            Some(comp) => {
+                comp.latest = pc::PriceInfo {
+                    price,
+                    conf,
+                    status: pc::price_status(status)?,
+                    corp_act: pc::CorpAction::NoCorpAct as u32,
+                    pub_slot: Clock::get()?.slot,
+                }
+            }
+            None => return Err(ProgramError::MissingRequiredSignature),
+        }
+        Ok(())
+    }
+    pub fn aggregate_price(ctx: Context<SetPrice>) -> ProgramResult {
+        let mut price_oracle = Price::load(&ctx.accounts.price)?;
+        price_oracle.aggregate(Clock::get()?.slot);
+        Ok(())
+    }
+}
+fn account_key(key: &Pubkey) -> AccKey {
+    AccKey {
+        val: key.to_bytes(),
+    }
 }
 #[derive(Accounts)]
 pub struct SetPrice<'info> {
@@ -33,3 +193,29 @@ pub struct Initialize<'info> {
     #[account(mut)]
     pub price: AccountInfo<'info>,
 }
+#[derive(Accounts)]
+pub struct InitializeMapping<'info> {
+    #[account(mut)]
+    pub mapping: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct AddProduct<'info> {
+    #[account(mut)]
+    pub mapping: AccountInfo<'info>,
+    #[account(mut)]
+    pub product: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct LinkPrice<'info> {
+    #[account(mut)]
+    pub product: AccountInfo<'info>,
+    #[account(mut)]
+    pub price: AccountInfo<'info>,
+}
+#[derive(Accounts)]
+pub struct UpdateComponentPrice<'info> {
+    #[account(mut)]
+    pub price: AccountInfo<'info>,
+    #[account(signer)]
+    pub publisher: AccountInfo<'info>,
+}
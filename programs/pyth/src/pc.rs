@@ -1,14 +1,31 @@
 use crate::*;
 use anchor_lang::prelude::AccountInfo;
-use bytemuck::{cast_slice_mut, from_bytes_mut, try_cast_slice_mut, Pod, Zeroable};
+use bytemuck::{from_bytes_mut, try_from_bytes_mut, Pod, Zeroable};
 use std::cell::RefMut;
 
+pub const MAGIC: u32 = 0xa1b2c3d4;
+pub const VERSION_1: u32 = 1;
+pub const VERSION: u32 = VERSION_1;
+pub const MAP_TABLE_SIZE: usize = 640;
+pub const PROD_ACCT_SIZE: usize = 512;
+pub const PROD_HDR_SIZE: usize = 48;
+pub const PROD_ATTR_SIZE: usize = PROD_ACCT_SIZE - PROD_HDR_SIZE;
+
 #[derive(Default, Copy, Clone)]
 #[repr(C)]
 pub struct AccKey {
     pub val: [u8; 32],
 }
 
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub enum AccountType {
+    Unknown,
+    Mapping,
+    Product,
+    Price,
+}
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub enum PriceStatus {
@@ -41,16 +58,16 @@ impl Default for CorpAction {
 pub struct PriceInfo {
     pub price: i64,
     pub conf: u64,
-    pub status: PriceStatus,
-    pub corp_act: CorpAction,
+    pub status: u32,   // Raw PriceStatus, account data can hold any value
+    pub corp_act: u32, // Raw CorpAction
     pub pub_slot: u64,
 }
 #[derive(Default, Copy, Clone)]
 #[repr(C)]
 pub struct PriceComp {
-    publisher: AccKey,
-    agg: PriceInfo,
-    latest: PriceInfo,
+    pub publisher: AccKey, // Key of contributing quoter.
+    pub agg: PriceInfo,    // Contributing price to last aggregate.
+    pub latest: PriceInfo, // Latest contributing price (not in agg.).
 }
 
 #[derive(Copy, Clone)]
@@ -71,13 +88,13 @@ impl Default for PriceType {
 #[derive(Default, Copy, Clone)]
 #[repr(C)]
 pub struct Price {
-    pub magic: u32,       // Pyth magic number.
-    pub ver: u32,         // Program version.
-    pub atype: u32,       // Account type.
-    pub size: u32,        // Price account size.
-    pub ptype: PriceType, // Price or calculation type.
-    pub expo: i32,        // Price exponent.
-    pub num: u32,         // Number of component prices.
+    pub magic: u32, // Pyth magic number.
+    pub ver: u32,   // Program version.
+    pub atype: u32, // Account type.
+    pub size: u32,  // Price account size.
+    pub ptype: u32, // Price or calculation type, raw PriceType.
+    pub expo: i32,  // Price exponent.
+    pub num: u32,   // Number of component prices.
     pub unused: u32,
     pub curr_slot: u64,        // Currently accumulating price slot.
     pub valid_slot: u64,       // Valid slot-time of agg. price.
@@ -99,15 +116,14 @@ pub struct Price {
 impl Price {
     #[inline]
     pub fn load<'a>(price_feed: &'a AccountInfo) -> Result<RefMut<'a, Price>, ProgramError> {
-        let account_data: RefMut<'a, [u8]>;
-        let state: RefMut<'a, Self>;
-
-        account_data = RefMut::map(price_feed.try_borrow_mut_data().unwrap(), |data| *data);
+        let mut account_data: RefMut<'a, [u8]> =
+            RefMut::map(price_feed.try_borrow_mut_data()?, |data| *data);
+        // Account of wrong size or alignment cannot be a price account
+        if try_from_bytes_mut::<Price>(&mut account_data).is_err() {
+            return Err(ProgramError::InvalidAccountData);
+        }
 
-        state = RefMut::map(account_data, |data| {
-            from_bytes_mut(cast_slice_mut::<u8, u8>(try_cast_slice_mut(data).unwrap()))
-        });
-        Ok(state)
+        Ok(RefMut::map(account_data, |data| from_bytes_mut(data)))
     }
 }
 
@@ -116,3 +132,266 @@ unsafe impl Zeroable for Price {}
 
 #[cfg(target_endian = "little")]
 unsafe impl Pod for Price {}
+
+impl Price {
+    // Stored number of components, corrupted one can't reach past components
+    pub fn comp_count(&self) -> usize {
+        (self.num as usize).min(self.comp.len())
+    }
+    pub fn add_component(&mut self, publisher: AccKey) -> Result<(), ProgramError> {
+        let index = self.comp_count();
+        if index == self.comp.len() {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        self.comp[index].publisher = publisher;
+        self.num = index as u32 + 1;
+        Ok(())
+    }
+    // Price published in slot is valid since that slot
+    pub fn set_price(&mut self, price: i64, slot: u64) {
+        self.agg.price = price;
+        self.set_valid_slot(slot);
+    }
+    pub fn set_valid_slot(&mut self, slot: u64) {
+        self.valid_slot = slot;
+        self.agg.pub_slot = slot;
+    }
+    pub fn set_status(&mut self, status: u8) -> Result<(), ProgramError> {
+        self.agg.status = price_status(status)?;
+        Ok(())
+    }
+    // Computes aggregate from latest trading component prices
+    pub fn aggregate(&mut self, slot: u64) {
+        let mut prices = [0i64; 32];
+        let mut confs = [0u64; 32];
+        let mut count = 0;
+        let num = self.comp_count();
+        for comp in self.comp[..num].iter_mut() {
+            comp.agg = comp.latest;
+            if comp.latest.status == PriceStatus::Trading as u32 {
+                prices[count] = comp.latest.price;
+                count += 1;
+            }
+        }
+        self.curr_slot = slot;
+        if count == 0 {
+            self.agg.status = PriceStatus::Unknown as u32;
+            return;
+        }
+        let price = median(&mut prices[..count]);
+        // Confidence covers both publisher uncertainty and their disagreement
+        let mut index = 0;
+        for comp in self.comp[..num].iter() {
+            if comp.latest.status == PriceStatus::Trading as u32 {
+                let spread = (comp.latest.price as i128 - price as i128).unsigned_abs() as u64;
+                confs[index] = comp.latest.conf.saturating_add(spread);
+                index += 1;
+            }
+        }
+        self.agg.price = price;
+        self.agg.conf = median(&mut confs[..count]);
+        self.agg.status = PriceStatus::Trading as u32;
+        self.agg.pub_slot = slot;
+        self.valid_slot = slot;
+    }
+}
+
+// Only known statuses can be written
+pub fn price_status(status: u8) -> Result<u32, ProgramError> {
+    match status {
+        0 => Ok(PriceStatus::Unknown as u32),
+        1 => Ok(PriceStatus::Trading as u32),
+        2 => Ok(PriceStatus::Halted as u32),
+        3 => Ok(PriceStatus::Auction as u32),
+        _ => Err(ProgramError::InvalidArgument),
+    }
+}
+
+fn median<T: Ord + Copy>(values: &mut [T]) -> T {
+    values.sort_unstable();
+    // lower median keeps values exact for even count
+    values[(values.len() - 1) / 2]
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Product {
+    pub magic: u32,                 // Pyth magic number.
+    pub ver: u32,                   // Program version.
+    pub atype: u32,                 // Account type.
+    pub size: u32,                  // Price account size.
+    pub px_acc: AccKey,             // First price account in list.
+    pub attr: [u8; PROD_ATTR_SIZE], // Key/value pairs of reference attr.
+}
+
+impl Product {
+    #[inline]
+    pub fn load<'a>(product: &'a AccountInfo) -> Result<RefMut<'a, Product>, ProgramError> {
+        let mut account_data: RefMut<'a, [u8]> =
+            RefMut::map(product.try_borrow_mut_data()?, |data| *data);
+        if try_from_bytes_mut::<Product>(&mut account_data).is_err() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(RefMut::map(account_data, |data| from_bytes_mut(data)))
+    }
+}
+
+#[cfg(target_endian = "little")]
+unsafe impl Zeroable for Product {}
+
+#[cfg(target_endian = "little")]
+unsafe impl Pod for Product {}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Mapping {
+    pub magic: u32, // Pyth magic number.
+    pub ver: u32,   // Program version.
+    pub atype: u32, // Account type.
+    pub size: u32,  // Account size.
+    pub num: u32,   // Number of product accounts.
+    pub unused: u32,
+    pub next: AccKey,                       // Next mapping account (if any).
+    pub products: [AccKey; MAP_TABLE_SIZE], // Product accounts.
+}
+
+impl Mapping {
+    #[inline]
+    pub fn load<'a>(mapping: &'a AccountInfo) -> Result<RefMut<'a, Mapping>, ProgramError> {
+        let mut account_data: RefMut<'a, [u8]> =
+            RefMut::map(mapping.try_borrow_mut_data()?, |data| *data);
+        if try_from_bytes_mut::<Mapping>(&mut account_data).is_err() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(RefMut::map(account_data, |data| from_bytes_mut(data)))
+    }
+}
+
+#[cfg(target_endian = "little")]
+unsafe impl Zeroable for Mapping {}
+
+#[cfg(target_endian = "little")]
+unsafe impl Pod for Mapping {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn component(price: i64, conf: u64, status: PriceStatus) -> PriceComp {
+        PriceComp {
+            latest: PriceInfo {
+                price,
+                conf,
+                status: status as u32,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+    #[test]
+    fn test_setters_layout() {
+        use std::convert::TryInto;
+        let key = Pubkey::new_unique();
+        let mut lamports = 0u64;
+        // u64 words keep price account aligned
+        let mut data = vec![0u64; std::mem::size_of::<Price>() / 8];
+        let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut data);
+        let account = AccountInfo::new(&key, false, true, &mut lamports, bytes, &key, false, 0);
+        {
+            let mut price = Price::load(&account).unwrap();
+            price.ptype = PriceType::Price as u32;
+            price.expo = -8;
+            price.twap = 41;
+            price.agg.conf = 7;
+            price.set_price(-42, 10);
+            price.set_status(PriceStatus::Halted as u8).unwrap();
+            price.set_valid_slot(12);
+            assert_eq!(price.set_status(4), Err(ProgramError::InvalidArgument));
+        }
+        // Offsets of Pyth v2 price account read by clients
+        let bytes = account.try_borrow_data().unwrap();
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let read_u64 =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        assert_eq!(read_u32(16), PriceType::Price as u32);
+        assert_eq!(read_u32(20) as i32, -8);
+        assert_eq!(read_u64(40), 12);
+        assert_eq!(read_u64(48) as i64, 41);
+        assert_eq!(read_u64(208) as i64, -42);
+        assert_eq!(read_u64(216), 7);
+        assert_eq!(read_u32(224), PriceStatus::Halted as u32);
+        assert_eq!(read_u64(232), 12);
+        drop(bytes);
+
+        // Written fields read back through loaded account
+        let price = Price::load(&account).unwrap();
+        assert_eq!(price.agg.price, -42);
+        assert_eq!(price.agg.status, PriceStatus::Halted as u32);
+        assert_eq!(price.valid_slot, 12);
+        assert_eq!(price.agg.pub_slot, 12);
+    }
+    #[test]
+    fn test_aggregate() {
+        let mut price = Price {
+            num: 4,
+            ..Default::default()
+        };
+        price.comp[0] = component(100, 2, PriceStatus::Trading);
+        price.comp[1] = component(104, 1, PriceStatus::Trading);
+        price.comp[2] = component(101, 3, PriceStatus::Trading);
+        price.comp[3] = component(500, 1, PriceStatus::Halted);
+        price.aggregate(10);
+
+        assert_eq!(price.agg.price, 101);
+        // confidences: 2 + 1, 1 + 3, 3 + 0
+        assert_eq!(price.agg.conf, 3);
+        assert_eq!(price.agg.pub_slot, 10);
+        assert_eq!(price.agg.status, PriceStatus::Trading as u32);
+        assert_eq!(price.valid_slot, 10);
+        // Halted publisher price is kept in component but not aggregated
+        assert_eq!(price.comp[3].agg.price, 500);
+    }
+    #[test]
+    fn test_aggregate_without_trading_publishers() {
+        let mut price = Price {
+            num: 1,
+            ..Default::default()
+        };
+        price.comp[0] = component(100, 2, PriceStatus::Halted);
+        price.aggregate(10);
+        assert_eq!(price.agg.status, PriceStatus::Unknown as u32);
+    }
+    #[test]
+    fn test_aggregate_with_corrupted_num() {
+        let mut price = Price {
+            num: u32::MAX,
+            ..Default::default()
+        };
+        price.comp[31] = component(100, 2, PriceStatus::Trading);
+        price.aggregate(10);
+        assert_eq!(price.comp_count(), 32);
+        assert_eq!(price.agg.price, 100);
+        // Extreme prices don't overflow spread
+        price.comp[0] = component(i64::MIN, 0, PriceStatus::Trading);
+        price.comp[1] = component(i64::MAX, 0, PriceStatus::Trading);
+        price.aggregate(11);
+        assert_eq!(price.agg.price, 100);
+    }
+    #[test]
+    fn test_add_component() {
+        let mut price = Price::default();
+        for index in 0..32 {
+            let publisher = AccKey { val: [index; 32] };
+            assert!(price.add_component(publisher).is_ok());
+        }
+        assert_eq!(price.num, 32);
+        assert_eq!(price.comp[31].publisher.val, [31; 32]);
+        // Full or corrupted component list can't be extended
+        assert!(price.add_component(AccKey::default()).is_err());
+        price.num = u32::MAX;
+        assert!(price.add_component(AccKey::default()).is_err());
+    }
+}
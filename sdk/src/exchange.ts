@@ -2,7 +2,16 @@ import { DEV_NET, Network, TEST_NET } from './network'
 import idl from './idl/exchange.json'
 import { BN, Idl, Program, Provider, utils } from '@project-serum/anchor'
 import { IWallet } from '.'
-import { calculateDebt, DEFAULT_PUBLIC_KEY, signAndSend, sleep, tou64 } from './utils'
+import {
+  AssetKind,
+  calculateDebt,
+  DEFAULT_PUBLIC_KEY,
+  PriceMode,
+  PriceSource,
+  signAndSend,
+  sleep,
+  tou64
+} from './utils'
 import { Token, TOKEN_PROGRAM_ID } from '@solana/spl-token'
 import {
   Connection,
@@ -16,7 +25,10 @@ import {
   sendAndConfirmRawTransaction
 } from '@solana/web3.js'
 
-export const STATE_SEED = 'statev1'
+export const STATE_SEED = 'statev2'
+// seeds of accounts created before debt, shares and points were widened to u128
+export const LEGACY_STATE_SEED = 'statev1'
+export const LEGACY_ACCOUNT_SEED = 'accountv1'
 export class Exchange {
   connection: Connection
   network: Network
@@ -99,18 +111,26 @@ export class Exchange {
     nonce,
     amountPerRound,
     stakingRoundLength,
-    stakingFundAccount
+    stakingFundAccount,
+    oracleProgram
   }: Init) {
     const [stateAddress, bump] = await PublicKey.findProgramAddress(
       [Buffer.from(utils.bytes.utf8.encode(STATE_SEED))],
       this.program.programId
     )
+    // init is rejected while state of previous version is not migrated
+    const [legacyState, _] = await PublicKey.findProgramAddress(
+      [Buffer.from(utils.bytes.utf8.encode(LEGACY_STATE_SEED))],
+      this.program.programId
+    )
     await this.program.rpc.init(bump, nonce, stakingRoundLength, amountPerRound, {
       accounts: {
         state: stateAddress,
         admin: admin,
         assetsList: assetsList,
         stakingFundAccount: stakingFundAccount,
+        oracleProgram: oracleProgram,
+        legacyState: legacyState,
         payer: this.wallet.publicKey,
         rent: SYSVAR_RENT_PUBKEY,
         systemProgram: SystemProgram.programId
@@ -160,7 +180,7 @@ export class Exchange {
   }
   public async createExchangeAccount(owner: PublicKey) {
     const [account, bump] = await PublicKey.findProgramAddress(
-      [Buffer.from(utils.bytes.utf8.encode('accountv1')), owner.toBuffer()],
+      [Buffer.from(utils.bytes.utf8.encode('accountv2')), owner.toBuffer()],
       this.program.programId
     )
     await this.program.rpc.createExchangeAccount(bump, {
@@ -176,7 +196,7 @@ export class Exchange {
   }
   public async createExchangeAccountInstruction(owner: PublicKey) {
     const [account, bump] = await PublicKey.findProgramAddress(
-      [Buffer.from(utils.bytes.utf8.encode('accountv1')), owner.toBuffer()],
+      [Buffer.from(utils.bytes.utf8.encode('accountv2')), owner.toBuffer()],
       this.program.programId
     )
     const ix = (await this.program.instruction.createExchangeAccount(bump, {
@@ -192,11 +212,60 @@ export class Exchange {
   }
   public async getExchangeAccountAddress(owner: PublicKey) {
     const [account, bump] = await PublicKey.findProgramAddress(
-      [Buffer.from(utils.bytes.utf8.encode('accountv1')), owner.toBuffer()],
+      [Buffer.from(utils.bytes.utf8.encode('accountv2')), owner.toBuffer()],
       this.program.programId
     )
     return account
   }
+  // legacy assets list is migrated into new account created with current size
+  public async migrateState({ admin, legacyAssetsList, oracleProgram }: MigrateState) {
+    const [state, bump] = await PublicKey.findProgramAddress(
+      [Buffer.from(utils.bytes.utf8.encode(STATE_SEED))],
+      this.program.programId
+    )
+    const [legacyState, _] = await PublicKey.findProgramAddress(
+      [Buffer.from(utils.bytes.utf8.encode(LEGACY_STATE_SEED))],
+      this.program.programId
+    )
+    const assetListAccount = new Account()
+    await this.program.rpc.migrateState(bump, {
+      accounts: {
+        state: state,
+        legacyState: legacyState,
+        assetsList: assetListAccount.publicKey,
+        legacyAssetsList: legacyAssetsList,
+        admin: admin.publicKey,
+        oracleProgram: oracleProgram,
+        payer: this.wallet.publicKey,
+        rent: SYSVAR_RENT_PUBKEY,
+        systemProgram: SystemProgram.programId
+      },
+      signers: [assetListAccount, admin],
+      instructions: [await this.program.account.assetsList.createInstruction(assetListAccount)]
+    })
+    return { state, assetsList: assetListAccount.publicKey }
+  }
+  public async migrateExchangeAccountInstruction(owner: PublicKey) {
+    const [account, bump] = await PublicKey.findProgramAddress(
+      [Buffer.from(utils.bytes.utf8.encode('accountv2')), owner.toBuffer()],
+      this.program.programId
+    )
+    const [legacyAccount, _] = await PublicKey.findProgramAddress(
+      [Buffer.from(utils.bytes.utf8.encode(LEGACY_ACCOUNT_SEED)), owner.toBuffer()],
+      this.program.programId
+    )
+    const ix = (await this.program.instruction.migrateExchangeAccount(bump, {
+      accounts: {
+        exchangeAccount: account,
+        legacyExchangeAccount: legacyAccount,
+        owner: owner,
+        payer: this.wallet.publicKey,
+        rent: SYSVAR_RENT_PUBKEY,
+        systemProgram: SystemProgram.programId
+      }
+    })) as TransactionInstruction
+    return { account, ix }
+  }
 
   public async depositInstruction({
     amount,
@@ -239,12 +308,19 @@ export class Exchange {
       }
     }) as TransactionInstruction)
   }
-  public async mintInstruction({ amount, exchangeAccount, owner, to }: MintInstruction) {
+  public async mintInstruction({
+    amount,
+    exchangeAccount,
+    owner,
+    to,
+    syntheticToken
+  }: MintInstruction) {
     return await (this.program.instruction.mint(amount, {
       accounts: {
         state: this.stateAddress,
         exchangeAuthority: this.exchangeAuthority,
-        usdToken: this.assetsList.synthetics[0].assetAddress,
+        // xUSD by default
+        syntheticToken: syntheticToken || this.assetsList.synthetics[0].assetAddress,
         to: to,
         tokenProgram: TOKEN_PROGRAM_ID,
         exchangeAccount: exchangeAccount,
@@ -278,6 +354,27 @@ export class Exchange {
       }
     }) as TransactionInstruction)
   }
+  public async settleSyntheticInstruction({
+    amount,
+    owner,
+    settledToken,
+    userSettledTokenAccount,
+    userUsdTokenAccount
+  }: SettleSyntheticInstruction) {
+    return await (this.program.instruction.settleSynthetic(amount, {
+      accounts: {
+        state: this.stateAddress,
+        exchangeAuthority: this.exchangeAuthority,
+        assetsList: this.state.assetsList,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        settledToken,
+        usdToken: this.assetsList.synthetics[0].assetAddress,
+        userSettledTokenAccount,
+        userUsdTokenAccount,
+        owner
+      }
+    }) as TransactionInstruction)
+  }
   public async liquidateInstruction({
     exchangeAccount,
     signer,
@@ -307,13 +404,15 @@ export class Exchange {
     amount,
     exchangeAccount,
     owner,
-    userTokenAccountBurn
+    userTokenAccountBurn,
+    syntheticToken
   }: BurnInstruction) {
     return await (this.program.instruction.burn(amount, {
       accounts: {
         state: this.stateAddress,
         exchangeAuthority: this.exchangeAuthority,
-        usdToken: this.assetsList.synthetics[0].assetAddress,
+        // xUSD by default
+        syntheticToken: syntheticToken || this.assetsList.synthetics[0].assetAddress,
         userTokenAccountBurn: userTokenAccountBurn,
         tokenProgram: TOKEN_PROGRAM_ID,
         exchangeAccount: exchangeAccount,
@@ -424,6 +523,88 @@ export class Exchange {
       }
     }) as TransactionInstruction)
   }
+  public async setOracleProgramInstruction(oracleProgram: PublicKey) {
+    return await (this.program.instruction.setOracleProgram({
+      accounts: {
+        state: this.stateAddress,
+        admin: this.state.admin,
+        oracleProgram
+      }
+    }) as TransactionInstruction)
+  }
+  public async setCrankFundInstruction(crankFundAccount: PublicKey) {
+    return await (this.program.instruction.setCrankFund({
+      accounts: {
+        state: this.stateAddress,
+        admin: this.state.admin,
+        crankFundAccount
+      }
+    }) as TransactionInstruction)
+  }
+  public async setCrankRewardInstruction({
+    reward,
+    minAge,
+    maxRewardsPerSlot
+  }: SetCrankRewardInstruction) {
+    return await (this.program.instruction.setCrankReward(reward, minAge, maxRewardsPerSlot, {
+      accounts: {
+        state: this.stateAddress,
+        admin: this.state.admin
+      }
+    }) as TransactionInstruction)
+  }
+  public async setDebtCeilingInstruction(debtCeiling: BN) {
+    return await (this.program.instruction.setDebtCeiling(debtCeiling, {
+      accounts: {
+        state: this.stateAddress,
+        admin: this.state.admin
+      }
+    }) as TransactionInstruction)
+  }
+  public async setAccountDebtLimitsInstruction({
+    minAccountDebt,
+    maxAccountDebt
+  }: SetAccountDebtLimitsInstruction) {
+    return await (this.program.instruction.setAccountDebtLimits(minAccountDebt, maxAccountDebt, {
+      accounts: {
+        state: this.stateAddress,
+        admin: this.state.admin
+      }
+    }) as TransactionInstruction)
+  }
+  // stabilityFee 100000 -> 100% yearly
+  public async setStabilityFeeInstruction(stabilityFee: number) {
+    return await (this.program.instruction.setStabilityFee(stabilityFee, {
+      accounts: {
+        state: this.stateAddress,
+        signer: this.state.admin,
+        assetsList: this.state.assetsList
+      }
+    }) as TransactionInstruction)
+  }
+  public async withdrawStabilityFeeInstruction(to: PublicKey) {
+    return await (this.program.instruction.withdrawStabilityFee({
+      accounts: {
+        state: this.stateAddress,
+        signer: this.state.admin,
+        exchangeAuthority: this.exchangeAuthority,
+        assetsList: this.state.assetsList,
+        usdToken: this.assetsList.synthetics[0].assetAddress,
+        to,
+        tokenProgram: TOKEN_PROGRAM_ID
+      }
+    }) as TransactionInstruction)
+  }
+  public async setStabilityFeeVaultInstruction(stabilityFeeVault: PublicKey) {
+    return await (this.program.instruction.setStabilityFeeVault({
+      accounts: {
+        state: this.stateAddress,
+        admin: this.state.admin,
+        assetsList: this.state.assetsList,
+        stabilityFeeVault
+      }
+    }) as TransactionInstruction)
+  }
   private async processOperations(txs: Transaction[]) {
     const blockhash = await this.connection.getRecentBlockhash(
       this.opts?.commitment || Provider.defaultOptions().commitment
@@ -534,13 +715,21 @@ export class Exchange {
     )
     return this.updatePricesAndSend([approveIx, swapIx], signers, this.assetsList.headAssets >= 20)
   }
-  public async burn({ amount, exchangeAccount, owner, userTokenAccountBurn, signers }: Burn) {
+  public async burn({
+    amount,
+    exchangeAccount,
+    owner,
+    userTokenAccountBurn,
+    syntheticToken,
+    signers
+  }: Burn) {
     const updateIx = await this.updatePricesInstruction(this.state.assetsList)
     const burnIx = await this.burnInstruction({
       amount,
       exchangeAccount,
       owner,
-      userTokenAccountBurn
+      userTokenAccountBurn,
+      syntheticToken
     })
     const approveIx = await Token.createApproveInstruction(
       TOKEN_PROGRAM_ID,
@@ -556,12 +745,13 @@ export class Exchange {
 
     return sendAndConfirmRawTransaction(this.connection, txs[0].serialize())
   }
-  public async mint({ amount, exchangeAccount, owner, to, signers }: Mint) {
+  public async mint({ amount, exchangeAccount, owner, to, syntheticToken, signers }: Mint) {
     const mintIx = await this.mintInstruction({
       amount,
       exchangeAccount,
       owner,
-      to
+      to,
+      syntheticToken
     })
     await this.getState()
     await this.updatePricesAndSend([mintIx], signers, this.assetsList.headAssets >= 20)
@@ -644,6 +834,16 @@ export class Exchange {
     assetList.synthetics = assetList.synthetics.slice(0, assetList.headSynthetics)
     return assetList
   }
+  public async getPriceHistory(priceHistory: PublicKey): Promise<PriceHistory> {
+    const history = (await this.program.account.priceHistory.fetch(priceHistory)) as PriceHistory
+    // entries from oldest to newest
+    const start = history.head - history.count + history.entries.length
+    history.entries = Array.from(
+      { length: history.count },
+      (_, offset) => history.entries[(start + offset) % history.entries.length]
+    )
+    return history
+  }
   public onAssetsListChange(address: PublicKey, fn: (list: AssetsList) => void) {
     this.program.account.assetsList
       .subscribe(address, 'singleGossip')
@@ -663,6 +863,32 @@ export class Exchange {
     })
     return assetListAccount.publicKey
   }
+  public async createPriceHistory() {
+    const priceHistoryAccount = new Account()
+    await this.program.rpc.createPriceHistory({
+      accounts: {
+        priceHistory: priceHistoryAccount.publicKey,
+        rent: SYSVAR_RENT_PUBKEY
+      },
+      signers: [priceHistoryAccount],
+      instructions: [await this.program.account.priceHistory.createInstruction(priceHistoryAccount)]
+    })
+    return priceHistoryAccount.publicKey
+  }
+  public async setPriceHistoryInstruction({
+    assetsList,
+    feedAddress,
+    priceHistory
+  }: SetPriceHistoryInstruction) {
+    return (await this.program.instruction.setPriceHistory(feedAddress, {
+      accounts: {
+        state: this.stateAddress,
+        signer: this.state.admin,
+        assetsList,
+        priceHistory
+      }
+    })) as TransactionInstruction
+  }
   public async setPriceFeedInstruction({
     assetsList,
     priceFeed,
@@ -744,6 +970,143 @@ export class Exchange {
       signers: [exchangeAdmin]
     })
   }
+  public async setSettlementSlotInstruction({
+    assetsList,
+    assetAddress,
+    settlementSlot
+  }: SetSettlementSlotInstruction) {
+    return (await this.program.instruction.setSettlementSlot(assetAddress, settlementSlot, {
+      accounts: {
+        state: this.stateAddress,
+        signer: this.state.admin,
+        assetsList
+      }
+    })) as TransactionInstruction
+  }
+  public async setMaxConfidenceInstruction({
+    assetsList,
+    feedAddress,
+    maxConfidence
+  }: SetMaxConfidenceInstruction) {
+    return (await this.program.instruction.setMaxConfidence(feedAddress, maxConfidence, {
+      accounts: {
+        state: this.stateAddress,
+        signer: this.state.admin,
+        assetsList
+      }
+    })) as TransactionInstruction
+  }
+  public async setConfidenceMultiplierInstruction({
+    assetsList,
+    feedAddress,
+    confidenceMultiplier
+  }: SetConfidenceMultiplierInstruction) {
+    return (await this.program.instruction.setConfidenceMultiplier(
+      feedAddress,
+      confidenceMultiplier,
+      {
+        accounts: {
+          state: this.stateAddress,
+          signer: this.state.admin,
+          assetsList
+        }
+      }
+    )) as TransactionInstruction
+  }
+  public async setInversePriceBandInstruction({
+    assetsList,
+    assetAddress,
+    entryPrice,
+    lowerBound,
+    upperBound
+  }: SetInversePriceBandInstruction) {
+    return (await this.program.instruction.setInversePriceBand(
+      assetAddress,
+      entryPrice,
+      lowerBound,
+      upperBound,
+      {
+        accounts: {
+          state: this.stateAddress,
+          signer: this.state.admin,
+          assetsList
+        }
+      }
+    )) as TransactionInstruction
+  }
+  public async setAssetMaxDelayInstruction({
+    assetsList,
+    feedAddress,
+    maxDelay
+  }: SetAssetMaxDelayInstruction) {
+    return (await this.program.instruction.setAssetMaxDelay(feedAddress, maxDelay, {
+      accounts: {
+        state: this.stateAddress,
+        signer: this.state.admin,
+        assetsList
+      }
+    })) as TransactionInstruction
+  }
+  public async setPriceModeInstruction({
+    assetsList,
+    feedAddress,
+    priceMode
+  }: SetPriceModeInstruction) {
+    return (await this.program.instruction.setPriceMode(feedAddress, priceMode, {
+      accounts: {
+        state: this.stateAddress,
+        signer: this.state.admin,
+        assetsList
+      }
+    })) as TransactionInstruction
+  }
+  public async setSecondaryFeedInstruction({
+    assetsList,
+    feedAddress,
+    secondaryFeed,
+    maxDivergence
+  }: SetSecondaryFeedInstruction) {
+    return (await this.program.instruction.setSecondaryFeed(feedAddress, maxDivergence, {
+      accounts: {
+        state: this.stateAddress,
+        signer: this.state.admin,
+        assetsList,
+        secondaryFeed
+      }
+    })) as TransactionInstruction
+  }
+  public async setPriceBreakerInstruction({
+    assetsList,
+    feedAddress,
+    maxPriceChange,
+    breakerCooldown
+  }: SetPriceBreakerInstruction) {
+    return (await this.program.instruction.setPriceBreaker(
+      feedAddress,
+      maxPriceChange,
+      breakerCooldown,
+      {
+        accounts: {
+          state: this.stateAddress,
+          signer: this.state.admin,
+          assetsList
+        }
+      }
+    )) as TransactionInstruction
+  }
+  public async resetPriceBreakerInstruction({
+    assetsList,
+    priceFeed
+  }: ResetPriceBreakerInstruction) {
+    return (await this.program.instruction.resetPriceBreaker({
+      accounts: {
+        state: this.stateAddress,
+        signer: this.state.admin,
+        assetsList,
+        priceFeed
+      }
+    })) as TransactionInstruction
+  }
   public async addNewAssetInstruction({ assetsList, assetFeedAddress }: AddNewAssetInstruction) {
     return (await this.program.instruction.addNewAsset(assetFeedAddress, {
       accounts: {
@@ -753,6 +1116,31 @@ export class Exchange {
       }
     })) as TransactionInstruction
   }
+  public async addDerivedAssetInstruction({
+    assetsList,
+    assetAddress,
+    assetKind,
+    inputFeeds,
+    inputWeights
+  }: AddDerivedAssetInstruction) {
+    return (await this.program.instruction.addDerivedAsset(
+      assetAddress,
+      assetKind,
+      inputWeights || [],
+      {
+        remainingAccounts: inputFeeds.map((pubkey) => ({
+          pubkey,
+          isWritable: false,
+          isSigner: false
+        })),
+        accounts: {
+          state: this.stateAddress,
+          signer: this.state.admin,
+          assetsList
+        }
+      }
+    )) as TransactionInstruction
+  }
   public async addCollateralInstruction({
     assetsList,
     assetAddress,
@@ -783,32 +1171,78 @@ export class Exchange {
   }
   public async updatePrices(assetsList: PublicKey) {
     const assetsListData = await this.getAssetsList(assetsList)
-    const feedAddresses = assetsListData.assets
-      .filter((asset) => !asset.feedAddress.equals(DEFAULT_PUBLIC_KEY))
-      .map((asset) => {
-        return { pubkey: asset.feedAddress, isWritable: false, isSigner: false }
-      })
+    const feedAddresses = this.getPriceFeedAccounts(assetsListData.assets)
     return await this.program.rpc.setAssetsPrices({
       remainingAccounts: feedAddresses,
       accounts: {
+        state: this.stateAddress,
         assetsList: assetsList
       }
     })
   }
   public async updatePricesInstruction(assetsList: PublicKey) {
     const assetsListData = await this.getAssetsList(assetsList)
-    const feedAddresses = assetsListData.assets
-      .filter((asset) => !asset.feedAddress.equals(DEFAULT_PUBLIC_KEY))
-      .map((asset) => {
-        return { pubkey: asset.feedAddress, isWritable: false, isSigner: false }
-      })
+    const feedAddresses = this.getPriceFeedAccounts(assetsListData.assets)
     return (await this.program.instruction.setAssetsPrices({
       remainingAccounts: feedAddresses,
       accounts: {
+        state: this.stateAddress,
+        assetsList: assetsList
+      }
+    })) as TransactionInstruction
+  }
+  public async crankAssetsPricesInstruction(assetsList: PublicKey, to: PublicKey) {
+    const assetsListData = await this.getAssetsList(assetsList)
+    const feedAddresses = this.getPriceFeedAccounts(assetsListData.assets)
+    return (await this.program.instruction.crankAssetsPrices({
+      remainingAccounts: feedAddresses,
+      accounts: {
+        state: this.stateAddress,
+        assetsList: assetsList,
+        exchangeAuthority: this.exchangeAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        crankFundAccount: this.state.crank.fundAccount,
+        to
+      }
+    })) as TransactionInstruction
+  }
+  public async refreshDebtInstruction(assetsList: PublicKey) {
+    return (await this.program.instruction.refreshDebt({
+      accounts: {
+        state: this.stateAddress,
+        assetsList: assetsList
+      }
+    })) as TransactionInstruction
+  }
+  public async accrueStabilityFeeInstruction(assetsList: PublicKey) {
+    return (await this.program.instruction.accrueStabilityFee({
+      accounts: {
+        state: this.stateAddress,
         assetsList: assetsList
       }
     })) as TransactionInstruction
   }
+  private getPriceFeedAccounts(assets: Asset[]) {
+    return assets.reduce((acc, asset) => {
+      // derived price is calculated from input feeds
+      const feeds =
+        asset.assetKind === AssetKind.Feed
+          ? [asset.feedAddress]
+          : asset.inputFeeds.filter((feed) => !feed.equals(DEFAULT_PUBLIC_KEY))
+      // fallback feed is used when primary one is outdated or not trading
+      feeds.push(asset.secondaryFeedAddress)
+      for (const feed of feeds) {
+        if (!feed.equals(DEFAULT_PUBLIC_KEY) && !acc.some((x) => x.pubkey.equals(feed))) {
+          acc.push({ pubkey: feed, isWritable: false, isSigner: false })
+        }
+      }
+      // updated price is appended to history
+      if (!asset.priceHistory.equals(DEFAULT_PUBLIC_KEY)) {
+        acc.push({ pubkey: asset.priceHistory, isWritable: true, isSigner: false })
+      }
+      return acc
+    }, [] as { pubkey: PublicKey; isWritable: boolean; isSigner: boolean }[])
+  }
 }
 export interface InitializeAssetList {
   collateralToken: PublicKey
@@ -821,8 +1255,30 @@ export interface InitializeAssetList {
 export interface Asset {
   feedAddress: PublicKey
   price: BN
+  twap: BN
+  priceMode: number
   lastUpdate: BN
   confidence: number
+  maxConfidence: number
+  frozen: boolean
+  secondaryFeedAddress: PublicKey
+  maxDivergence: number
+  priceSource: PriceSource
+  maxPriceChange: number
+  breakerCooldown: BN
+  breakerSlot: BN
+  inBreaker: boolean
+  confidenceMultiplier: number
+  lastCrank: BN
+  // 0 uses global maxDelay
+  maxDelay: number
+  assetKind: AssetKind
+  inputFeeds: PublicKey[]
+  inputWeights: BN[]
+  entryPrice: BN
+  lowerBound: BN
+  upperBound: BN
+  priceHistory: PublicKey
 }
 export interface AssetsList {
   initialized: boolean
@@ -833,6 +1289,17 @@ export interface AssetsList {
   collaterals: Array<Collateral>
   synthetics: Array<Synthetic>
 }
+export interface PriceEntry {
+  slot: BN
+  price: BN
+  confidence: number
+}
+export interface PriceHistory {
+  feedAddress: PublicKey
+  head: number
+  count: number
+  entries: PriceEntry[]
+}
 export interface Collateral {
   assetIndex: number
   collateralAddress: PublicKey
@@ -848,6 +1315,7 @@ export interface Synthetic {
   supply: BN
   maxSupply: BN
   settlementSlot: BN
+  settlementPrice: BN
   decimals: number
 }
 
@@ -862,6 +1330,72 @@ export interface SetAssetMaxSupply {
   exchangeAdmin: Account
   newMaxSupply: BN
 }
+export interface SetSettlementSlotInstruction {
+  assetsList: PublicKey
+  assetAddress: PublicKey
+  // u64::MAX cancels settlement
+  settlementSlot: BN
+}
+export interface SettleSyntheticInstruction {
+  amount: BN
+  owner: PublicKey
+  settledToken: PublicKey
+  userSettledTokenAccount: PublicKey
+  userUsdTokenAccount: PublicKey
+}
+export interface SetMaxConfidenceInstruction {
+  assetsList: PublicKey
+  feedAddress: PublicKey
+  maxConfidence: number
+}
+export interface SetConfidenceMultiplierInstruction {
+  assetsList: PublicKey
+  feedAddress: PublicKey
+  confidenceMultiplier: number
+}
+export interface SetInversePriceBandInstruction {
+  assetsList: PublicKey
+  assetAddress: PublicKey
+  entryPrice: BN
+  lowerBound: BN
+  upperBound: BN
+}
+export interface SetPriceHistoryInstruction {
+  assetsList: PublicKey
+  feedAddress: PublicKey
+  priceHistory: PublicKey
+}
+export interface SetAssetMaxDelayInstruction {
+  assetsList: PublicKey
+  feedAddress: PublicKey
+  maxDelay: number
+}
+export interface SetPriceModeInstruction {
+  assetsList: PublicKey
+  feedAddress: PublicKey
+  priceMode: PriceMode
+}
+export interface SetSecondaryFeedInstruction {
+  assetsList: PublicKey
+  feedAddress: PublicKey
+  secondaryFeed: PublicKey
+  maxDivergence: number
+}
+export interface SetPriceBreakerInstruction {
+  assetsList: PublicKey
+  feedAddress: PublicKey
+  maxPriceChange: number
+  breakerCooldown: BN
+}
+export interface SetCrankRewardInstruction {
+  reward: BN
+  minAge: number
+  maxRewardsPerSlot: number
+}
+export interface ResetPriceBreakerInstruction {
+  assetsList: PublicKey
+  priceFeed: PublicKey
+}
 export interface AddNewAssetInstruction {
   assetsList: PublicKey
   assetFeedAddress: PublicKey
@@ -884,6 +1418,14 @@ export interface AddSyntheticInstruction {
   maxSupply: BN
   decimals: number
 }
+export interface AddDerivedAssetInstruction {
+  assetsList: PublicKey
+  assetAddress: PublicKey
+  assetKind: AssetKind
+  inputFeeds: PublicKey[]
+  // only for basket
+  inputWeights?: BN[]
+}
 export interface AddCollateralInstruction {
   assetsList: PublicKey
   assetAddress: PublicKey
@@ -900,6 +1442,7 @@ export interface Mint {
   owner: PublicKey
   to: PublicKey
   amount: BN
+  syntheticToken?: PublicKey
   signers?: Array<Account>
 }
 export interface Liquidate {
@@ -928,6 +1471,7 @@ export interface Burn {
   owner: PublicKey
   userTokenAccountBurn: PublicKey
   amount: BN
+  syntheticToken?: PublicKey
   signers?: Array<Account>
 }
 interface Deposit {
@@ -959,6 +1503,7 @@ export interface MintInstruction {
   owner: PublicKey
   to: PublicKey
   amount: BN
+  syntheticToken?: PublicKey
 }
 export interface SwapInstruction {
   exchangeAccount: PublicKey
@@ -979,11 +1524,21 @@ export interface LiquidateInstruction {
   amount: BN
 }
 
+export interface MigrateState {
+  admin: Account
+  legacyAssetsList: PublicKey
+  oracleProgram: PublicKey
+}
+export interface SetAccountDebtLimitsInstruction {
+  minAccountDebt: BN
+  maxAccountDebt: BN
+}
 export interface BurnInstruction {
   exchangeAccount: PublicKey
   owner: PublicKey
   userTokenAccountBurn: PublicKey
   amount: BN
+  syntheticToken?: PublicKey
 }
 export interface WithdrawRewardsInstruction {
   exchangeAccount: PublicKey
@@ -1011,6 +1566,7 @@ export interface Init {
   stakingFundAccount: PublicKey
   amountPerRound: BN
   assetsList: PublicKey
+  oracleProgram: PublicKey
 }
 export interface ExchangeState {
   admin: PublicKey
@@ -1027,6 +1583,28 @@ export interface ExchangeState {
   liquidationBuffer: number
   accountVersion: number
   staking: Staking
+  oracleProgram: PublicKey
+  crank: Crank
+  debt: BN
+  debtCeiling: BN
+  minAccountDebt: BN
+  maxAccountDebt: BN
+  stabilityFee: number
+  stabilityFeeIndex: BN
+  stabilityFeeSlot: BN
+  accumulatedStabilityFee: BN
+  stabilityFeeVault: PublicKey
+}
+export interface SkippedPriceFeeds {
+  feeds: PublicKey[]
+}
+export interface Crank {
+  fundAccount: PublicKey
+  reward: BN
+  minAge: number
+  maxRewardsPerSlot: number
+  lastSlot: BN
+  rewardsInSlot: number
 }
 export interface Staking {
   fundAccount: PublicKey
@@ -8,11 +8,12 @@ import {
   sendAndConfirmRawTransaction,
   Account
 } from '@solana/web3.js'
-import { Asset, AssetsList, Collateral, ExchangeAccount } from './exchange'
+import { Asset, AssetsList, Collateral, ExchangeAccount, Synthetic } from './exchange'
 
 export const DEFAULT_PUBLIC_KEY = new PublicKey(0)
 export const ORACLE_OFFSET = 6
 export const ACCURACY = 6
+export const CONFIDENCE_OFFSET = 6
 export enum ERRORS {
   SIGNATURE = 'Error: Signature verification failed',
   SIGNER = 'Error: unknown signer',
@@ -42,7 +43,81 @@ export enum ERRORS_EXCHANGE {
   INITIALIZED = '0x13d',
   UNINITIALIZED = '0x13e',
   NO_ASSET_FOUND = '0x13f',
-  MAX_SUPPLY = '0x140'
+  MAX_SUPPLY = '0x140',
+  NOT_COLLATERAL = '0x141',
+  ALREADY_A_COLLATERAL = '0x142',
+  UNCERTAIN_ORACLE = '0x143',
+  FROZEN_ASSET = '0x144',
+  INVALID_ORACLE_PROGRAM = '0x145',
+  INVALID_ORACLE_ACCOUNT = '0x146',
+  INVALID_PRICE_TYPE = '0x147',
+  INVALID_PRICE_MODE = '0x148',
+  PRICE_BREAKER = '0x149',
+  NEGATIVE_PRICE = '0x14a',
+  ZERO_PRICE = '0x14b',
+  PRICE_OVERFLOW = '0x14c',
+  INVALID_ASSET_KIND = '0x14d',
+  INVALID_PRICE_BAND = '0x14e',
+  SETTLED_SYNTHETIC = '0x14f',
+  DEBT_CEILING = '0x150',
+  ACCOUNT_DEBT_DUST = '0x151',
+  ACCOUNT_DEBT_LIMIT = '0x152',
+  INVALID_ACCOUNT_DEBT_LIMITS = '0x153',
+  INVALID_SETTLEMENT = '0x154',
+  NOT_SETTLED_SYNTHETIC = '0x155',
+  INVALID_LEGACY_ACCOUNT = '0x156',
+  INVALID_STABILITY_FEE = '0x157',
+  LEGACY_STATE = '0x158'
+}
+export enum PriceMode {
+  Spot = 0,
+  Twap = 1,
+  // lower of spot and twap for collateral, higher for debt
+  Conservative = 2
+}
+export enum AssetKind {
+  Feed = 0,
+  Product = 1,
+  Ratio = 2,
+  Inverse = 3,
+  // sum of weight * price, weight 10^6 -> 1
+  Basket = 4,
+  // 2 * entryPrice - price within price band
+  InverseSynthetic = 5
+}
+export enum PriceSource {
+  Primary = 0,
+  // fallback feed
+  Secondary = 1
+}
+const modePrice = (asset: Asset, conservative: (a: BN, b: BN) => BN) => {
+  switch (asset.priceMode) {
+    case PriceMode.Twap:
+      return asset.twap
+    case PriceMode.Conservative:
+      return conservative(asset.price, asset.twap)
+    default:
+      return asset.price
+  }
+}
+// k * conf, confidenceMultiplier 100 -> k = 1
+export const confidenceAdjustment = (asset: Asset, price: BN) => {
+  return price
+    .mul(new BN(asset.confidence))
+    .muln(asset.confidenceMultiplier)
+    .div(new BN(10 ** CONFIDENCE_OFFSET).muln(100))
+}
+export const collateralPrice = (asset: Asset) => {
+  const price = modePrice(asset, BN.min)
+  return BN.max(price.sub(confidenceAdjustment(asset, price)), new BN(0))
+}
+export const debtPrice = (asset: Asset) => {
+  const price = modePrice(asset, BN.max)
+  return price.add(confidenceAdjustment(asset, price))
+}
+// settled synthetic keeps price frozen at settlement
+export const syntheticDebtPrice = (asset: Asset, synthetic: Synthetic) => {
+  return synthetic.settlementPrice.eqn(0) ? debtPrice(asset) : synthetic.settlementPrice
 }
 export const signAndSend = async (
   tx: Transaction,
@@ -88,7 +163,7 @@ export const calculateLiquidation = (
   const seizedInToken = seizedCollateralInUsd
     .muln(10 ** ORACLE_OFFSET)
     .muln(10 ** (collateral.decimals - ACCURACY))
-    .div(asset.price)
+    .div(collateralPrice(asset))
 
   const collateralToExchange = divUp(
     seizedInToken.muln(penaltyToExchange),
@@ -103,7 +178,7 @@ export const calculateDebt = (assetsList: AssetsList) => {
     (acc, synthetic) =>
       acc.add(
         synthetic.supply
-          .mul(assetsList.assets[synthetic.assetIndex].price)
+          .mul(syntheticDebtPrice(assetsList.assets[synthetic.assetIndex], synthetic))
           .div(new BN(10 ** (synthetic.decimals + ORACLE_OFFSET - ACCURACY)))
       ),
     new BN(0)
@@ -117,7 +192,7 @@ export const calculateUserCollateral = (
     const collateral = assetsList.collaterals[entry.index]
     return acc.add(
       entry.amount
-        .mul(assetsList.assets[collateral.assetIndex].price)
+        .mul(collateralPrice(assetsList.assets[collateral.assetIndex]))
         .div(new BN(10 ** (collateral.decimals + ORACLE_OFFSET - ACCURACY)))
     )
   }, new BN(0))
@@ -128,7 +203,7 @@ export const calculateUserMaxDebt = (exchangeAccount: ExchangeAccount, assetsLis
     const asset = assetsList.assets[collateral.assetIndex]
     return acc.add(
       entry.amount
-        .mul(asset.price)
+        .mul(collateralPrice(asset))
         .muln(collateral.collateralRatio)
         .divn(100)
         .div(new BN(10 ** (collateral.decimals + ORACLE_OFFSET - ACCURACY)))
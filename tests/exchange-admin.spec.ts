@@ -11,13 +11,16 @@ import {
   EXCHANGE_ADMIN,
   SYNTHETIFY_ECHANGE_SEED,
   assertThrowsAsync,
+  createAccountWithCollateral,
+  tou64,
   DEFAULT_PUBLIC_KEY,
+  sleep,
   U64_MAX
 } from './utils'
-import { createPriceFeed, setFeedPrice } from './oracleUtils'
+import { createPriceFeed, setFeedData, setFeedPrice, setFeedStatus } from './oracleUtils'
 import { ERRORS } from '@synthetify/sdk/src/utils'
-import { Collateral } from '../sdk/lib/exchange'
-import { ERRORS_EXCHANGE } from '../sdk/lib/utils'
+import { Collateral, SkippedPriceFeeds } from '../sdk/lib/exchange'
+import { AssetKind, ERRORS_EXCHANGE } from '../sdk/lib/utils'
 
 describe('admin', () => {
   const provider = anchor.Provider.local()
@@ -96,7 +99,8 @@ describe('admin', () => {
       nonce,
       amountPerRound: amountPerRound,
       stakingRoundLength: stakingRoundLength,
-      stakingFundAccount: stakingFundAccount
+      stakingFundAccount: stakingFundAccount,
+      oracleProgram: oracleProgram.programId
     })
     exchange = await Exchange.build(
       connection,
@@ -331,6 +335,166 @@ describe('admin', () => {
       assert.ok(state.staking.roundLength === length)
     })
   })
+  describe('#setOracleProgram()', async () => {
+    it('Fail without admin signature', async () => {
+      const ix = await exchange.setOracleProgramInstruction(managerProgram.programId)
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet], connection),
+        ERRORS.SIGNATURE
+      )
+      const state = await exchange.getState()
+      assert.ok(state.oracleProgram.equals(oracleProgram.programId))
+    })
+  })
+  describe('#setCrankReward()', async () => {
+    it('Fail without admin signature', async () => {
+      const reward = new BN(1000)
+      const ix = await exchange.setCrankRewardInstruction({
+        reward,
+        minAge: 100,
+        maxRewardsPerSlot: 5
+      })
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet], connection),
+        ERRORS.SIGNATURE
+      )
+      const state = await exchange.getState()
+      assert.ok(!state.crank.reward.eq(reward))
+    })
+    it('change value', async () => {
+      const reward = new BN(1000)
+      const ix = await exchange.setCrankRewardInstruction({
+        reward,
+        minAge: 100,
+        maxRewardsPerSlot: 5
+      })
+      await signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection)
+      const state = await exchange.getState()
+      assert.ok(state.crank.reward.eq(reward))
+      assert.ok(state.crank.minAge === 100)
+      assert.ok(state.crank.maxRewardsPerSlot === 5)
+    })
+  })
+  describe('#setDebtCeiling()', async () => {
+    it('Fail without admin signature', async () => {
+      const debtCeiling = new BN(1e6).muln(1e6)
+      const ix = await exchange.setDebtCeilingInstruction(debtCeiling)
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet], connection),
+        ERRORS.SIGNATURE
+      )
+      const state = await exchange.getState()
+      assert.ok(!state.debtCeiling.eq(debtCeiling))
+    })
+    it('change value', async () => {
+      const debtCeiling = new BN(1e6).muln(1e6)
+      const ix = await exchange.setDebtCeilingInstruction(debtCeiling)
+      await signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection)
+      const state = await exchange.getState()
+      assert.ok(state.debtCeiling.eq(debtCeiling))
+    })
+  })
+  describe('#setAccountDebtLimits()', async () => {
+    it('Fail without admin signature', async () => {
+      const minAccountDebt = new BN(10 * 1e6)
+      const maxAccountDebt = new BN(1e6).muln(1e6)
+      const ix = await exchange.setAccountDebtLimitsInstruction({ minAccountDebt, maxAccountDebt })
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet], connection),
+        ERRORS.SIGNATURE
+      )
+      const state = await exchange.getState()
+      assert.ok(!state.minAccountDebt.eq(minAccountDebt))
+    })
+    it('Fail with minimum over maximum', async () => {
+      const ix = await exchange.setAccountDebtLimitsInstruction({
+        minAccountDebt: new BN(1000 * 1e6),
+        maxAccountDebt: new BN(10 * 1e6)
+      })
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection),
+        ERRORS_EXCHANGE.INVALID_ACCOUNT_DEBT_LIMITS
+      )
+    })
+    it('change value', async () => {
+      const minAccountDebt = new BN(10 * 1e6)
+      const maxAccountDebt = new BN(1e6).muln(1e6)
+      const ix = await exchange.setAccountDebtLimitsInstruction({ minAccountDebt, maxAccountDebt })
+      await signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection)
+      const state = await exchange.getState()
+      assert.ok(state.minAccountDebt.eq(minAccountDebt))
+      assert.ok(state.maxAccountDebt.eq(maxAccountDebt))
+    })
+  })
+  describe('#setStabilityFee()', async () => {
+    it('Fail without admin signature', async () => {
+      const ix = await exchange.setStabilityFeeInstruction(1000)
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet], connection),
+        ERRORS.SIGNATURE
+      )
+      const state = await exchange.getState()
+      assert.ok(state.stabilityFee === 0)
+    })
+    it('Fail above 100% yearly', async () => {
+      const ix = await exchange.setStabilityFeeInstruction(100001)
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection),
+        ERRORS_EXCHANGE.INVALID_STABILITY_FEE
+      )
+    })
+    it('change value', async () => {
+      const ix = await exchange.setStabilityFeeInstruction(1000)
+      await signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection)
+      const state = await exchange.getState()
+      assert.ok(state.stabilityFee === 1000)
+    })
+    it('Should accrue without signature', async () => {
+      const stateBefore = await exchange.getState()
+      const ix = await exchange.accrueStabilityFeeInstruction(assetsList)
+      await signAndSend(new Transaction().add(ix), [wallet], connection)
+      const state = await exchange.getState()
+      assert.ok(state.stabilityFeeSlot.gt(stateBefore.stabilityFeeSlot))
+      assert.ok(state.stabilityFeeIndex.gt(stateBefore.stabilityFeeIndex))
+      // without debt no xUSD is accrued
+      assert.ok(state.accumulatedStabilityFee.eqn(0))
+    })
+    it('Fail to set vault of other token', async () => {
+      const vault = await collateralToken.createAccount(new Account().publicKey)
+      const ix = await exchange.setStabilityFeeVaultInstruction(vault)
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection),
+        ERRORS_EXCHANGE.NOT_SYNTHETIC_USD
+      )
+    })
+    it('Should withdraw accumulated fee to vault', async () => {
+      const to = await usdToken.createAccount(new Account().publicKey)
+      const ix = await exchange.withdrawStabilityFeeInstruction(to)
+      // vault is not set yet
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection),
+        ERRORS_EXCHANGE.FUND_ACCOUNT_ERROR
+      )
+      const vaultIx = await exchange.setStabilityFeeVaultInstruction(to)
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(vaultIx), [wallet], connection),
+        ERRORS.SIGNATURE
+      )
+      await signAndSend(new Transaction().add(vaultIx), [wallet, EXCHANGE_ADMIN], connection)
+      assert.ok((await exchange.getState()).stabilityFeeVault.equals(to))
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet], connection),
+        ERRORS.SIGNATURE
+      )
+      await signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection)
+      const state = await exchange.getState()
+      assert.ok(state.accumulatedStabilityFee.eqn(0))
+      assert.ok((await usdToken.getAccountInfo(to)).amount.eqn(0))
+
+      const resetIx = await exchange.setStabilityFeeInstruction(0)
+      await signAndSend(new Transaction().add(resetIx), [wallet, EXCHANGE_ADMIN], connection)
+    })
+  })
   describe('#addNewAsset', async () => {
     it('Should add new asset ', async () => {
       const beforeAssetList = await exchange.getAssetsList(assetsList)
@@ -542,6 +706,57 @@ describe('admin', () => {
       )
     })
   })
+  describe('#setSettlementSlot()', async () => {
+    it('Fail for xUSD', async () => {
+      const assetsListData = await exchange.getAssetsList(assetsList)
+      const ix = await exchange.setSettlementSlotInstruction({
+        assetsList,
+        assetAddress: assetsListData.synthetics[0].assetAddress,
+        settlementSlot: new BN(await connection.getSlot()).addn(1000)
+      })
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection),
+        ERRORS_EXCHANGE.INVALID_SETTLEMENT
+      )
+    })
+    it('Fail in past', async () => {
+      const assetsListData = await exchange.getAssetsList(assetsList)
+      const synthetic = assetsListData.synthetics[assetsListData.synthetics.length - 1]
+      const ix = await exchange.setSettlementSlotInstruction({
+        assetsList,
+        assetAddress: synthetic.assetAddress,
+        settlementSlot: new BN(1)
+      })
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection),
+        ERRORS_EXCHANGE.INVALID_SETTLEMENT
+      )
+    })
+    it('Schedule and cancel settlement', async () => {
+      const assetsListData = await exchange.getAssetsList(assetsList)
+      const index = assetsListData.synthetics.length - 1
+      const assetAddress = assetsListData.synthetics[index].assetAddress
+      const settlementSlot = new BN(await connection.getSlot()).addn(1000)
+      const scheduleIx = await exchange.setSettlementSlotInstruction({
+        assetsList,
+        assetAddress,
+        settlementSlot
+      })
+      await signAndSend(new Transaction().add(scheduleIx), [wallet, EXCHANGE_ADMIN], connection)
+      const scheduled = await exchange.getAssetsList(assetsList)
+      assert.ok(scheduled.synthetics[index].settlementSlot.eq(settlementSlot))
+
+      const noSettlement = new BN(2).pow(new BN(64)).subn(1)
+      const cancelIx = await exchange.setSettlementSlotInstruction({
+        assetsList,
+        assetAddress,
+        settlementSlot: noSettlement
+      })
+      await signAndSend(new Transaction().add(cancelIx), [wallet, EXCHANGE_ADMIN], connection)
+      const cancelled = await exchange.getAssetsList(assetsList)
+      assert.ok(cancelled.synthetics[index].settlementSlot.eq(noSettlement))
+    })
+  })
   describe('#setPriceFeed()', async () => {
     it('New price_feed should be set', async () => {
       const newPriceFeed = await createPriceFeed({
@@ -582,10 +797,11 @@ describe('admin', () => {
         exchangeProgram.rpc.setAssetsPrices({
           remainingAccounts: feedAddresses,
           accounts: {
+            state: exchange.stateAddress,
             assetsList: assetsList
           }
         }),
-        ERRORS.PANICKED
+        ERRORS_EXCHANGE.INVALID_ORACLE_PROGRAM
       )
       const assetList = await exchange.getAssetsList(assetsList)
       const collateralAsset = assetList.assets[1]
@@ -593,6 +809,13 @@ describe('admin', () => {
       // Check not changed price
       assert.ok(collateralAsset.price.eq(new BN(0)))
     })
+    it('Should fail with assets list not bound to state', async () => {
+      const otherAssetsList = await exchange.createAssetsList()
+      await assertThrowsAsync(
+        exchange.updatePrices(otherAssetsList),
+        ERRORS_EXCHANGE.INVALID_ASSETS_LIST
+      )
+    })
     it('Should change prices', async () => {
       const assetListBefore = await exchange.getAssetsList(assetsList)
       await setFeedPrice(oracleProgram, newPrice, collateralTokenFeed)
@@ -610,5 +833,319 @@ describe('admin', () => {
       // Check last_update new value
       assert.ok(collateralAsset.lastUpdate > collateralAssetLastUpdateBefore)
     })
+    it('Should freeze asset with halted feed', async () => {
+      await setFeedStatus(oracleProgram, 'Halted', collateralTokenFeed)
+      await exchange.updatePrices(assetsList)
+
+      const frozenAsset = (await exchange.getAssetsList(assetsList)).assets[1]
+      assert.ok(frozenAsset.frozen)
+      assert.ok(frozenAsset.price.eq(new BN(newPrice * 1e6)))
+
+      await setFeedStatus(oracleProgram, 'Trading', collateralTokenFeed)
+      await exchange.updatePrices(assetsList)
+
+      const tradingAsset = (await exchange.getAssetsList(assetsList)).assets[1]
+      assert.ok(!tradingAsset.frozen)
+    })
+    it('Should skip feed with negative price', async () => {
+      let skippedFeeds: PublicKey[] = []
+      const listener = exchangeProgram.addEventListener(
+        'SkippedPriceFeeds',
+        (event: SkippedPriceFeeds) => {
+          skippedFeeds = event.feeds
+        }
+      )
+      await setFeedData({ oracleProgram, priceFeed: collateralTokenFeed, price: -1 })
+      await exchange.updatePrices(assetsList)
+      await sleep(1000)
+      await exchangeProgram.removeEventListener(listener)
+
+      const collateralAsset = (await exchange.getAssetsList(assetsList)).assets[1]
+      assert.ok(collateralAsset.price.eq(new BN(newPrice * 1e6)))
+      assert.ok(!collateralAsset.frozen)
+      // Skipped feed is reported
+      assert.ok(skippedFeeds.some((feed) => feed.equals(collateralTokenFeed)))
+    })
+    it('Should take last update from feed', async () => {
+      await setFeedData({
+        oracleProgram,
+        priceFeed: collateralTokenFeed,
+        price: newPrice,
+        validSlot: 1
+      })
+      await exchange.updatePrices(assetsList)
+
+      const collateralAsset = (await exchange.getAssetsList(assetsList)).assets[1]
+      assert.ok(collateralAsset.price.eq(new BN(newPrice * 1e6)))
+      assert.ok(collateralAsset.lastUpdate.eq(new BN(1)))
+    })
+  })
+  describe('#addDerivedAsset()', async () => {
+    it('Should fail with wrong number of inputs', async () => {
+      const ix = await exchange.addDerivedAssetInstruction({
+        assetsList,
+        assetAddress: new Account().publicKey,
+        assetKind: AssetKind.Ratio,
+        inputFeeds: [collateralTokenFeed]
+      })
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection),
+        ERRORS_EXCHANGE.INVALID_ASSET_KIND
+      )
+    })
+    it('Should calculate ratio price', async () => {
+      const quoteFeed = await createPriceFeed({ oracleProgram, initPrice: 4, expo: -6 })
+      const assetAddress = new Account().publicKey
+      const ix = await exchange.addDerivedAssetInstruction({
+        assetsList,
+        assetAddress,
+        assetKind: AssetKind.Ratio,
+        inputFeeds: [collateralTokenFeed, quoteFeed]
+      })
+      await signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection)
+      await setFeedPrice(oracleProgram, 6, collateralTokenFeed)
+      await exchange.updatePrices(assetsList)
+
+      const derivedAsset = (await exchange.getAssetsList(assetsList)).assets.find((asset) =>
+        asset.feedAddress.equals(assetAddress)
+      )
+      assert.ok(derivedAsset.assetKind === AssetKind.Ratio)
+      // 6 / 4
+      assert.ok(derivedAsset.price.eq(new BN(1.5 * 1e6)))
+
+      // Derived asset is listed and minted as synthetic
+      const syntheticToken = await createToken({
+        connection,
+        payer: wallet,
+        mintAuthority: exchangeAuthority
+      })
+      const addSyntheticIx = await exchange.addSyntheticInstruction({
+        assetsList,
+        assetAddress: syntheticToken.publicKey,
+        priceFeed: assetAddress,
+        decimals: 6,
+        maxSupply: new BN(1e6).muln(1e6)
+      })
+      await signAndSend(
+        new Transaction().add(addSyntheticIx),
+        [wallet, EXCHANGE_ADMIN],
+        connection
+      )
+      const { accountOwner, exchangeAccount } = await createAccountWithCollateral({
+        reserveAddress: reserveAccount,
+        collateralToken,
+        exchangeAuthority,
+        exchange,
+        collateralTokenMintAuthority: CollateralTokenMinter.publicKey,
+        amount: new BN(1000 * 1e6)
+      })
+      const syntheticTokenAccount = await syntheticToken.createAccount(accountOwner.publicKey)
+      const mintAmount = new BN(20 * 1e6)
+      await exchange.mint({
+        amount: mintAmount,
+        exchangeAccount,
+        owner: accountOwner.publicKey,
+        to: syntheticTokenAccount,
+        syntheticToken: syntheticToken.publicKey,
+        signers: [accountOwner]
+      })
+      const tokenAccount = await syntheticToken.getAccountInfo(syntheticTokenAccount)
+      assert.ok(tokenAccount.amount.eq(mintAmount))
+      const synthetic = (await exchange.getAssetsList(assetsList)).synthetics.find((s) =>
+        s.assetAddress.equals(syntheticToken.publicKey)
+      )
+      assert.ok(synthetic !== undefined)
+      assert.ok(synthetic.supply.eq(mintAmount))
+    })
+    it('Should calculate inverse synthetic price within band', async () => {
+      const assetAddress = new Account().publicKey
+      const addIx = await exchange.addDerivedAssetInstruction({
+        assetsList,
+        assetAddress,
+        assetKind: AssetKind.InverseSynthetic,
+        inputFeeds: [collateralTokenFeed]
+      })
+      const bandIx = await exchange.setInversePriceBandInstruction({
+        assetsList,
+        assetAddress,
+        entryPrice: new BN(5 * 1e6),
+        lowerBound: new BN(3 * 1e6),
+        upperBound: new BN(7 * 1e6)
+      })
+      await signAndSend(
+        new Transaction().add(addIx).add(bandIx),
+        [wallet, EXCHANGE_ADMIN],
+        connection
+      )
+      await exchange.updatePrices(assetsList)
+
+      const getInverseAsset = async () =>
+        (await exchange.getAssetsList(assetsList)).assets.find((asset) =>
+          asset.feedAddress.equals(assetAddress)
+        )
+      // 2 * 5 - 6
+      assert.ok((await getInverseAsset()).price.eq(new BN(4 * 1e6)))
+
+      // 2 * 5 - 8 is below lower bound
+      await setFeedPrice(oracleProgram, 8, collateralTokenFeed)
+      await exchange.updatePrices(assetsList)
+      assert.ok((await getInverseAsset()).price.eq(new BN(3 * 1e6)))
+      await setFeedPrice(oracleProgram, 6, collateralTokenFeed)
+    })
+    it('Should settle inverse synthetic at price band', async () => {
+      const assetAddress = new Account().publicKey
+      const addIx = await exchange.addDerivedAssetInstruction({
+        assetsList,
+        assetAddress,
+        assetKind: AssetKind.InverseSynthetic,
+        inputFeeds: [collateralTokenFeed]
+      })
+      const bandIx = await exchange.setInversePriceBandInstruction({
+        assetsList,
+        assetAddress,
+        entryPrice: new BN(5 * 1e6),
+        lowerBound: new BN(3 * 1e6),
+        upperBound: new BN(7 * 1e6)
+      })
+      await signAndSend(
+        new Transaction().add(addIx).add(bandIx),
+        [wallet, EXCHANGE_ADMIN],
+        connection
+      )
+      const inverseToken = await createToken({
+        connection,
+        payer: wallet,
+        mintAuthority: exchangeAuthority
+      })
+      const addSyntheticIx = await exchange.addSyntheticInstruction({
+        assetsList,
+        assetAddress: inverseToken.publicKey,
+        priceFeed: assetAddress,
+        decimals: 6,
+        maxSupply: new BN(1e6).muln(1e6)
+      })
+      await signAndSend(
+        new Transaction().add(addSyntheticIx),
+        [wallet, EXCHANGE_ADMIN],
+        connection
+      )
+
+      const { accountOwner, exchangeAccount } = await createAccountWithCollateral({
+        reserveAddress: reserveAccount,
+        collateralToken,
+        exchangeAuthority,
+        exchange,
+        collateralTokenMintAuthority: CollateralTokenMinter.publicKey,
+        amount: new BN(1000 * 1e6)
+      })
+      const inverseTokenAccount = await inverseToken.createAccount(accountOwner.publicKey)
+      // Minted at 2 * 5 - 6
+      const mintAmount = new BN(5 * 1e6)
+      await exchange.mint({
+        amount: mintAmount,
+        exchangeAccount,
+        owner: accountOwner.publicKey,
+        to: inverseTokenAccount,
+        syntheticToken: inverseToken.publicKey,
+        signers: [accountOwner]
+      })
+
+      // 2 * 5 - 8 hits lower bound and settles synthetic
+      await setFeedPrice(oracleProgram, 8, collateralTokenFeed)
+      await exchange.updatePrices(assetsList)
+      const getInverseSynthetic = async () =>
+        (await exchange.getAssetsList(assetsList)).synthetics.find((synthetic) =>
+          synthetic.assetAddress.equals(inverseToken.publicKey)
+        )
+      const settledSynthetic = await getInverseSynthetic()
+      assert.ok(!settledSynthetic.settlementSlot.eq(U64_MAX))
+      assert.ok(settledSynthetic.settlementPrice.eq(new BN(3 * 1e6)))
+
+      // Settled synthetic can't be reopened by new band
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(bandIx), [wallet, EXCHANGE_ADMIN], connection),
+        ERRORS_EXCHANGE.SETTLED_SYNTHETIC
+      )
+
+      // Holder gets xUSD worth settled synthetic at bound
+      const usdTokenAccount = await usdToken.createAccount(accountOwner.publicKey)
+      const settleIx = await exchange.settleSyntheticInstruction({
+        amount: mintAmount,
+        owner: accountOwner.publicKey,
+        settledToken: inverseToken.publicKey,
+        userSettledTokenAccount: inverseTokenAccount,
+        userUsdTokenAccount: usdTokenAccount
+      })
+      const approveIx = Token.createApproveInstruction(
+        inverseToken.programId,
+        inverseTokenAccount,
+        exchangeAuthority,
+        accountOwner.publicKey,
+        [],
+        tou64(mintAmount)
+      )
+      await signAndSend(
+        new Transaction().add(approveIx).add(settleIx),
+        [wallet, accountOwner],
+        connection
+      )
+      assert.ok((await inverseToken.getAccountInfo(inverseTokenAccount)).amount.eqn(0))
+      // 5 * 3
+      const usdAccount = await usdToken.getAccountInfo(usdTokenAccount)
+      assert.ok(usdAccount.amount.eq(new BN(15 * 1e6)))
+      assert.ok((await getInverseSynthetic()).supply.eqn(0))
+      await setFeedPrice(oracleProgram, 6, collateralTokenFeed)
+    })
+    it('Should fail to set invalid price band', async () => {
+      const ix = await exchange.setInversePriceBandInstruction({
+        assetsList,
+        assetAddress: new Account().publicKey,
+        entryPrice: new BN(5 * 1e6),
+        lowerBound: new BN(6 * 1e6),
+        upperBound: new BN(7 * 1e6)
+      })
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection),
+        ERRORS_EXCHANGE.INVALID_PRICE_BAND
+      )
+    })
+  })
+  describe('#setPriceHistory()', async () => {
+    it('Should append updated prices', async () => {
+      const priceHistory = await exchange.createPriceHistory()
+      const ix = await exchange.setPriceHistoryInstruction({
+        assetsList,
+        feedAddress: collateralTokenFeed,
+        priceHistory
+      })
+      await signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection)
+
+      await setFeedPrice(oracleProgram, 7, collateralTokenFeed)
+      await exchange.updatePrices(assetsList)
+      const collateralAsset = (await exchange.getAssetsList(assetsList)).assets[1]
+      const history = await exchange.getPriceHistory(priceHistory)
+
+      assert.ok(history.feedAddress.equals(collateralTokenFeed))
+      assert.ok(history.count === 1)
+      assert.ok(history.entries[0].price.eq(collateralAsset.price))
+      assert.ok(history.entries[0].slot.eq(collateralAsset.lastUpdate))
+
+      await setFeedPrice(oracleProgram, 8, collateralTokenFeed)
+      await exchange.updatePrices(assetsList)
+      const updatedHistory = await exchange.getPriceHistory(priceHistory)
+      assert.ok(updatedHistory.count === 2)
+      assert.ok(updatedHistory.entries[1].price.eq(new BN(8 * 1e6)))
+    })
+  })
+  describe('#migrateExchangeAccount()', async () => {
+    it('Should fail without legacy account', async () => {
+      const owner = new Account()
+      const { account, ix } = await exchange.migrateExchangeAccountInstruction(owner.publicKey)
+      await assertThrowsAsync(
+        signAndSend(new Transaction().add(ix), [wallet, owner], connection),
+        ERRORS_EXCHANGE.INVALID_LEGACY_ACCOUNT
+      )
+      assert.ok((await connection.getAccountInfo(account)) === null)
+    })
   })
 })
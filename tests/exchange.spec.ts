@@ -25,7 +25,9 @@ import {
   createAccountWithCollateralAndMaxMintUsd,
   assertThrowsAsync,
   mulByPercentage,
-  createCollateralToken
+  createCollateralToken,
+  ORACLE_MAX_DELAY,
+  setOracleMaxDelay
 } from './utils'
 import { createPriceFeed } from './oracleUtils'
 import { ERRORS } from '@synthetify/sdk/lib/utils'
@@ -102,7 +104,8 @@ describe('exchange', () => {
       nonce,
       amountPerRound: new BN(100),
       stakingRoundLength: 300,
-      stakingFundAccount: stakingFundAccount
+      stakingFundAccount: stakingFundAccount,
+      oracleProgram: oracleProgram.programId
     })
     exchange = await Exchange.build(
       connection,
@@ -111,6 +114,7 @@ describe('exchange', () => {
       exchangeAuthority,
       exchangeProgram.programId
     )
+    await setOracleMaxDelay(exchange, wallet, connection)
     const state = await exchange.getState()
   })
   it('Initialize', async () => {
@@ -121,7 +125,7 @@ describe('exchange', () => {
     assert.ok(state.assetsList.equals(assetsList))
     // Check initialized parameters
     assert.ok(state.nonce === nonce)
-    assert.ok(state.maxDelay === 0)
+    assert.ok(state.maxDelay === ORACLE_MAX_DELAY)
     assert.ok(state.fee === 300)
     assert.ok(state.debtShares.eq(new BN(0)))
     assert.ok(state.accountVersion === 0)
@@ -299,6 +303,8 @@ describe('exchange', () => {
       // Increase exchange debt
       const exchangeStateAfter = await exchange.getState()
       assert.ok(exchangeStateAfter.debtShares.eq(usdMintAmount))
+      // Cached debt follows minted xUSD
+      assert.ok(exchangeStateAfter.debt.eq(usdMintAmount))
 
       // Increase asset supply
       const assetsListAfter = await exchange.getAssetsList(assetsList)
@@ -381,6 +387,90 @@ describe('exchange', () => {
         ERRORS_EXCHANGE.MINT_LIMIT
       )
     })
+    it('Mint over debt ceiling', async () => {
+      const collateralAmount = new BN(100 * 1e6)
+      const { accountOwner, exchangeAccount } = await createAccountWithCollateral({
+        reserveAddress: snyReserve,
+        collateralToken,
+        exchangeAuthority,
+        exchange,
+        collateralTokenMintAuthority: CollateralTokenMinter.publicKey,
+        amount: collateralAmount
+      })
+      const usdTokenAccount = await usdToken.createAccount(accountOwner.publicKey)
+      const { debt } = await exchange.getState()
+      const setCeilingIx = await exchange.setDebtCeilingInstruction(debt)
+      await signAndSend(
+        new Transaction().add(setCeilingIx),
+        [wallet, EXCHANGE_ADMIN],
+        connection
+      )
+
+      await assertThrowsAsync(
+        exchange.mint({
+          amount: new BN(1e6),
+          exchangeAccount,
+          owner: accountOwner.publicKey,
+          to: usdTokenAccount,
+          signers: [accountOwner]
+        }),
+        ERRORS_EXCHANGE.DEBT_CEILING
+      )
+      // Remove ceiling
+      const noCeiling = new BN(2).pow(new BN(64)).subn(1)
+      const removeCeilingIx = await exchange.setDebtCeilingInstruction(noCeiling)
+      await signAndSend(
+        new Transaction().add(removeCeilingIx),
+        [wallet, EXCHANGE_ADMIN],
+        connection
+      )
+    })
+    it('Mint dust', async () => {
+      const collateralAmount = new BN(100 * 1e6)
+      const { accountOwner, exchangeAccount } = await createAccountWithCollateral({
+        reserveAddress: snyReserve,
+        collateralToken,
+        exchangeAuthority,
+        exchange,
+        collateralTokenMintAuthority: CollateralTokenMinter.publicKey,
+        amount: collateralAmount
+      })
+      const usdTokenAccount = await usdToken.createAccount(accountOwner.publicKey)
+      const { maxAccountDebt } = await exchange.getState()
+      const setLimitsIx = await exchange.setAccountDebtLimitsInstruction({
+        minAccountDebt: new BN(10 * 1e6),
+        maxAccountDebt
+      })
+      await signAndSend(new Transaction().add(setLimitsIx), [wallet, EXCHANGE_ADMIN], connection)
+
+      await assertThrowsAsync(
+        exchange.mint({
+          amount: new BN(1e6),
+          exchangeAccount,
+          owner: accountOwner.publicKey,
+          to: usdTokenAccount,
+          signers: [accountOwner]
+        }),
+        ERRORS_EXCHANGE.ACCOUNT_DEBT_DUST
+      )
+      // Remove minimum
+      const removeLimitsIx = await exchange.setAccountDebtLimitsInstruction({
+        minAccountDebt: new BN(0),
+        maxAccountDebt
+      })
+      await signAndSend(new Transaction().add(removeLimitsIx), [wallet, EXCHANGE_ADMIN], connection)
+    })
+  })
+  describe('#refreshDebt()', async () => {
+    it('Refresh debt', async () => {
+      await exchange.updatePrices(assetsList)
+      const refreshIx = await exchange.refreshDebtInstruction(assetsList)
+      await signAndSend(new Transaction().add(refreshIx), [wallet], connection)
+
+      const assetsListData = await exchange.getAssetsList(assetsList)
+      const state = await exchange.getState()
+      assert.ok(state.debt.eq(calculateDebt(assetsListData)))
+    })
   })
   describe('#withdraw()', async () => {
     let healthFactor: BN
@@ -1031,6 +1121,75 @@ describe('exchange', () => {
       const userEthTokenAccountAfter = await ethToken.getAccountInfo(ethTokenAccount)
       assert.ok(userEthTokenAccountAfter.amount.eq(ethAmountOut))
     })
+    it('Mint btc directly', async () => {
+      const collateralAmount = new BN(1000 * 1e6)
+      const { accountOwner, exchangeAccount } = await createAccountWithCollateral({
+        reserveAddress: snyReserve,
+        collateralToken,
+        exchangeAuthority,
+        exchange,
+        collateralTokenMintAuthority: CollateralTokenMinter.publicKey,
+        amount: collateralAmount
+      })
+      const btcTokenAccount = await btcToken.createAccount(accountOwner.publicKey)
+      const exchangeStateBefore = await exchange.getState()
+
+      // 0.001 BTC -> 50 USD of debt
+      const btcMintAmount = new BN(1e5)
+      await exchange.mint({
+        amount: btcMintAmount,
+        exchangeAccount,
+        owner: accountOwner.publicKey,
+        to: btcTokenAccount,
+        syntheticToken: btcToken.publicKey,
+        signers: [accountOwner]
+      })
+      const userBtcTokenAccountAfter = await btcToken.getAccountInfo(btcTokenAccount)
+      assert.ok(userBtcTokenAccountAfter.amount.eq(btcMintAmount))
+
+      // Debt is valued in usd through btc oracle
+      const exchangeStateAfter = await exchange.getState()
+      const newShares = exchangeStateAfter.debtShares.sub(exchangeStateBefore.debtShares)
+      const exchangeAccountAfter = await exchange.getExchangeAccount(exchangeAccount)
+      assert.ok(exchangeAccountAfter.debtShares.eq(newShares))
+      const userDebt = await exchange.getUserDebtBalance(exchangeAccount)
+      assert.ok(userDebt.sub(new BN(50 * 1e6)).abs().lten(1))
+    })
+    it('Burn btc directly', async () => {
+      const collateralAmount = new BN(1000 * 1e6)
+      const { accountOwner, exchangeAccount } = await createAccountWithCollateral({
+        reserveAddress: snyReserve,
+        collateralToken,
+        exchangeAuthority,
+        exchange,
+        collateralTokenMintAuthority: CollateralTokenMinter.publicKey,
+        amount: collateralAmount
+      })
+      const btcTokenAccount = await btcToken.createAccount(accountOwner.publicKey)
+      const btcMintAmount = new BN(1e5)
+      await exchange.mint({
+        amount: btcMintAmount,
+        exchangeAccount,
+        owner: accountOwner.publicKey,
+        to: btcTokenAccount,
+        syntheticToken: btcToken.publicKey,
+        signers: [accountOwner]
+      })
+
+      // Repay whole debt with minted btc
+      await exchange.burn({
+        amount: btcMintAmount,
+        exchangeAccount,
+        owner: accountOwner.publicKey,
+        userTokenAccountBurn: btcTokenAccount,
+        syntheticToken: btcToken.publicKey,
+        signers: [accountOwner]
+      })
+      const exchangeAccountAfter = await exchange.getExchangeAccount(exchangeAccount)
+      assert.ok(exchangeAccountAfter.debtShares.eq(new BN(0)))
+      const userBtcTokenAccountAfter = await btcToken.getAccountInfo(btcTokenAccount)
+      assert.ok(userBtcTokenAccountAfter.amount.lt(btcMintAmount))
+    })
     it('Swap usd->usd should fail', async () => {
       const collateralAmount = new BN(10000 * 1e6)
       const { accountOwner, exchangeAccount, userCollateralTokenAccount } =
@@ -13,7 +13,8 @@ import {
   createAccountWithCollateral,
   SYNTHETIFY_ECHANGE_SEED,
   createAccountWithCollateralAndMaxMintUsd,
-  mulByPercentage
+  mulByPercentage,
+  setOracleMaxDelay
 } from './utils'
 import { createPriceFeed } from './oracleUtils'
 
@@ -94,7 +95,8 @@ describe('isolated exchange burn', () => {
       nonce,
       amountPerRound: new BN(100),
       stakingRoundLength: 300,
-      stakingFundAccount: stakingFundAccount
+      stakingFundAccount: stakingFundAccount,
+      oracleProgram: oracleProgram.programId
     })
     exchange = await Exchange.build(
       connection,
@@ -103,6 +105,7 @@ describe('isolated exchange burn', () => {
       exchangeAuthority,
       exchangeProgram.programId
     )
+    await setOracleMaxDelay(exchange, wallet, connection)
   })
   it('Burn all debt', async () => {
     const collateralAmount = new BN(1000 * 1e6)
@@ -23,7 +23,9 @@ import {
   createAccountWithCollateralAndMaxMintUsd,
   tokenToUsdValue,
   assertThrowsAsync,
-  U64_MAX
+  U64_MAX,
+  ORACLE_MAX_DELAY,
+  setOracleMaxDelay
 } from './utils'
 import { createPriceFeed, setFeedPrice } from './oracleUtils'
 import { ERRORS, ERRORS_EXCHANGE } from '@synthetify/sdk/src/utils'
@@ -105,7 +107,8 @@ describe('liquidation', () => {
       nonce,
       amountPerRound: new BN(100),
       stakingRoundLength: 300,
-      stakingFundAccount: stakingFundAccount
+      stakingFundAccount: stakingFundAccount,
+      oracleProgram: oracleProgram.programId
     })
 
     exchange = await Exchange.build(
@@ -115,6 +118,7 @@ describe('liquidation', () => {
       exchangeAuthority,
       exchangeProgram.programId
     )
+    await setOracleMaxDelay(exchange, wallet, connection)
 
     const liquidatorData = await createAccountWithCollateralAndMaxMintUsd({
       usdToken,
@@ -139,7 +143,7 @@ describe('liquidation', () => {
     assert.ok(state.assetsList.equals(assetsList))
     // Check initialized parameters
     assert.ok(state.nonce === nonce)
-    assert.ok(state.maxDelay === 0)
+    assert.ok(state.maxDelay === ORACLE_MAX_DELAY)
     assert.ok(state.fee === 300)
     assert.ok(state.penaltyToExchange === 5)
     assert.ok(state.penaltyToLiquidator === 5)
@@ -14,7 +14,9 @@ import {
   mulByPercentage,
   createCollateralToken,
   createToken,
-  waitForBeggingOfASlot
+  waitForBeggingOfASlot,
+  ORACLE_MAX_DELAY,
+  setOracleMaxDelay
 } from './utils'
 import { createPriceFeed } from './oracleUtils'
 
@@ -93,7 +95,8 @@ describe('max collaterals', () => {
       nonce,
       amountPerRound: new BN(100),
       stakingRoundLength: 300,
-      stakingFundAccount: stakingFundAccount
+      stakingFundAccount: stakingFundAccount,
+      oracleProgram: oracleProgram.programId
     })
     exchange = await Exchange.build(
       connection,
@@ -102,6 +105,7 @@ describe('max collaterals', () => {
       exchangeAuthority,
       exchangeProgram.programId
     )
+    await setOracleMaxDelay(exchange, wallet, connection)
 
     healthFactor = new BN((await exchange.getState()).healthFactor)
     const createCollateralProps = {
@@ -179,7 +183,7 @@ describe('max collaterals', () => {
     assert.ok(state.assetsList.equals(assetsList))
     // Check initialized parameters
     assert.ok(state.nonce === nonce)
-    assert.ok(state.maxDelay === 0)
+    assert.ok(state.maxDelay === ORACLE_MAX_DELAY)
     assert.ok(state.fee === 300)
     assert.ok(state.debtShares.eq(new BN(0)))
     assert.ok(state.accountVersion === 0)
@@ -51,6 +51,174 @@ export const setFeedPrice = async (
     accounts: { price: priceFeed }
   })
 }
+export const setFeedConfidence = async (
+  oracleProgram: Program,
+  newConfidence: number,
+  priceFeed: web3.PublicKey
+) => {
+  const data = await getFeedData(oracleProgram, priceFeed)
+  await oracleProgram.rpc.setConfidence(new BN(newConfidence * 10 ** -data.exponent), {
+    accounts: { price: priceFeed }
+  })
+}
+export const setFeedStatus = async (
+  oracleProgram: Program,
+  status: string,
+  priceFeed: web3.PublicKey
+) => {
+  await oracleProgram.rpc.setStatus(PriceStatus.indexOf(status), {
+    accounts: { price: priceFeed }
+  })
+}
+export const setFeedExponent = async (
+  oracleProgram: Program,
+  expo: number,
+  priceFeed: web3.PublicKey
+) => {
+  await oracleProgram.rpc.setExponent(expo, {
+    accounts: { price: priceFeed }
+  })
+}
+export const setFeedTwap = async (
+  oracleProgram: Program,
+  newTwap: number,
+  priceFeed: web3.PublicKey
+) => {
+  const data = await getFeedData(oracleProgram, priceFeed)
+  await oracleProgram.rpc.setTwap(new BN(newTwap * 10 ** -data.exponent), {
+    accounts: { price: priceFeed }
+  })
+}
+export const setFeedValidSlot = async (
+  oracleProgram: Program,
+  validSlot: number,
+  priceFeed: web3.PublicKey
+) => {
+  await oracleProgram.rpc.setValidSlot(new BN(validSlot), {
+    accounts: { price: priceFeed }
+  })
+}
+interface ISetFeedData {
+  oracleProgram: Program
+  priceFeed: web3.PublicKey
+  price?: number
+  confidence?: number
+  status?: string
+  expo?: number
+  twap?: number
+  validSlot?: number
+}
+// Values are scaled by the feed exponent, the new one if expo is given
+export const setFeedData = async ({
+  oracleProgram,
+  priceFeed,
+  price,
+  confidence,
+  status,
+  expo,
+  twap,
+  validSlot
+}: ISetFeedData) => {
+  const exponent =
+    expo === undefined ? (await getFeedData(oracleProgram, priceFeed)).exponent : expo
+  const scale = (value?: number) => (value === undefined ? null : new BN(value * 10 ** -exponent))
+  await oracleProgram.rpc.setPriceInfo(
+    scale(price),
+    scale(confidence),
+    status === undefined ? null : PriceStatus.indexOf(status),
+    expo === undefined ? null : expo,
+    scale(twap),
+    validSlot === undefined ? null : new BN(validSlot),
+    {
+      accounts: { price: priceFeed }
+    }
+  )
+}
+export const MAPPING_SIZE = 20536
+export const PRODUCT_SIZE = 512
+const createOracleAccount = async (oracleProgram: Program, space: number) => {
+  const account = new web3.Account()
+  const instruction = web3.SystemProgram.createAccount({
+    fromPubkey: oracleProgram.provider.wallet.publicKey,
+    newAccountPubkey: account.publicKey,
+    space,
+    lamports: await oracleProgram.provider.connection.getMinimumBalanceForRentExemption(space),
+    programId: oracleProgram.programId
+  })
+  return { account, instruction }
+}
+export const createMapping = async (oracleProgram: Program) => {
+  const { account, instruction } = await createOracleAccount(oracleProgram, MAPPING_SIZE)
+  await oracleProgram.rpc.initializeMapping({
+    accounts: { mapping: account.publicKey },
+    signers: [account],
+    instructions: [instruction]
+  })
+  return account.publicKey
+}
+export const createProduct = async (
+  oracleProgram: Program,
+  mapping: web3.PublicKey,
+  symbol: string
+) => {
+  const { account, instruction } = await createOracleAccount(oracleProgram, PRODUCT_SIZE)
+  await oracleProgram.rpc.addProduct(symbol, {
+    accounts: { mapping, product: account.publicKey },
+    signers: [account],
+    instructions: [instruction]
+  })
+  return account.publicKey
+}
+export const linkPrice = async (
+  oracleProgram: Program,
+  product: web3.PublicKey,
+  priceFeed: web3.PublicKey
+) => {
+  await oracleProgram.rpc.linkPrice({
+    accounts: { product, price: priceFeed }
+  })
+}
+export const addPublisher = async (
+  oracleProgram: Program,
+  publisher: web3.PublicKey,
+  priceFeed: web3.PublicKey
+) => {
+  await oracleProgram.rpc.addPublisher(publisher, {
+    accounts: { price: priceFeed }
+  })
+}
+interface IUpdateComponentPrice {
+  oracleProgram: Program
+  priceFeed: web3.PublicKey
+  publisher: web3.Account
+  price: number
+  confidence: number
+  status?: string
+}
+export const updateComponentPrice = async ({
+  oracleProgram,
+  priceFeed,
+  publisher,
+  price,
+  confidence,
+  status = 'Trading'
+}: IUpdateComponentPrice) => {
+  const data = await getFeedData(oracleProgram, priceFeed)
+  await oracleProgram.rpc.updateComponentPrice(
+    new BN(price * 10 ** -data.exponent),
+    new BN(confidence * 10 ** -data.exponent),
+    PriceStatus.indexOf(status),
+    {
+      accounts: { price: priceFeed, publisher: publisher.publicKey },
+      signers: [publisher]
+    }
+  )
+}
+export const aggregatePrice = async (oracleProgram: Program, priceFeed: web3.PublicKey) => {
+  await oracleProgram.rpc.aggregatePrice({
+    accounts: { price: priceFeed }
+  })
+}
 export const getFeedData = async (oracleProgram: Program, priceFeed: web3.PublicKey) => {
   const info = await oracleProgram.provider.connection.getAccountInfo(priceFeed)
   return parsePriceData(info.data)
@@ -213,6 +381,30 @@ export const parsePriceData = (data: Buffer) => {
   }
 }
 
+export const parseMappingData = (data: Buffer) => {
+  // Pyth magic number.
+  const magic = data.readUInt32LE(0)
+  // Program version.
+  const version = data.readUInt32LE(4)
+  // Account type.
+  const type = data.readUInt32LE(8)
+  // Account size.
+  const size = data.readUInt32LE(12)
+  // Number of product accounts.
+  const productCount = data.readUInt32LE(16)
+  // unused
+  // const unused = accountInfo.data.readUInt32LE(20)
+  // Next mapping account (if any).
+  const nextMappingAccount = PKorNull(data.slice(24, 56))
+  // Read each product account.
+  const productAccountKeys = []
+  for (let i = 0; i < productCount; i++) {
+    const offset = 56 + i * 32
+    productAccountKeys.push(new web3.PublicKey(data.slice(offset, offset + 32)))
+  }
+  return { magic, version, type, size, productCount, nextMappingAccount, productAccountKeys }
+}
+
 interface ProductAttributes {
   [index: string]: string
 }
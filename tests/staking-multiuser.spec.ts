@@ -13,7 +13,9 @@ import {
   SYNTHETIFY_ECHANGE_SEED,
   createAccountWithCollateralAndMaxMintUsd,
   skipToSlot,
-  mulByPercentage
+  mulByPercentage,
+  ORACLE_MAX_DELAY,
+  setOracleMaxDelay
 } from './utils'
 import { createPriceFeed } from './oracleUtils'
 
@@ -100,7 +102,8 @@ describe('staking with multiple users', () => {
       nonce,
       amountPerRound: amountPerRound,
       stakingRoundLength: stakingRoundLength,
-      stakingFundAccount: stakingFundAccount
+      stakingFundAccount: stakingFundAccount,
+      oracleProgram: oracleProgram.programId
     })
     exchange = await Exchange.build(
       connection,
@@ -109,6 +112,7 @@ describe('staking with multiple users', () => {
       exchangeAuthority,
       exchangeProgram.programId
     )
+    await setOracleMaxDelay(exchange, wallet, connection)
     const state = await exchange.getState()
     nextRoundStart = state.staking.nextRound.start
   })
@@ -123,7 +127,7 @@ describe('staking with multiple users', () => {
     assert.ok(state.assetsList.equals(assetsList))
     // Check initialized parameters
     assert.ok(state.nonce === nonce)
-    assert.ok(state.maxDelay === 0)
+    assert.ok(state.maxDelay === ORACLE_MAX_DELAY)
     assert.ok(state.fee === 300)
     assert.ok(state.liquidationPenalty === 15)
     assert.ok(state.liquidationThreshold === 200)
@@ -15,7 +15,9 @@ import {
   createAccountWithMultipleCollaterals,
   skipToSlot,
   mulByPercentage,
-  createCollateralToken
+  createCollateralToken,
+  ORACLE_MAX_DELAY,
+  setOracleMaxDelay
 } from './utils'
 import { createPriceFeed } from './oracleUtils'
 import { Collateral } from '../sdk/lib/exchange'
@@ -101,7 +103,8 @@ describe('staking', () => {
       nonce,
       amountPerRound: amountPerRound,
       stakingRoundLength: stakingRoundLength,
-      stakingFundAccount: stakingFundAccount
+      stakingFundAccount: stakingFundAccount,
+      oracleProgram: oracleProgram.programId
     })
     exchange = await Exchange.build(
       connection,
@@ -110,6 +113,7 @@ describe('staking', () => {
       exchangeAuthority,
       exchangeProgram.programId
     )
+    await setOracleMaxDelay(exchange, wallet, connection)
     const state = await exchange.getState()
     nextRoundStart = state.staking.nextRound.start
   })
@@ -121,7 +125,7 @@ describe('staking', () => {
     assert.ok(state.assetsList.equals(assetsList))
     // Check initialized parameters
     assert.ok(state.nonce === nonce)
-    assert.ok(state.maxDelay === 0)
+    assert.ok(state.maxDelay === ORACLE_MAX_DELAY)
     assert.ok(state.fee === 300)
     assert.ok(state.liquidationBuffer === 172800)
     assert.ok(state.debtShares.eq(new BN(0)))
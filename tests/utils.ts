@@ -14,6 +14,8 @@ export const DEFAULT_PUBLIC_KEY = new PublicKey(0)
 export const ORACLE_OFFSET = 6
 export const ACCURACY = 6
 export const U64_MAX = new BN('18446744073709551615')
+// Mock price feeds are published only when their price is set
+export const ORACLE_MAX_DELAY = 10000
 
 export const tou64 = (amount) => {
   // eslint-disable-next-line new-cap
@@ -425,3 +427,11 @@ export const waitForBeggingOfASlot = async (connection: Connection) => {
   const startSlot = await connection.getSlot()
   while (startSlot == (await connection.getSlot())) {}
 }
+export const setOracleMaxDelay = async (
+  exchange: Exchange,
+  wallet: Account,
+  connection: Connection
+) => {
+  const ix = await exchange.setMaxDelayInstruction(ORACLE_MAX_DELAY)
+  await signAndSend(new Transaction().add(ix), [wallet, EXCHANGE_ADMIN], connection)
+}